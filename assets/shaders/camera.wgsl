@@ -0,0 +1,6 @@
+// Layout of crate::camera::CameraUniform, shared by every shader that reads the camera
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+};
@@ -0,0 +1,88 @@
+// Infinite editor grid on the XZ plane.
+// Drawn as a fullscreen triangle: every pixel is unprojected back into a world space ray
+// and intersected with y = 0, so the grid has no geometry and no edges.
+#include "camera.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+const MINOR_CELL: f32 = 1.0;
+const MAJOR_CELL: f32 = 10.0;
+const FADE_START: f32 = 15.0;
+const FADE_END: f32 = 60.0;
+
+const LINE_COLOR: vec3<f32> = vec3<f32>(0.35, 0.35, 0.35);
+const X_AXIS_COLOR: vec3<f32> = vec3<f32>(0.9, 0.2, 0.2);
+const Z_AXIS_COLOR: vec3<f32> = vec3<f32>(0.2, 0.4, 0.9);
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) near_point: vec3<f32>,
+    @location(1) far_point: vec3<f32>,
+};
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = camera.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // (0,0) (2,0) (0,2) -> one triangle covering the whole screen
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    // wgpu depth range is 0..1
+    out.near_point = unproject(ndc, 0.0);
+    out.far_point = unproject(ndc, 1.0);
+    return out;
+}
+
+// 1.0 on a line, 0.0 between lines, anti-aliased over one pixel
+fn grid_line(coord: vec2<f32>, cell: f32) -> f32 {
+    let scaled = coord / cell;
+    let derivative = fwidth(scaled);
+    let grid = abs(fract(scaled - 0.5) - 0.5) / derivative;
+    return 1.0 - min(min(grid.x, grid.y), 1.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let t = -in.near_point.y / (in.far_point.y - in.near_point.y);
+    let position = in.near_point + t * (in.far_point - in.near_point);
+
+    // Derivatives must be taken before any non-uniform branching
+    let minor = grid_line(position.xz, MINOR_CELL);
+    let major = grid_line(position.xz, MAJOR_CELL);
+    let derivative = fwidth(position.xz);
+
+    var color = vec4<f32>(LINE_COLOR, max(minor * 0.4, major * 0.8));
+
+    // The X axis is the line z = 0, the Z axis is the line x = 0
+    let x_axis = 1.0 - min(abs(position.z) / derivative.y, 1.0);
+    let z_axis = 1.0 - min(abs(position.x) / derivative.x, 1.0);
+    color = mix(color, vec4<f32>(X_AXIS_COLOR, 1.0), x_axis);
+    color = mix(color, vec4<f32>(Z_AXIS_COLOR, 1.0), z_axis);
+
+    let distance = length(position - camera.view_position.xyz);
+    color.a *= 1.0 - smoothstep(FADE_START, FADE_END, distance);
+
+    let clip = camera.view_proj * vec4<f32>(position, 1.0);
+
+    var out: FragmentOutput;
+    out.color = color;
+    out.depth = clip.z / clip.w;
+
+    // Ray points away from the plane (t < 0) or pixel fully faded
+    if t <= 0.0 || color.a <= 0.001 {
+        discard;
+    }
+    return out;
+}
@@ -0,0 +1,201 @@
+#include "camera.wgsl"
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(11) color: vec4<f32>,
+#ifdef SKINNED
+    @location(3) joints: vec4<u32>,
+    @location(4) weights: vec4<f32>,
+#endif
+#ifdef MORPH
+    @builtin(vertex_index) vertex_index: u32,
+#endif
+}
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+#ifdef SKINNED
+    @location(9) joint_offset: u32,
+#endif
+#ifdef MORPH
+    @location(10) morph_offset: u32,
+#endif
+};
+
+#ifdef SKINNED
+@group(2) @binding(0)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+const NOT_SKINNED: u32 = 0xffffffffu;
+
+fn skin_matrix(model: VertexInput, offset: u32) -> mat4x4<f32> {
+    // Instances without a pose are drawn in bind pose
+    if offset == NOT_SKINNED {
+        return mat4x4<f32>(
+            vec4<f32>(1.0, 0.0, 0.0, 0.0),
+            vec4<f32>(0.0, 1.0, 0.0, 0.0),
+            vec4<f32>(0.0, 0.0, 1.0, 0.0),
+            vec4<f32>(0.0, 0.0, 0.0, 1.0),
+        );
+    }
+    return joint_matrices[offset + model.joints.x] * model.weights.x
+        + joint_matrices[offset + model.joints.y] * model.weights.y
+        + joint_matrices[offset + model.joints.z] * model.weights.z
+        + joint_matrices[offset + model.joints.w] * model.weights.w;
+}
+#endif
+
+#ifdef MORPH
+struct MorphDelta {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+}
+struct MorphInfo {
+    vertex_count: u32,
+    target_count: u32,
+    _padding: vec2<u32>,
+}
+
+@group(2) @binding(1)
+var<storage, read> morph_weights: array<f32>;
+@group(3) @binding(0)
+var<storage, read> morph_deltas: array<MorphDelta>;
+@group(3) @binding(1)
+var<uniform> morph_info: MorphInfo;
+
+const NOT_MORPHED: u32 = 0xffffffffu;
+
+fn morph_position(model: VertexInput, offset: u32) -> vec3<f32> {
+    var position = model.position;
+    // Instances without weights are drawn in the base shape
+    if offset == NOT_MORPHED {
+        return position;
+    }
+    for (var target_index = 0u; target_index < morph_info.target_count; target_index++) {
+        let weight = morph_weights[offset + target_index];
+        if weight != 0.0 {
+            let delta = morph_deltas[target_index * morph_info.vertex_count + model.vertex_index];
+            position += delta.position.xyz * weight;
+        }
+    }
+    return position;
+}
+#endif
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+#ifdef SKINNED
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    ) * skin_matrix(model, instance.joint_offset);
+#else
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+#endif
+#ifdef MORPH
+    let position = morph_position(model, instance.morph_offset);
+#else
+    let position = model.position;
+#endif
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.color = model.color;
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(position, 1.0);
+    return out;
+}
+
+// model::MaterialUniform
+struct Material {
+    // Ka, w unused
+    ambient: vec4<f32>,
+    // Kd / base color, alpha is the dissolve (d)
+    diffuse: vec4<f32>,
+    // Ks, w is the shininess (Ns)
+    specular: vec4<f32>,
+    // Ke, w is the index of refraction (Ni)
+    emissive: vec4<f32>,
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    alpha_cutoff: f32,
+    illum: u32,
+    // MAP_* bits, which optional texture maps the material has
+    maps: u32,
+    _padding: u32,
+}
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+@group(0) @binding(2)
+var<uniform> material: Material;
+// The optional maps, in the order of the MAP_* bits. Absent ones are bound to a fallback.
+@group(0) @binding(3)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(4)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(5)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(6)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(7)
+var t_ambient: texture_2d<f32>;
+@group(0) @binding(8)
+var t_specular: texture_2d<f32>;
+@group(0) @binding(9)
+var t_shininess: texture_2d<f32>;
+@group(0) @binding(10)
+var t_dissolve: texture_2d<f32>;
+@group(0) @binding(11)
+var t_displacement: texture_2d<f32>;
+@group(0) @binding(12)
+var t_reflection: texture_2d<f32>;
+
+const MAP_EMISSIVE: u32 = 8u;
+const MAP_DISSOLVE: u32 = 128u;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef WIREFRAME
+    return vec4<f32>(0.05, 0.05, 0.05, 1.0);
+#else
+    var color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * material.diffuse * in.color;
+    // Sampled unconditionally, texture sampling needs uniform control flow
+    let dissolve = textureSample(t_dissolve, s_diffuse, in.tex_coords).r;
+    let emissive_map = textureSample(t_emissive, s_diffuse, in.tex_coords).rgb;
+    if (material.maps & MAP_DISSOLVE) != 0u {
+        color.a *= dissolve;
+    }
+    if color.a < material.alpha_cutoff {
+        discard;
+    }
+    var emissive = material.emissive.rgb;
+    if (material.maps & MAP_EMISSIVE) != 0u {
+        emissive *= emissive_map;
+    }
+    return vec4<f32>(color.rgb + emissive, color.a);
+#endif
+}
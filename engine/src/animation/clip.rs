@@ -0,0 +1,337 @@
+use std::ops::{Add, Mul};
+
+use cgmath::{InnerSpace as _, Quaternion, Vector3};
+
+use crate::{animation::skeleton::Skeleton, components::Transform};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    Step,
+    /// Hermite spline, every key stores in-tangent, value and out-tangent
+    CubicSpline,
+}
+
+#[derive(Clone, Debug)]
+pub enum ChannelValues {
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+    /// Morph target weights, all targets of a keyframe are stored together
+    MorphWeights(Vec<f32>),
+}
+
+/// Keyframes for one property of one node.
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub node: usize,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: String,
+    /// Seconds, the last keyframe of the longest channel
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    pub fn new(name: impl Into<String>, channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        Self {
+            name: name.into(),
+            duration,
+            channels,
+        }
+    }
+
+    /// Overwrites the animated properties of `pose` at `time`. Channels that target
+    /// nodes outside the skeleton are ignored.
+    pub fn sample(&self, time: f32, skeleton: &Skeleton, pose: &mut [Transform]) {
+        for channel in &self.channels {
+            let Some(joint) = skeleton.joint_for_node(channel.node) else {
+                continue;
+            };
+            if channel.times.is_empty() {
+                continue;
+            }
+            let transform = &mut pose[joint];
+            match &channel.values {
+                ChannelValues::Translation(values) => {
+                    transform.position = sample(channel, time, |i| values[i], lerp)
+                }
+                ChannelValues::Scale(values) => {
+                    transform.scale = sample(channel, time, |i| values[i], lerp)
+                }
+                ChannelValues::Rotation(values) => {
+                    transform.rotation = sample(channel, time, |i| values[i], nlerp).normalize()
+                }
+                ChannelValues::MorphWeights(_) => {}
+            }
+        }
+    }
+
+    /// Writes the morph target weights of `node` at `time` into `weights`.
+    /// Returns false when the clip doesn't animate them.
+    pub fn sample_weights(&self, time: f32, node: usize, weights: &mut [f32]) -> bool {
+        let mut animated = false;
+        for channel in &self.channels {
+            let ChannelValues::MorphWeights(values) = &channel.values else {
+                continue;
+            };
+            if channel.node != node || channel.times.is_empty() {
+                continue;
+            }
+            let slots = match channel.interpolation {
+                Interpolation::CubicSpline => channel.times.len() * 3,
+                _ => channel.times.len(),
+            };
+            let targets = values.len() / slots;
+            for (target, weight) in weights.iter_mut().enumerate().take(targets) {
+                *weight = sample(channel, time, |i| values[i * targets + target], lerp);
+            }
+            animated = true;
+        }
+        animated
+    }
+}
+
+fn lerp<T>(a: T, b: T, s: f32) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    a * (1.0 - s) + b * s
+}
+
+/// Shortest-path normalized lerp, close enough to slerp between neighbouring keys.
+pub fn nlerp(a: Quaternion<f32>, b: Quaternion<f32>, s: f32) -> Quaternion<f32> {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    (a * (1.0 - s) + b * s).normalize()
+}
+
+/// `values` maps a slot to its value: the key index, or `key * 3 + {0, 1, 2}` for the
+/// in-tangent, value and out-tangent of a cubic spline.
+fn sample<T>(
+    channel: &Channel,
+    time: f32,
+    values: impl Fn(usize) -> T,
+    lerp: impl Fn(T, T, f32) -> T,
+) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    let times = &channel.times;
+    let cubic = channel.interpolation == Interpolation::CubicSpline;
+    let value = |key: usize| {
+        if cubic {
+            values(key * 3 + 1)
+        } else {
+            values(key)
+        }
+    };
+
+    let last = times.len() - 1;
+    if time <= times[0] {
+        return value(0);
+    }
+    if time >= times[last] {
+        return value(last);
+    }
+
+    let next = times.partition_point(|&key| key <= time);
+    let prev = next - 1;
+    let dt = times[next] - times[prev];
+    let s = (time - times[prev]) / dt;
+
+    match channel.interpolation {
+        Interpolation::Step => value(prev),
+        Interpolation::Linear => lerp(value(prev), value(next), s),
+        Interpolation::CubicSpline => {
+            let out_tangent = values(prev * 3 + 2);
+            let in_tangent = values(next * 3);
+            let (s2, s3) = (s * s, s * s * s);
+            value(prev) * (2.0 * s3 - 3.0 * s2 + 1.0)
+                + out_tangent * ((s3 - 2.0 * s2 + s) * dt)
+                + value(next) * (-2.0 * s3 + 3.0 * s2)
+                + in_tangent * ((s3 - s2) * dt)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Matrix4, Rotation3 as _, SquareMatrix as _};
+
+    use super::*;
+    use crate::animation::skeleton::Joint;
+
+    /// One joint, for node 0
+    fn skeleton() -> Skeleton {
+        Skeleton::new(vec![Joint {
+            name: "root".to_owned(),
+            node: 0,
+            parent: None,
+            offset: Matrix4::identity(),
+            rest: Transform::default(),
+            inverse_bind: Matrix4::identity(),
+        }])
+        .unwrap()
+    }
+
+    fn translation(interpolation: Interpolation, times: &[f32], x: &[f32]) -> AnimationClip {
+        AnimationClip::new(
+            "clip",
+            vec![Channel {
+                node: 0,
+                interpolation,
+                times: times.to_vec(),
+                values: ChannelValues::Translation(
+                    x.iter().map(|&x| Vector3::new(x, 0.0, 0.0)).collect(),
+                ),
+            }],
+        )
+    }
+
+    /// X of the joint at each of `times`
+    fn x_at(clip: &AnimationClip, times: &[f32]) -> Vec<f32> {
+        let skeleton = skeleton();
+        times
+            .iter()
+            .map(|&time| {
+                let mut pose = skeleton.rest_pose();
+                clip.sample(time, &skeleton, &mut pose);
+                pose[0].position.x
+            })
+            .collect()
+    }
+
+    #[test]
+    fn linear_keys_are_interpolated_and_clamped() {
+        let clip = translation(Interpolation::Linear, &[0.0, 1.0, 3.0], &[0.0, 2.0, 6.0]);
+        assert_eq!(clip.duration, 3.0);
+        assert_eq!(
+            x_at(&clip, &[-1.0, 0.0, 0.5, 1.0, 2.0, 3.0, 5.0]),
+            [0.0, 0.0, 1.0, 2.0, 4.0, 6.0, 6.0]
+        );
+    }
+
+    #[test]
+    fn step_keys_hold_until_the_next() {
+        let clip = translation(Interpolation::Step, &[0.0, 1.0, 3.0], &[0.0, 2.0, 6.0]);
+        assert_eq!(
+            x_at(&clip, &[0.5, 0.999, 1.0, 2.9, 4.0]),
+            [0.0, 0.0, 2.0, 2.0, 6.0]
+        );
+    }
+
+    #[test]
+    fn cubic_splines_use_the_tangents() {
+        // In-tangent, value, out-tangent per key
+        let flat = translation(
+            Interpolation::CubicSpline,
+            &[0.0, 1.0],
+            &[9.0, 0.0, 0.0, 0.0, 1.0, 9.0],
+        );
+        // Zero tangents ease in and out, the outer tangents are never read
+        assert_eq!(x_at(&flat, &[-1.0, 0.5, 2.0]), [0.0, 0.5, 1.0]);
+        assert!((x_at(&flat, &[0.25])[0] - 0.15625).abs() < 1e-6);
+
+        // Tangents are per second, the key spacing scales them: this one is a straight line
+        let straight = translation(
+            Interpolation::CubicSpline,
+            &[0.0, 2.0],
+            &[0.0, 0.0, 0.5, 0.5, 1.0, 0.0],
+        );
+        for (x, expected) in x_at(&straight, &[0.5, 1.0, 1.5])
+            .into_iter()
+            .zip([0.25, 0.5, 0.75])
+        {
+            assert!((x - expected).abs() < 1e-6, "{x} != {expected}");
+        }
+    }
+
+    #[test]
+    fn nlerp_takes_the_short_way() {
+        let a = Quaternion::from_angle_z(Deg(0.0));
+        let b = Quaternion::from_angle_z(Deg(90.0));
+        let expected = Quaternion::from_angle_z(Deg(45.0));
+        // `-b` is the same rotation, from the other hemisphere
+        for b in [b, -b] {
+            let q = nlerp(a, b, 0.5);
+            assert!(q.dot(expected).abs() > 0.99999, "{q:?}");
+            assert!((q.magnitude() - 1.0).abs() < 1e-6);
+        }
+        assert!(nlerp(a, -a, 0.5).dot(a).abs() > 0.99999);
+    }
+
+    #[test]
+    fn rotations_come_out_normalized() {
+        let clip = AnimationClip::new(
+            "spin",
+            vec![Channel {
+                node: 0,
+                interpolation: Interpolation::Linear,
+                times: vec![0.0, 1.0],
+                values: ChannelValues::Rotation(vec![
+                    Quaternion::from_angle_y(Deg(0.0)),
+                    // Stored from the other hemisphere
+                    -Quaternion::from_angle_y(Deg(120.0)),
+                ]),
+            }],
+        );
+        let skeleton = skeleton();
+        let mut pose = skeleton.rest_pose();
+        clip.sample(0.5, &skeleton, &mut pose);
+        let rotation = pose[0].rotation;
+        assert!((rotation.magnitude() - 1.0).abs() < 1e-6);
+        assert!(rotation.dot(Quaternion::from_angle_y(Deg(60.0))).abs() > 0.99999);
+    }
+
+    #[test]
+    fn channels_of_other_nodes_are_ignored() {
+        let mut clip = translation(Interpolation::Linear, &[0.0, 1.0], &[1.0, 1.0]);
+        clip.channels[0].node = 7;
+        assert_eq!(x_at(&clip, &[0.5]), [0.0]);
+        // No keys, nothing to sample
+        let empty = translation(Interpolation::Linear, &[], &[]);
+        assert_eq!(x_at(&empty, &[0.5]), [0.0]);
+        assert_eq!(empty.duration, 0.0);
+    }
+
+    #[test]
+    fn morph_weights_are_sampled_per_target() {
+        let channel = |interpolation, values: Vec<f32>| Channel {
+            node: 3,
+            interpolation,
+            times: vec![0.0, 1.0],
+            values: ChannelValues::MorphWeights(values),
+        };
+        let clip = AnimationClip::new(
+            "blink",
+            vec![channel(Interpolation::Linear, vec![0.0, 1.0, 1.0, 0.0])],
+        );
+        let mut weights = [9.0; 3];
+        assert!(clip.sample_weights(0.25, 3, &mut weights));
+        // The third weight has no target in the clip
+        assert_eq!(weights, [0.25, 0.75, 9.0]);
+        assert!(!clip.sample_weights(0.25, 4, &mut weights));
+
+        // Two targets, tangents around each value
+        let cubic = AnimationClip::new(
+            "blink",
+            vec![channel(
+                Interpolation::CubicSpline,
+                vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0],
+            )],
+        );
+        let mut weights = [0.0; 2];
+        assert!(cubic.sample_weights(0.5, 3, &mut weights));
+        assert_eq!(weights, [0.5, 0.5]);
+    }
+}
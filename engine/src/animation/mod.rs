@@ -0,0 +1,41 @@
+pub mod clip;
+pub mod player;
+pub mod skeleton;
+
+use bevy_ecs::world::World;
+
+use crate::{
+    animation::player::AnimationPlayer,
+    asset_manager::AssetManager,
+    components::{JointMatrices, MorphWeights, SkinnedMesh, SourceNode},
+};
+
+/// Column-major mat4 as uploaded to the joint storage buffer
+pub type JointMatrix = [[f32; 4]; 4];
+
+/// Advances every [`AnimationPlayer`], recomputes the joint matrices of skinned entities
+/// and the morph target weights of morphed ones. Skinned entities without a player are held
+/// in their rest pose.
+pub fn animate(world: &mut World, assets: &AssetManager, dt: f32) {
+    let mut players = world.query::<&mut AnimationPlayer>();
+    for mut player in players.iter_mut(world) {
+        player.update(dt, assets.animations());
+    }
+
+    let mut skinned = world.query::<(&SkinnedMesh, Option<&AnimationPlayer>, &mut JointMatrices)>();
+    for (skin, player, mut joints) in skinned.iter_mut(world) {
+        let Some(skeleton) = assets.skeleton(skin.skeleton) else {
+            continue;
+        };
+        let pose = match player {
+            Some(player) => player.sample(skeleton, assets.animations()),
+            None => skeleton.rest_pose(),
+        };
+        skeleton.joint_matrices(&pose, &mut joints.0);
+    }
+
+    let mut morphed = world.query::<(&SourceNode, &AnimationPlayer, &mut MorphWeights)>();
+    for (node, player, mut weights) in morphed.iter_mut(world) {
+        player.sample_weights(node.0, assets.animations(), &mut weights.0);
+    }
+}
@@ -0,0 +1,352 @@
+use bevy_ecs::component::Component;
+use cgmath::{InnerSpace as _, Quaternion, Vector3, Zero as _};
+
+use crate::{
+    animation::{
+        clip::AnimationClip,
+        skeleton::{Pose, Skeleton},
+    },
+    asset_manager::handle::{Assets, Handle},
+};
+
+#[derive(Clone, Debug)]
+pub struct AnimationLayer {
+    pub clip: Handle<AnimationClip>,
+    pub time: f32,
+    pub speed: f32,
+    pub weight: f32,
+    pub looping: bool,
+    // Weight moves towards `target_weight` by `fade_rate` per second
+    target_weight: f32,
+    fade_rate: f32,
+}
+
+impl AnimationLayer {
+    fn new(clip: Handle<AnimationClip>, weight: f32) -> Self {
+        Self {
+            clip,
+            time: 0.0,
+            speed: 1.0,
+            weight,
+            looping: true,
+            target_weight: weight,
+            fade_rate: 0.0,
+        }
+    }
+}
+
+/// Plays and blends animation clips on a skinned entity. Layers are blended by weight;
+/// when the weights add up to less than one the rest pose fills the remainder.
+#[derive(Component, Clone, Debug, Default)]
+pub struct AnimationPlayer {
+    pub layers: Vec<AnimationLayer>,
+    pub paused: bool,
+}
+
+impl AnimationPlayer {
+    /// Stops everything else and plays `clip` from the start.
+    pub fn play(&mut self, clip: Handle<AnimationClip>) -> &mut AnimationLayer {
+        self.layers.clear();
+        self.layers.push(AnimationLayer::new(clip, 1.0));
+        self.layers.last_mut().unwrap()
+    }
+
+    /// Fades the current layers out and `clip` in over `seconds`.
+    pub fn crossfade(&mut self, clip: Handle<AnimationClip>, seconds: f32) -> &mut AnimationLayer {
+        if seconds <= 0.0 {
+            return self.play(clip);
+        }
+        for layer in &mut self.layers {
+            layer.target_weight = 0.0;
+            layer.fade_rate = layer.weight / seconds;
+        }
+        let mut layer = AnimationLayer::new(clip, 0.0);
+        layer.target_weight = 1.0;
+        layer.fade_rate = 1.0 / seconds;
+        self.layers.push(layer);
+        self.layers.last_mut().unwrap()
+    }
+
+    /// Plays `clip` on top of the current layers with a fixed weight.
+    pub fn add_layer(&mut self, clip: Handle<AnimationClip>, weight: f32) -> &mut AnimationLayer {
+        self.layers.push(AnimationLayer::new(clip, weight));
+        self.layers.last_mut().unwrap()
+    }
+
+    pub fn update(&mut self, dt: f32, clips: &Assets<AnimationClip>) {
+        if self.paused {
+            return;
+        }
+        for layer in &mut self.layers {
+            let duration = clips.get(layer.clip).map_or(0.0, |clip| clip.duration);
+            layer.time += dt * layer.speed;
+            if duration > 0.0 {
+                layer.time = if layer.looping {
+                    layer.time.rem_euclid(duration)
+                } else {
+                    layer.time.clamp(0.0, duration)
+                };
+            }
+
+            let step = layer.fade_rate * dt;
+            layer.weight = if layer.weight < layer.target_weight {
+                (layer.weight + step).min(layer.target_weight)
+            } else {
+                (layer.weight - step).max(layer.target_weight)
+            };
+        }
+        // Faded out layers are done
+        self.layers
+            .retain(|layer| layer.weight > 0.0 || layer.target_weight > 0.0);
+    }
+
+    pub fn sample(&self, skeleton: &Skeleton, clips: &Assets<AnimationClip>) -> Pose {
+        let rest = skeleton.rest_pose();
+        let layers: Vec<(&AnimationLayer, &AnimationClip)> = self
+            .layers
+            .iter()
+            .filter(|layer| layer.weight > 0.0)
+            .filter_map(|layer| clips.get(layer.clip).map(|clip| (layer, clip.as_ref())))
+            .collect();
+        match layers.as_slice() {
+            [] => return rest,
+            [(layer, clip)] if layer.weight >= 1.0 => {
+                let mut pose = rest;
+                clip.sample(layer.time, skeleton, &mut pose);
+                return pose;
+            }
+            _ => {}
+        }
+
+        let total: f32 = layers.iter().map(|(layer, _)| layer.weight).sum();
+        let rest_weight = (1.0 - total).max(0.0);
+        let norm = 1.0 / (total + rest_weight);
+
+        let mut translation = vec![Vector3::zero(); rest.len()];
+        let mut scale = vec![Vector3::zero(); rest.len()];
+        let mut rotation = vec![Quaternion::zero(); rest.len()];
+        let mut accumulate = |pose: &Pose, weight: f32| {
+            for (joint, transform) in pose.iter().enumerate() {
+                translation[joint] += transform.position * weight;
+                scale[joint] += transform.scale * weight;
+                // Keep every rotation in the same hemisphere before summing
+                let q = if rotation[joint].dot(transform.rotation) < 0.0 {
+                    -transform.rotation
+                } else {
+                    transform.rotation
+                };
+                rotation[joint] += q * weight;
+            }
+        };
+
+        if rest_weight > 0.0 {
+            accumulate(&rest, rest_weight * norm);
+        }
+        for (layer, clip) in &layers {
+            let mut pose = rest.clone();
+            clip.sample(layer.time, skeleton, &mut pose);
+            accumulate(&pose, layer.weight * norm);
+        }
+
+        rest.into_iter()
+            .enumerate()
+            .map(|(joint, mut transform)| {
+                transform.position = translation[joint];
+                transform.scale = scale[joint];
+                transform.rotation = rotation[joint].normalize();
+                transform
+            })
+            .collect()
+    }
+
+    /// Blends the morph target weights the playing clips set for `node`. Layers that don't
+    /// animate the node are skipped, `weights` is left as is when none of them does.
+    pub fn sample_weights(&self, node: usize, clips: &Assets<AnimationClip>, weights: &mut [f32]) {
+        let mut blended = vec![0.0; weights.len()];
+        let mut sampled = vec![0.0; weights.len()];
+        let mut total = 0.0;
+        for layer in self.layers.iter().filter(|layer| layer.weight > 0.0) {
+            let Some(clip) = clips.get(layer.clip) else {
+                continue;
+            };
+            sampled.copy_from_slice(weights);
+            if clip.sample_weights(layer.time, node, &mut sampled) {
+                for (sum, value) in blended.iter_mut().zip(&sampled) {
+                    *sum += value * layer.weight;
+                }
+                total += layer.weight;
+            }
+        }
+        if total > 0.0 {
+            for (weight, sum) in weights.iter_mut().zip(blended) {
+                *weight = sum / total;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Matrix4, Rotation3 as _, SquareMatrix as _};
+
+    use super::*;
+    use crate::{
+        animation::{
+            clip::{Channel, ChannelValues, Interpolation},
+            skeleton::Joint,
+        },
+        components::Transform,
+    };
+
+    fn skeleton() -> Skeleton {
+        Skeleton::new(vec![Joint {
+            name: "root".to_owned(),
+            node: 0,
+            parent: None,
+            offset: Matrix4::identity(),
+            rest: Transform::default(),
+            inverse_bind: Matrix4::identity(),
+        }])
+        .unwrap()
+    }
+
+    /// Holds the root at `x` for two seconds
+    fn hold(x: f32) -> AnimationClip {
+        AnimationClip::new(
+            format!("hold {x}"),
+            vec![Channel {
+                node: 0,
+                interpolation: Interpolation::Linear,
+                times: vec![0.0, 2.0],
+                values: ChannelValues::Translation(vec![Vector3::new(x, 0.0, 0.0); 2]),
+            }],
+        )
+    }
+
+    fn x(player: &AnimationPlayer, clips: &Assets<AnimationClip>) -> f32 {
+        player.sample(&skeleton(), clips)[0].position.x
+    }
+
+    #[test]
+    fn layers_blend_by_weight() {
+        let mut clips = Assets::default();
+        let (two, four) = (clips.insert(hold(2.0)), clips.insert(hold(4.0)));
+
+        let mut player = AnimationPlayer::default();
+        assert_eq!(x(&player, &clips), 0.0);
+        player.play(two);
+        assert_eq!(x(&player, &clips), 2.0);
+
+        player.layers[0].weight = 0.5;
+        player.add_layer(four, 0.5);
+        assert_eq!(x(&player, &clips), 3.0);
+
+        // Weights above one are normalized
+        player.layers[0].weight = 3.0;
+        player.layers[1].weight = 1.0;
+        assert_eq!(x(&player, &clips), 2.5);
+
+        // Below one the rest pose makes up the difference
+        player.play(four).weight = 0.25;
+        assert_eq!(x(&player, &clips), 1.0);
+
+        // Layers of removed clips don't count
+        player.add_layer(two, 0.75);
+        clips.remove(two);
+        assert_eq!(x(&player, &clips), 1.0);
+    }
+
+    #[test]
+    fn rotations_blend_in_one_hemisphere() {
+        let turn = |degrees: f32, sign: f32| {
+            AnimationClip::new(
+                "turn",
+                vec![Channel {
+                    node: 0,
+                    interpolation: Interpolation::Step,
+                    times: vec![0.0],
+                    values: ChannelValues::Rotation(vec![
+                        Quaternion::from_angle_z(Deg(degrees)) * sign,
+                    ]),
+                }],
+            )
+        };
+        let mut clips = Assets::default();
+        let (a, b) = (clips.insert(turn(0.0, 1.0)), clips.insert(turn(90.0, -1.0)));
+        let mut player = AnimationPlayer::default();
+        player.play(a).weight = 0.5;
+        player.add_layer(b, 0.5);
+        let rotation = player.sample(&skeleton(), &clips)[0].rotation;
+        assert!(rotation.dot(Quaternion::from_angle_z(Deg(45.0))).abs() > 0.99999);
+    }
+
+    #[test]
+    fn update_loops_or_clamps_the_time() {
+        let mut clips = Assets::default();
+        let clip = clips.insert(hold(1.0));
+        let mut player = AnimationPlayer::default();
+        player.play(clip).speed = 2.0;
+        player.update(1.5, &clips);
+        assert_eq!(player.layers[0].time, 1.0);
+
+        player.layers[0].looping = false;
+        player.update(1.5, &clips);
+        assert_eq!(player.layers[0].time, 2.0);
+
+        player.paused = true;
+        player.layers[0].time = 0.5;
+        player.update(1.0, &clips);
+        assert_eq!(player.layers[0].time, 0.5);
+    }
+
+    #[test]
+    fn crossfades_move_the_weight_and_drop_faded_layers() {
+        let mut clips = Assets::default();
+        let (two, four) = (clips.insert(hold(2.0)), clips.insert(hold(4.0)));
+        let mut player = AnimationPlayer::default();
+        player.play(two);
+        player.crossfade(four, 1.0);
+        assert_eq!(x(&player, &clips), 2.0);
+
+        player.update(0.5, &clips);
+        assert_eq!(player.layers.len(), 2);
+        assert_eq!(x(&player, &clips), 3.0);
+
+        player.update(0.75, &clips);
+        assert_eq!(player.layers.len(), 1);
+        assert_eq!(player.layers[0].clip, four);
+        assert_eq!(x(&player, &clips), 4.0);
+    }
+
+    #[test]
+    fn morph_weights_blend_over_the_layers_that_set_them() {
+        let weights = |value: f32| {
+            AnimationClip::new(
+                "weights",
+                vec![Channel {
+                    node: 5,
+                    interpolation: Interpolation::Step,
+                    times: vec![0.0],
+                    values: ChannelValues::MorphWeights(vec![value, 1.0 - value]),
+                }],
+            )
+        };
+        let mut clips = Assets::default();
+        let (open, shut, other) = (
+            clips.insert(weights(1.0)),
+            clips.insert(weights(0.0)),
+            clips.insert(hold(1.0)),
+        );
+        let mut player = AnimationPlayer::default();
+        player.play(open).weight = 0.25;
+        player.add_layer(shut, 0.75);
+        player.add_layer(other, 1.0);
+        let mut sampled = [0.5, 0.5];
+        player.sample_weights(5, &clips, &mut sampled);
+        assert_eq!(sampled, [0.25, 0.75]);
+
+        let mut untouched = [0.5, 0.5];
+        player.sample_weights(6, &clips, &mut untouched);
+        assert_eq!(untouched, [0.5, 0.5]);
+    }
+}
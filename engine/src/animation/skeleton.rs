@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+
+use cgmath::{Matrix4, SquareMatrix as _};
+
+use crate::{animation::JointMatrix, components::Transform};
+
+#[derive(Clone, Debug)]
+pub struct Joint {
+    pub name: String,
+    /// Node of the source file, animation channels target nodes
+    pub node: usize,
+    pub parent: Option<usize>,
+    /// Static transform between the parent joint and this one (non-joint nodes in between).
+    /// For a root joint it places the skeleton in model space.
+    pub offset: Matrix4<f32>,
+    /// Local transform when nothing is playing
+    pub rest: Transform,
+    pub inverse_bind: Matrix4<f32>,
+}
+
+/// Joint hierarchy of a skin. The joint order matches the joint indices stored in the
+/// mesh's [`SkinVertex`](crate::model::SkinVertex)es.
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    joints: Vec<Joint>,
+    // Parents come before their children
+    order: Vec<usize>,
+    node_to_joint: HashMap<usize, usize>,
+}
+
+/// Local transform of every joint, indexed like [`Skeleton::joints`]
+pub type Pose = Vec<Transform>;
+
+impl Skeleton {
+    pub fn new(joints: Vec<Joint>) -> anyhow::Result<Self> {
+        let mut order = Vec::with_capacity(joints.len());
+        let mut placed = vec![false; joints.len()];
+        while order.len() < joints.len() {
+            let before = order.len();
+            for (index, joint) in joints.iter().enumerate() {
+                if !placed[index] && joint.parent.is_none_or(|parent| placed[parent]) {
+                    placed[index] = true;
+                    order.push(index);
+                }
+            }
+            if order.len() == before {
+                anyhow::bail!("Skeleton joint hierarchy has a cycle");
+            }
+        }
+        let node_to_joint = joints
+            .iter()
+            .enumerate()
+            .map(|(index, joint)| (joint.node, index))
+            .collect();
+
+        Ok(Self {
+            joints,
+            order,
+            node_to_joint,
+        })
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    pub fn joint_for_node(&self, node: usize) -> Option<usize> {
+        self.node_to_joint.get(&node).copied()
+    }
+
+    pub fn rest_pose(&self) -> Pose {
+        self.joints.iter().map(|joint| joint.rest).collect()
+    }
+
+    /// Skinning matrices (joint model transform times inverse bind) for the vertex shader.
+    pub fn joint_matrices(&self, pose: &[Transform], out: &mut Vec<JointMatrix>) {
+        let mut global = vec![Matrix4::identity(); self.joints.len()];
+        for &index in &self.order {
+            let joint = &self.joints[index];
+            let parent = joint.parent.map_or(Matrix4::identity(), |p| global[p]);
+            global[index] = parent * joint.offset * pose[index].matrix();
+        }
+
+        out.clear();
+        out.extend(
+            self.joints
+                .iter()
+                .zip(&global)
+                .map(|(joint, global)| -> JointMatrix { (global * joint.inverse_bind).into() }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use super::*;
+
+    fn joint(node: usize, parent: Option<usize>, x: f32) -> Joint {
+        Joint {
+            name: format!("joint {node}"),
+            node,
+            parent,
+            offset: Matrix4::identity(),
+            rest: Transform {
+                position: Vector3::new(x, 0.0, 0.0),
+                ..Default::default()
+            },
+            inverse_bind: Matrix4::identity(),
+        }
+    }
+
+    #[test]
+    fn parents_are_ordered_before_their_children() {
+        // Listed leaf first: 0 -> 1 -> 2, with 3 a second root
+        let skeleton = Skeleton::new(vec![
+            joint(10, Some(1), 1.0),
+            joint(11, Some(2), 1.0),
+            joint(12, None, 1.0),
+            joint(13, None, 1.0),
+        ])
+        .unwrap();
+        let position = |index: usize| skeleton.order.iter().position(|&i| i == index).unwrap();
+        assert!(position(2) < position(1));
+        assert!(position(1) < position(0));
+        assert_eq!(skeleton.order.len(), 4);
+
+        assert_eq!(skeleton.joint_for_node(11), Some(1));
+        assert_eq!(skeleton.joint_for_node(14), None);
+    }
+
+    #[test]
+    fn cycles_are_errors() {
+        let error = Skeleton::new(vec![
+            joint(0, None, 0.0),
+            joint(1, Some(2), 0.0),
+            joint(2, Some(1), 0.0),
+        ])
+        .err()
+        .unwrap();
+        assert_eq!(error.to_string(), "Skeleton joint hierarchy has a cycle");
+        assert!(Skeleton::new(vec![joint(0, Some(0), 0.0)]).is_err());
+    }
+
+    #[test]
+    fn joint_matrices_chain_the_parents() {
+        // The child is listed before its parent
+        let child = joint(1, Some(1), 1.0);
+        let mut root = joint(0, None, 2.0);
+        root.offset = Matrix4::from_translation(Vector3::new(0.0, 5.0, 0.0));
+        root.inverse_bind = Matrix4::from_translation(Vector3::new(-2.0, -5.0, 0.0));
+        let skeleton = Skeleton::new(vec![child, root]).unwrap();
+
+        let mut matrices = Vec::new();
+        skeleton.joint_matrices(&skeleton.rest_pose(), &mut matrices);
+        let identity: JointMatrix = Matrix4::identity().into();
+        // The root sits at its bind pose, the child one unit further along X
+        assert_eq!(matrices[1], identity);
+        assert_eq!(matrices[0][3], [3.0, 5.0, 0.0, 1.0]);
+
+        let mut pose = skeleton.rest_pose();
+        pose[1].position.x = 4.0;
+        skeleton.joint_matrices(&pose, &mut matrices);
+        assert_eq!(matrices[1][3], [2.0, 0.0, 0.0, 1.0]);
+        assert_eq!(matrices[0][3], [5.0, 5.0, 0.0, 1.0]);
+    }
+}
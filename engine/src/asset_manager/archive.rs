@@ -0,0 +1,425 @@
+// Single-file asset archive for shipping builds. Little endian throughout:
+//
+//   header   magic "CHUPACK\0", version: u32, entry count: u32, TOC offset: u64
+//   data     entry contents, back to back
+//   TOC      per entry: path length: u16, path (UTF-8, '/' separated, relative to the assets
+//            folder), offset: u64, stored size: u64, size: u64, compression: u8,
+//            xxh3 hash of the uncompressed content: u64
+//
+// The TOC goes last so the writer can stream the entries.
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+    path::{Component, Path},
+    sync::Mutex,
+};
+
+use anyhow::{Context, bail, ensure};
+
+const MAGIC: [u8; 8] = *b"CHUPACK\0";
+const VERSION: u32 = 1;
+const HEADER_SIZE: u64 = 24;
+/// TOC entry without its path
+const TOC_ENTRY_SIZE: usize = 2 + 8 + 8 + 8 + 1 + 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lz4,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ArchiveEntry {
+    pub offset: u64,
+    /// Bytes in the archive, after compression
+    pub stored_size: u64,
+    pub size: u64,
+    pub compression: Compression,
+    pub hash: u64,
+}
+
+trait Source: Read + Seek + Send {}
+impl<T: Read + Seek + Send> Source for T {}
+
+/// Read side of the format, entries are read on demand
+pub struct Archive {
+    source: Mutex<Box<dyn Source>>,
+    entries: HashMap<String, ArchiveEntry>,
+}
+
+impl Archive {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path).with_context(|| format!("Opening {path:?}"))?;
+        Self::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("Reading {path:?}"))
+    }
+
+    /// An archive that is already in memory, e.g. downloaded on the web
+    pub fn from_bytes(bytes: Vec<u8>) -> anyhow::Result<Self> {
+        Self::from_reader(Cursor::new(bytes))
+    }
+
+    pub fn from_reader(mut source: impl Read + Seek + Send + 'static) -> anyhow::Result<Self> {
+        let mut header = [0; HEADER_SIZE as usize];
+        source.read_exact(&mut header).context("Truncated header")?;
+        ensure!(header[..8] == MAGIC, "Not an asset archive");
+        let mut header = Cursor::new(&header[8..]);
+        let version = read_u32(&mut header)?;
+        ensure!(
+            version == VERSION,
+            "Archive version {version}, expected {VERSION}"
+        );
+        let count = read_u32(&mut header)?;
+        let toc_offset = read_u64(&mut header)?;
+
+        let len = source.seek(SeekFrom::End(0))?;
+        ensure!(toc_offset <= len, "Truncated archive");
+        source.seek(SeekFrom::Start(toc_offset))?;
+        let mut toc = Vec::new();
+        source.read_to_end(&mut toc)?;
+        // The smallest TOC entry has an empty path
+        let capacity = (count as usize).min(toc.len() / TOC_ENTRY_SIZE);
+        let mut toc = Cursor::new(toc);
+        let mut entries = HashMap::with_capacity(capacity);
+        for _ in 0..count {
+            let mut path = vec![0; read_u16(&mut toc)? as usize];
+            toc.read_exact(&mut path)?;
+            let path = String::from_utf8(path).context("Entry path is not UTF-8")?;
+            let entry = ArchiveEntry {
+                offset: read_u64(&mut toc)?,
+                stored_size: read_u64(&mut toc)?,
+                size: read_u64(&mut toc)?,
+                compression: match read_u8(&mut toc)? {
+                    0 => Compression::None,
+                    1 => Compression::Lz4,
+                    other => bail!("{path}: unknown compression {other}"),
+                },
+                hash: read_u64(&mut toc)?,
+            };
+            ensure!(
+                entry
+                    .offset
+                    .checked_add(entry.stored_size)
+                    .is_some_and(|end| end <= toc_offset),
+                "{path}: data runs past the table of contents"
+            );
+            entries.insert(path, entry);
+        }
+
+        Ok(Self {
+            source: Mutex::new(Box::new(source)),
+            entries,
+        })
+    }
+
+    pub fn contains(&self, path: impl AsRef<Path>) -> bool {
+        self.entries.contains_key(&entry_name(path.as_ref()))
+    }
+
+    pub fn entry(&self, path: impl AsRef<Path>) -> Option<&ArchiveEntry> {
+        self.entries.get(&entry_name(path.as_ref()))
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &ArchiveEntry)> {
+        self.entries
+            .iter()
+            .map(|(path, entry)| (path.as_str(), entry))
+    }
+
+    /// `None` when the archive has no such file. The content is checked against its hash.
+    pub fn read(&self, path: impl AsRef<Path>) -> anyhow::Result<Option<Vec<u8>>> {
+        let name = entry_name(path.as_ref());
+        let Some(entry) = self.entries.get(&name) else {
+            return Ok(None);
+        };
+
+        let mut stored = vec![0; entry.stored_size as usize];
+        {
+            let mut source = self
+                .source
+                .lock()
+                .map_err(|_| anyhow::anyhow!("Archive poisoned"))?;
+            source.seek(SeekFrom::Start(entry.offset))?;
+            source.read_exact(&mut stored)?;
+        }
+        let data = match entry.compression {
+            Compression::None => stored,
+            Compression::Lz4 => lz4_flex::block::decompress(&stored, entry.size as usize)
+                .with_context(|| format!("{name}: decompressing"))?,
+        };
+        ensure!(
+            xxhash_rust::xxh3::xxh3_64(&data) == entry.hash,
+            "{name}: content does not match its hash"
+        );
+        Ok(Some(data))
+    }
+}
+
+/// Builds an archive in memory and writes it in one go, entries sorted by path so the same
+/// files always give the same archive
+pub struct ArchiveWriter {
+    entries: BTreeMap<String, Vec<u8>>,
+    compress: bool,
+}
+
+impl Default for ArchiveWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ArchiveWriter {
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            compress: true,
+        }
+    }
+
+    /// LZ4 compress entries that get smaller, on by default
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// `path` is relative to the assets folder, a second file with the same path replaces
+    /// the first
+    pub fn add(&mut self, path: impl AsRef<Path>, data: Vec<u8>) {
+        self.entries.insert(entry_name(path.as_ref()), data);
+    }
+
+    /// Every file below `root`, with paths relative to it. Returns the number of files.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_dir(&mut self, root: impl AsRef<Path>) -> anyhow::Result<usize> {
+        let root = root.as_ref();
+        let mut pending = vec![root.to_path_buf()];
+        let mut count = 0;
+        while let Some(dir) = pending.pop() {
+            for entry in std::fs::read_dir(&dir).with_context(|| format!("Reading {dir:?}"))? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                let data = std::fs::read(&path).with_context(|| format!("Reading {path:?}"))?;
+                self.add(path.strip_prefix(root)?, data);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the archive size in bytes
+    pub fn write(&self, mut out: impl Write) -> anyhow::Result<u64> {
+        let mut toc = Vec::new();
+        let mut offset = HEADER_SIZE;
+        let mut data = Vec::new();
+        for (name, content) in &self.entries {
+            let compressed = self
+                .compress
+                .then(|| lz4_flex::block::compress(content))
+                .filter(|compressed| compressed.len() < content.len());
+            let (compression, stored) = match &compressed {
+                Some(compressed) => (Compression::Lz4, compressed.as_slice()),
+                None => (Compression::None, content.as_slice()),
+            };
+            let path_len = u16::try_from(name.len()).context("Entry path too long")?;
+
+            toc.extend(path_len.to_le_bytes());
+            toc.extend(name.as_bytes());
+            toc.extend(offset.to_le_bytes());
+            toc.extend((stored.len() as u64).to_le_bytes());
+            toc.extend((content.len() as u64).to_le_bytes());
+            toc.push(match compression {
+                Compression::None => 0,
+                Compression::Lz4 => 1,
+            });
+            toc.extend(xxhash_rust::xxh3::xxh3_64(content).to_le_bytes());
+
+            data.extend_from_slice(stored);
+            offset += stored.len() as u64;
+        }
+
+        out.write_all(&MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+        out.write_all(&offset.to_le_bytes())?;
+        out.write_all(&data)?;
+        out.write_all(&toc)?;
+        Ok(offset + toc.len() as u64)
+    }
+}
+
+/// Archive paths use '/' on every platform, `.` and `..` are resolved
+pub(crate) fn entry_name(path: &Path) -> String {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy()),
+            Component::ParentDir => {
+                parts.pop();
+            }
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    parts.join("/")
+}
+
+fn read_u8(r: &mut impl Read) -> anyhow::Result<u8> {
+    let mut bytes = [0; 1];
+    r.read_exact(&mut bytes)
+        .context("Truncated table of contents")?;
+    Ok(bytes[0])
+}
+
+fn read_u16(r: &mut impl Read) -> anyhow::Result<u16> {
+    let mut bytes = [0; 2];
+    r.read_exact(&mut bytes)
+        .context("Truncated table of contents")?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32(r: &mut impl Read) -> anyhow::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)
+        .context("Truncated table of contents")?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(r: &mut impl Read) -> anyhow::Result<u64> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)
+        .context("Truncated table of contents")?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(compress: bool) -> Vec<u8> {
+        let mut writer = ArchiveWriter::new().compress(compress);
+        writer.add("textures/noise.bin", (0..=255).collect());
+        writer.add(
+            "./shaders/../shaders/shader.wgsl",
+            "fn main() {}\n".repeat(64).into(),
+        );
+        writer.add("empty.txt", Vec::new());
+        let mut bytes = Vec::new();
+        let size = writer.write(&mut bytes).unwrap();
+        assert_eq!(size, bytes.len() as u64);
+        bytes
+    }
+
+    #[test]
+    fn read_returns_what_was_written() {
+        for compress in [false, true] {
+            let archive = Archive::from_bytes(archive(compress)).unwrap();
+            assert_eq!(archive.entries().count(), 3);
+            assert_eq!(
+                archive.read("textures/noise.bin").unwrap().unwrap(),
+                (0..=255).collect::<Vec<u8>>()
+            );
+            assert_eq!(
+                archive.read("shaders/shader.wgsl").unwrap().unwrap(),
+                "fn main() {}\n".repeat(64).as_bytes()
+            );
+            assert_eq!(archive.read("empty.txt").unwrap().unwrap(), b"");
+            assert_eq!(archive.read("missing.txt").unwrap(), None);
+            assert!(archive.contains("textures/./noise.bin"));
+
+            let compression = |path: &str| archive.entry(path).unwrap().compression;
+            // Only entries that get smaller are compressed
+            assert_eq!(compression("textures/noise.bin"), Compression::None);
+            let expected = if compress {
+                Compression::Lz4
+            } else {
+                Compression::None
+            };
+            assert_eq!(compression("shaders/shader.wgsl"), expected);
+        }
+    }
+
+    #[test]
+    fn same_files_give_the_same_archive() {
+        let mut writer = ArchiveWriter::new();
+        writer.add("empty.txt", Vec::new());
+        writer.add("shaders/shader.wgsl", "fn main() {}\n".repeat(64).into());
+        writer.add("textures/noise.bin", (0..=255).collect());
+        let mut bytes = Vec::new();
+        writer.write(&mut bytes).unwrap();
+        assert_eq!(bytes, archive(true));
+    }
+
+    #[test]
+    fn truncated_archives_are_rejected() {
+        let bytes = archive(true);
+        for len in 0..bytes.len() {
+            assert!(
+                Archive::from_bytes(bytes[..len].to_vec()).is_err(),
+                "opened {len} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn corrupt_headers_are_rejected() {
+        let error = |bytes: Vec<u8>| format!("{:#}", Archive::from_bytes(bytes).err().unwrap());
+
+        let mut bytes = archive(true);
+        bytes[0] = b'X';
+        assert_eq!(error(bytes), "Not an asset archive");
+
+        let mut bytes = archive(true);
+        bytes[8..12].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(error(bytes), "Archive version 2, expected 1");
+
+        // More entries than the TOC holds
+        let mut bytes = archive(true);
+        bytes[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            error(bytes),
+            "Truncated table of contents: failed to fill whole buffer"
+        );
+
+        let mut bytes = archive(true);
+        bytes[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(error(bytes), "Truncated archive");
+    }
+
+    #[test]
+    fn corrupt_content_fails_its_hash() {
+        let mut bytes = archive(false);
+        let offset = Archive::from_bytes(bytes.clone())
+            .unwrap()
+            .entry("textures/noise.bin")
+            .unwrap()
+            .offset;
+        bytes[offset as usize] ^= 1;
+        let archive = Archive::from_bytes(bytes).unwrap();
+        let error = archive.read("textures/noise.bin").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "textures/noise.bin: content does not match its hash"
+        );
+        assert!(archive.read("empty.txt").unwrap().is_some());
+    }
+
+    #[test]
+    fn entry_names_resolve_dots() {
+        assert_eq!(entry_name(Path::new("a/./b/../c.txt")), "a/c.txt");
+        assert_eq!(entry_name(Path::new("/a/b")), "a/b");
+        assert_eq!(entry_name(Path::new("../a")), "a");
+        assert_eq!(entry_name(Path::new("")), "");
+    }
+}
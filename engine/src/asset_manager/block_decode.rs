@@ -0,0 +1,392 @@
+// CPU decoders for block-compressed textures, used when the device lacks the format.
+// Covers the 8 bit formats textures are usually shipped in: BC1-5 and ETC2 RGB/RGBA.
+use anyhow::bail;
+
+type Block = [[u8; 4]; 16];
+
+const ETC_MODIFIERS: [[i32; 4]; 8] = [
+    [2, 8, -2, -8],
+    [5, 17, -5, -17],
+    [9, 29, -9, -29],
+    [13, 42, -13, -42],
+    [18, 60, -18, -60],
+    [24, 80, -24, -80],
+    [33, 106, -33, -106],
+    [47, 183, -47, -183],
+];
+const ETC_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+/// One mip level of `format` to tightly packed RGBA8 rows. Single and two channel formats
+/// fill the other channels the way the GPU samples them: 0 for color, 255 for alpha.
+pub(crate) fn decode(
+    format: wgpu::TextureFormat,
+    data: &[u8],
+    width: u32,
+    height: u32,
+) -> anyhow::Result<Vec<u8>> {
+    use wgpu::TextureFormat as F;
+
+    let (width, height) = (width as usize, height as usize);
+    let decode_block: fn(&[u8]) -> Block = match format.remove_srgb_suffix() {
+        F::Rgba8Unorm => return Ok(data[..width * height * 4].to_vec()),
+        F::Bc1RgbaUnorm => |block| bc1_color(block, true),
+        F::Bc2RgbaUnorm => bc2,
+        F::Bc3RgbaUnorm => bc3,
+        F::Bc4RUnorm => bc4,
+        F::Bc5RgUnorm => bc5,
+        F::Etc2Rgb8Unorm => etc2_rgb,
+        F::Etc2Rgba8Unorm => etc2_rgba,
+        other => bail!("No CPU decoder for {other:?}"),
+    };
+    let block_size = format.block_copy_size(None).unwrap_or(16) as usize;
+    let blocks_wide = width.div_ceil(4);
+    let block_count = blocks_wide * height.div_ceil(4);
+
+    let mut rgba = vec![0; width * height * 4];
+    for (i, block) in data.chunks_exact(block_size).take(block_count).enumerate() {
+        let (block_x, block_y) = (i % blocks_wide * 4, i / blocks_wide * 4);
+        for (t, texel) in decode_block(block).iter().enumerate() {
+            let (x, y) = (block_x + t % 4, block_y + t / 4);
+            // Edge blocks hang over small mips
+            if x < width && y < height {
+                let offset = (y * width + x) * 4;
+                rgba[offset..offset + 4].copy_from_slice(texel);
+            }
+        }
+    }
+    Ok(rgba)
+}
+
+fn rgb565(color: u16) -> [u32; 3] {
+    let r = (color >> 11 & 31) as u32;
+    let g = (color >> 5 & 63) as u32;
+    let b = (color & 31) as u32;
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+/// `three_color`: BC1 switches to 3 colors and transparent black when `c0 <= c1`,
+/// the color half of BC2/3 always has 4 colors
+fn bc1_color(block: &[u8], three_color: bool) -> Block {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (e0, e1) = (rgb565(c0), rgb565(c1));
+    let mix = |w0: u32, w1: u32| {
+        let [r, g, b] = [0, 1, 2].map(|i| ((e0[i] * w0 + e1[i] * w1) / (w0 + w1)) as u8);
+        [r, g, b, 255]
+    };
+    let palette = if c0 > c1 || !three_color {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+    } else {
+        [mix(1, 0), mix(0, 1), mix(1, 1), [0; 4]]
+    };
+
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    std::array::from_fn(|t| palette[(indices >> (2 * t) & 3) as usize])
+}
+
+/// Interpolated 8 bit channel of BC3 alpha, BC4 and BC5
+fn bc_channel(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let palette: [u8; 8] = std::array::from_fn(|i| {
+        let i = i as u32;
+        match i {
+            0 => a0 as u8,
+            1 => a1 as u8,
+            _ if a0 > a1 => (((8 - i) * a0 + (i - 1) * a1) / 7) as u8,
+            6 => 0,
+            7 => 255,
+            _ => (((6 - i) * a0 + (i - 1) * a1) / 5) as u8,
+        }
+    });
+
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    std::array::from_fn(|t| palette[(indices >> (3 * t) & 7) as usize])
+}
+
+fn bc2(block: &[u8]) -> Block {
+    let alpha = u64::from_le_bytes(block[..8].try_into().expect("16 byte block"));
+    let mut texels = bc1_color(&block[8..], false);
+    for (t, texel) in texels.iter_mut().enumerate() {
+        texel[3] = (alpha >> (4 * t) & 15) as u8 * 17;
+    }
+    texels
+}
+
+fn bc3(block: &[u8]) -> Block {
+    let alpha = bc_channel(&block[..8]);
+    let mut texels = bc1_color(&block[8..], false);
+    for (texel, alpha) in texels.iter_mut().zip(alpha) {
+        texel[3] = alpha;
+    }
+    texels
+}
+
+fn bc4(block: &[u8]) -> Block {
+    bc_channel(block).map(|r| [r, 0, 0, 255])
+}
+
+fn bc5(block: &[u8]) -> Block {
+    let (r, g) = (bc_channel(&block[..8]), bc_channel(&block[8..]));
+    std::array::from_fn(|t| [r[t], g[t], 0, 255])
+}
+
+fn clamp_rgb(color: [i32; 3]) -> [u8; 4] {
+    let [r, g, b] = color.map(|c| c.clamp(0, 255) as u8);
+    [r, g, b, 255]
+}
+
+/// ETC numbers the texels of a block column by column
+fn etc_texel(t: usize) -> usize {
+    t % 4 * 4 + t / 4
+}
+
+fn etc2_rgb(block: &[u8]) -> Block {
+    let bits = u64::from_be_bytes(block[..8].try_into().expect("8 byte block"));
+    let field = |shift: usize, len: u32| (bits >> shift & ((1 << len) - 1)) as i32;
+    let extend4 = |c: i32| c << 4 | c;
+    let extend5 = |c: i32| c << 3 | c >> 2;
+    let index = |t: usize| {
+        let p = etc_texel(t);
+        (field(16 + p, 1) << 1 | field(p, 1)) as usize
+    };
+    let offset = |color: [i32; 3], d: i32| color.map(|c| c + d);
+    let paint = |colors: [[i32; 3]; 4]| std::array::from_fn(|t| clamp_rgb(colors[index(t)]));
+    let subblocks = |colors: [[i32; 3]; 2], tables: [i32; 2]| {
+        let flip = field(32, 1) == 1;
+        std::array::from_fn(|t| {
+            let (x, y) = (t % 4, t / 4);
+            let sub = usize::from(if flip { y >= 2 } else { x >= 2 });
+            let modifier = ETC_MODIFIERS[tables[sub] as usize][index(t)];
+            clamp_rgb(offset(colors[sub], modifier))
+        })
+    };
+
+    // Individual mode, two 4 bit colors
+    if field(33, 1) == 0 {
+        let c0 = [field(60, 4), field(52, 4), field(44, 4)].map(extend4);
+        let c1 = [field(56, 4), field(48, 4), field(40, 4)].map(extend4);
+        return subblocks([c0, c1], [field(37, 3), field(34, 3)]);
+    }
+
+    // Differential mode, a 5 bit color and a signed 3 bit delta. A delta that overflows
+    // one of the channels selects the ETC2 T, H or planar mode instead.
+    let base = [field(59, 5), field(51, 5), field(43, 5)];
+    let delta = [field(56, 3), field(48, 3), field(40, 3)].map(|d| d << 29 >> 29);
+    let second = [0, 1, 2].map(|i| base[i] + delta[i]);
+    let overflows = |i: usize| !(0..32).contains(&second[i]);
+
+    if overflows(0) {
+        let c0 = [field(59, 2) << 2 | field(56, 2), field(52, 4), field(48, 4)].map(extend4);
+        let c1 = [field(44, 4), field(40, 4), field(36, 4)].map(extend4);
+        let d = ETC_DISTANCES[(field(34, 2) << 1 | field(32, 1)) as usize];
+        paint([c0, offset(c1, d), c1, offset(c1, -d)])
+    } else if overflows(1) {
+        let c0 = [
+            field(59, 4),
+            field(56, 3) << 1 | field(52, 1),
+            field(51, 1) << 3 | field(47, 3),
+        ];
+        let c1 = [field(43, 4), field(39, 4), field(35, 4)];
+        let ordering = (c0[0] << 8 | c0[1] << 4 | c0[2]) >= (c1[0] << 8 | c1[1] << 4 | c1[2]);
+        let d =
+            ETC_DISTANCES[(field(34, 1) << 2 | field(32, 1) << 1 | i32::from(ordering)) as usize];
+        let (c0, c1) = (c0.map(extend4), c1.map(extend4));
+        paint([offset(c0, d), offset(c0, -d), offset(c1, d), offset(c1, -d)])
+    } else if overflows(2) {
+        // Planar mode, a gradient from three 6/7/6 bit colors
+        let extend6 = |c: i32| c << 2 | c >> 4;
+        let extend7 = |c: i32| c << 1 | c >> 6;
+        let expand = |[r, g, b]: [i32; 3]| [extend6(r), extend7(g), extend6(b)];
+        let o = expand([
+            field(57, 6),
+            field(56, 1) << 6 | field(49, 6),
+            field(48, 1) << 5 | field(43, 2) << 3 | field(39, 3),
+        ]);
+        let h = expand([field(34, 5) << 1 | field(32, 1), field(25, 7), field(19, 6)]);
+        let v = expand([field(13, 6), field(6, 7), field(0, 6)]);
+        std::array::from_fn(|t| {
+            let (x, y) = ((t % 4) as i32, (t / 4) as i32);
+            clamp_rgb(
+                [0, 1, 2].map(|i| (x * (h[i] - o[i]) + y * (v[i] - o[i]) + 4 * o[i] + 2) >> 2),
+            )
+        })
+    } else {
+        subblocks(
+            [base.map(extend5), second.map(extend5)],
+            [field(37, 3), field(34, 3)],
+        )
+    }
+}
+
+fn etc2_rgba(block: &[u8]) -> Block {
+    let bits = u64::from_be_bytes(block[..8].try_into().expect("16 byte block"));
+    let base = (bits >> 56) as i32;
+    let multiplier = (bits >> 52 & 15) as i32;
+    let table = EAC_MODIFIERS[(bits >> 48 & 15) as usize];
+
+    let mut texels = etc2_rgb(&block[8..]);
+    for (t, texel) in texels.iter_mut().enumerate() {
+        let index = (bits >> (45 - 3 * etc_texel(t)) & 7) as usize;
+        texel[3] = (base + table[index] * multiplier).clamp(0, 255) as u8;
+    }
+    texels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(c: u8) -> [u8; 4] {
+        [c, c, c, 255]
+    }
+
+    /// A block with the given colors at ETC texel numbers and `rest` everywhere else
+    fn etc_block(texels: &[(usize, [u8; 4])], rest: [u8; 4]) -> Block {
+        std::array::from_fn(|t| {
+            let p = etc_texel(t);
+            texels
+                .iter()
+                .find(|(i, _)| *i == p)
+                .map_or(rest, |(_, c)| *c)
+        })
+    }
+
+    #[test]
+    fn solid_bc1_block() {
+        let block = [0x00, 0xf8, 0x00, 0x00, 0, 0, 0, 0];
+        let rgba = decode(wgpu::TextureFormat::Bc1RgbaUnorm, &block, 4, 4).unwrap();
+        assert_eq!(rgba, [255, 0, 0, 255].repeat(16));
+    }
+
+    #[test]
+    fn bc1_punch_through_alpha() {
+        // c0 black <= c1 white, texels 0-3 pick palette entries 0-3
+        let block = [0x00, 0x00, 0xff, 0xff, 0b11_10_01_00, 0, 0, 0];
+        let texels = bc1_color(&block, true);
+        assert_eq!(texels[..4], [gray(0), gray(255), gray(127), [0, 0, 0, 0]]);
+        assert!(texels[4..].iter().all(|&t| t == gray(0)));
+
+        // The color half of BC2/3 never turns transparent
+        assert_eq!(bc1_color(&block, false)[3], gray(170));
+    }
+
+    #[test]
+    fn etc2_individual_mode() {
+        // 4 bit colors 8 and 4, tables 0 and 1, texel 0 picks modifier index 2
+        let bits: u64 =
+            8 << 60 | 4 << 56 | 8 << 52 | 4 << 48 | 8 << 44 | 4 << 40 | 1 << 34 | 1 << 16;
+        let texels = etc2_rgb(&bits.to_be_bytes());
+        for (t, texel) in texels.iter().enumerate() {
+            let expected = match t {
+                0 => gray(136 - 2),
+                _ if t % 4 < 2 => gray(136 + 2),
+                _ => gray(68 + 5),
+            };
+            assert_eq!(*texel, expected, "texel {t}");
+        }
+    }
+
+    #[test]
+    fn etc2_differential_mode() {
+        // Base 16 with deltas +1, +1, -1, flipped into top and bottom halves
+        let bits: u64 =
+            16 << 59 | 1 << 56 | 16 << 51 | 1 << 48 | 16 << 43 | 0b111 << 40 | 1 << 33 | 1 << 32;
+        let texels = etc2_rgb(&bits.to_be_bytes());
+        for (t, texel) in texels.iter().enumerate() {
+            let expected = if t < 8 {
+                gray(132 + 2)
+            } else {
+                [142, 142, 125, 255]
+            };
+            assert_eq!(*texel, expected, "texel {t}");
+        }
+    }
+
+    #[test]
+    fn etc2_t_mode() {
+        // Red 30 + 2 overflows. c0 = (10, 0, 0), c1 = (8, 8, 8), distance 3
+        let bits: u64 = 0b111 << 61
+            | 0b10 << 59
+            | 0b10 << 56
+            | 8 << 44
+            | 8 << 40
+            | 8 << 36
+            | 1 << 33
+            | 1 << 1
+            | 1 << 20
+            | 1 << 4;
+        let texels = etc2_rgb(&bits.to_be_bytes());
+        let expected = etc_block(&[(1, gray(136 + 3)), (4, gray(136 - 3))], [170, 0, 0, 255]);
+        assert_eq!(texels, expected);
+    }
+
+    #[test]
+    fn etc2_h_mode() {
+        // Green 0 - 4 overflows. c0 = (8, 4, 0) >= c1 = (4, 4, 4) selects distance 6
+        let bits: u64 = 8 << 59
+            | 0b010 << 56
+            | 1 << 50
+            | 4 << 43
+            | 4 << 39
+            | 4 << 35
+            | 1 << 33
+            | 1 << 4
+            | 1 << 24
+            | 1 << 28
+            | 1 << 12;
+        let texels = etc2_rgb(&bits.to_be_bytes());
+        let expected = etc_block(
+            &[
+                (4, [130, 62, 0, 255]),
+                (8, gray(68 + 6)),
+                (12, gray(68 - 6)),
+            ],
+            [142, 74, 6, 255],
+        );
+        assert_eq!(texels, expected);
+    }
+
+    #[test]
+    fn etc2_planar_mode() {
+        // Blue 0 - 4 overflows. Red ramps up horizontally and green vertically
+        let bits: u64 = 1 << 42 | 0b11111 << 34 | 1 << 33 | 1 << 32 | 0x7f << 6;
+        let texels = etc2_rgb(&bits.to_be_bytes());
+        let ramp = [0, 64, 128, 191];
+        for (t, texel) in texels.iter().enumerate() {
+            assert_eq!(*texel, [ramp[t % 4], ramp[t / 4], 0, 255], "texel {t}");
+        }
+    }
+
+    #[test]
+    fn eac_alpha_block() {
+        // Base 250, multiplier 10, table 0. Texel 0 clamps at 255
+        let alpha: u64 = 250 << 56 | 10 << 52 | 7 << 45 | 3 << 42;
+        let mut block = [0; 16];
+        block[..8].copy_from_slice(&alpha.to_be_bytes());
+        let texels = etc2_rgba(&block);
+
+        let color = |a| [2, 2, 2, a];
+        let expected = etc_block(&[(0, color(255)), (1, color(250 - 150))], color(250 - 30));
+        assert_eq!(texels, expected);
+    }
+}
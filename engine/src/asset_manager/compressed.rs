@@ -0,0 +1,266 @@
+// KTX2 and DDS containers. Their mip chains are uploaded as stored when the device supports
+// the format, otherwise decoded on the CPU (see `block_decode`).
+use anyhow::{Context, bail};
+
+use crate::asset_manager::block_decode;
+
+const KTX2_MAGIC: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const DDS_MAGIC: [u8; 4] = *b"DDS ";
+
+/// A 2D texture in a GPU format with its pre-built mip levels
+#[derive(Clone, Debug)]
+pub struct MipChain {
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Largest level first, rows of blocks tightly packed
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl MipChain {
+    /// `None` when `bytes` is neither a KTX2 nor a DDS file
+    pub fn from_container(bytes: &[u8]) -> Option<anyhow::Result<Self>> {
+        if bytes.starts_with(&KTX2_MAGIC) {
+            Some(Self::from_ktx2(bytes).context("Reading KTX2"))
+        } else if bytes.starts_with(&DDS_MAGIC) {
+            Some(Self::from_dds(bytes).context("Reading DDS"))
+        } else {
+            None
+        }
+    }
+
+    pub fn from_ktx2(bytes: &[u8]) -> anyhow::Result<Self> {
+        let reader = ktx2::Reader::new(bytes).map_err(|e| anyhow::anyhow!("{e:?}"))?;
+        let header = reader.header();
+        if let Some(scheme) = header.supercompression_scheme {
+            bail!("Supercompression {scheme:?} is not supported, re-encode without it");
+        }
+        if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+            bail!("Only 2D textures are supported");
+        }
+        let format = header
+            .format
+            .context("Basis Universal textures are not supported")?;
+        let format =
+            ktx2_format(format).with_context(|| format!("Unsupported format {format:?}"))?;
+
+        let chain = Self {
+            format,
+            width: header.pixel_width,
+            height: header.pixel_height.max(1),
+            levels: reader.levels().map(|level| level.data.to_vec()).collect(),
+        };
+        chain.validate()?;
+        Ok(chain)
+    }
+
+    pub fn from_dds(bytes: &[u8]) -> anyhow::Result<Self> {
+        let dds = ddsfile::Dds::read(bytes)?;
+        if dds.get_depth() > 1 || dds.get_num_array_layers() > 1 {
+            bail!("Only 2D textures are supported");
+        }
+        let dxgi = dds
+            .get_dxgi_format()
+            .context("Only DXT1-5, ATI1/2 and DX10 headers are supported")?;
+        let format = dds_format(dxgi).with_context(|| format!("Unsupported format {dxgi:?}"))?;
+
+        let mut chain = Self {
+            format,
+            width: dds.get_width(),
+            height: dds.get_height(),
+            levels: Vec::new(),
+        };
+        // The levels follow each other in the first (and only) layer
+        let mut data = dds.get_data(0)?;
+        for level in 0..dds.get_num_mipmap_levels().max(1) {
+            let size = chain.level_size(level);
+            if data.len() < size {
+                bail!("Level {level} is truncated");
+            }
+            let (level, rest) = data.split_at(size);
+            chain.levels.push(level.to_vec());
+            data = rest;
+        }
+        Ok(chain)
+    }
+
+    /// Bytes of mip `level`, padded to whole blocks
+    pub fn level_size(&self, level: u32) -> usize {
+        let (block_width, block_height) = self.format.block_dimensions();
+        let block_size = self.format.block_copy_size(None).unwrap_or(4) as usize;
+        let width = (self.width >> level).max(1).div_ceil(block_width) as usize;
+        let height = (self.height >> level).max(1).div_ceil(block_height) as usize;
+        width * height * block_size
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.levels.is_empty() {
+            bail!("No mip levels");
+        }
+        for (level, data) in self.levels.iter().enumerate() {
+            let expected = self.level_size(level as u32);
+            if data.len() < expected {
+                bail!("Level {level} is {} bytes, expected {expected}", data.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// The same chain as sRGB or linear data. Containers rarely say what a texture is used
+    /// for, the material slot decides. Formats without an sRGB variant stay as they are.
+    pub fn with_color_space(mut self, srgb: bool) -> Self {
+        self.format = if srgb {
+            self.format.add_srgb_suffix()
+        } else {
+            self.format.remove_srgb_suffix()
+        };
+        self
+    }
+
+    /// The device can filter the format and the size fits its blocks (wgpu requires whole
+    /// blocks for the top level)
+    pub fn is_supported(&self, features: wgpu::Features) -> bool {
+        let (block_width, block_height) = self.format.block_dimensions();
+        features.contains(self.format.required_features())
+            && self
+                .format
+                .guaranteed_format_features(features)
+                .flags
+                .contains(wgpu::TextureFormatFeatureFlags::FILTERABLE)
+            && self.width.is_multiple_of(block_width)
+            && self.height.is_multiple_of(block_height)
+    }
+
+    /// Every level decoded to RGBA8 with the same color space, for devices without the
+    /// format.
+    pub fn decompress(&self) -> anyhow::Result<Self> {
+        let levels = self
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(level, data)| {
+                let width = (self.width >> level).max(1);
+                let height = (self.height >> level).max(1);
+                block_decode::decode(self.format, data, width, height)
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let format = if self.format.is_srgb() {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        };
+
+        Ok(Self {
+            format,
+            width: self.width,
+            height: self.height,
+            levels,
+        })
+    }
+}
+
+fn ktx2_format(format: ktx2::Format) -> Option<wgpu::TextureFormat> {
+    use ktx2::Format as K;
+    use wgpu::TextureFormat as F;
+
+    Some(match format {
+        K::R8G8B8A8_UNORM => F::Rgba8Unorm,
+        K::R8G8B8A8_SRGB => F::Rgba8UnormSrgb,
+        K::R16G16B16A16_SFLOAT => F::Rgba16Float,
+        K::R32G32B32A32_SFLOAT => F::Rgba32Float,
+        // BC1 has no separate RGB format in wgpu, the alpha of RGB blocks is 1 anyway
+        K::BC1_RGB_UNORM_BLOCK | K::BC1_RGBA_UNORM_BLOCK => F::Bc1RgbaUnorm,
+        K::BC1_RGB_SRGB_BLOCK | K::BC1_RGBA_SRGB_BLOCK => F::Bc1RgbaUnormSrgb,
+        K::BC2_UNORM_BLOCK => F::Bc2RgbaUnorm,
+        K::BC2_SRGB_BLOCK => F::Bc2RgbaUnormSrgb,
+        K::BC3_UNORM_BLOCK => F::Bc3RgbaUnorm,
+        K::BC3_SRGB_BLOCK => F::Bc3RgbaUnormSrgb,
+        K::BC4_UNORM_BLOCK => F::Bc4RUnorm,
+        K::BC4_SNORM_BLOCK => F::Bc4RSnorm,
+        K::BC5_UNORM_BLOCK => F::Bc5RgUnorm,
+        K::BC5_SNORM_BLOCK => F::Bc5RgSnorm,
+        K::BC6H_UFLOAT_BLOCK => F::Bc6hRgbUfloat,
+        K::BC6H_SFLOAT_BLOCK => F::Bc6hRgbFloat,
+        K::BC7_UNORM_BLOCK => F::Bc7RgbaUnorm,
+        K::BC7_SRGB_BLOCK => F::Bc7RgbaUnormSrgb,
+        K::ETC2_R8G8B8_UNORM_BLOCK => F::Etc2Rgb8Unorm,
+        K::ETC2_R8G8B8_SRGB_BLOCK => F::Etc2Rgb8UnormSrgb,
+        K::ETC2_R8G8B8A1_UNORM_BLOCK => F::Etc2Rgb8A1Unorm,
+        K::ETC2_R8G8B8A1_SRGB_BLOCK => F::Etc2Rgb8A1UnormSrgb,
+        K::ETC2_R8G8B8A8_UNORM_BLOCK => F::Etc2Rgba8Unorm,
+        K::ETC2_R8G8B8A8_SRGB_BLOCK => F::Etc2Rgba8UnormSrgb,
+        K::EAC_R11_UNORM_BLOCK => F::EacR11Unorm,
+        K::EAC_R11_SNORM_BLOCK => F::EacR11Snorm,
+        K::EAC_R11G11_UNORM_BLOCK => F::EacRg11Unorm,
+        K::EAC_R11G11_SNORM_BLOCK => F::EacRg11Snorm,
+        other => return astc_format(other.value()),
+    })
+}
+
+/// Vulkan numbers the ASTC formats in block size order, unorm and sRGB interleaved
+fn astc_format(value: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::{AstcBlock as B, AstcChannel};
+
+    const BLOCKS: [B; 14] = [
+        B::B4x4,
+        B::B5x4,
+        B::B5x5,
+        B::B6x5,
+        B::B6x6,
+        B::B8x5,
+        B::B8x6,
+        B::B8x8,
+        B::B10x5,
+        B::B10x6,
+        B::B10x8,
+        B::B10x10,
+        B::B12x10,
+        B::B12x12,
+    ];
+    const LDR_FIRST: u32 = 157;
+    const HDR_FIRST: u32 = 1_000_066_000;
+
+    let (block, channel) = match value {
+        LDR_FIRST..=184 => {
+            let offset = value - LDR_FIRST;
+            let channel = if offset.is_multiple_of(2) {
+                AstcChannel::Unorm
+            } else {
+                AstcChannel::UnormSrgb
+            };
+            (BLOCKS[offset as usize / 2], channel)
+        }
+        HDR_FIRST..=1_000_066_013 => (BLOCKS[(value - HDR_FIRST) as usize], AstcChannel::Hdr),
+        _ => return None,
+    };
+    Some(wgpu::TextureFormat::Astc { block, channel })
+}
+
+fn dds_format(format: ddsfile::DxgiFormat) -> Option<wgpu::TextureFormat> {
+    use ddsfile::DxgiFormat as D;
+    use wgpu::TextureFormat as F;
+
+    Some(match format {
+        D::R8G8B8A8_UNorm => F::Rgba8Unorm,
+        D::R8G8B8A8_UNorm_sRGB => F::Rgba8UnormSrgb,
+        D::R16G16B16A16_Float => F::Rgba16Float,
+        D::R32G32B32A32_Float => F::Rgba32Float,
+        D::BC1_Typeless | D::BC1_UNorm => F::Bc1RgbaUnorm,
+        D::BC1_UNorm_sRGB => F::Bc1RgbaUnormSrgb,
+        D::BC2_Typeless | D::BC2_UNorm => F::Bc2RgbaUnorm,
+        D::BC2_UNorm_sRGB => F::Bc2RgbaUnormSrgb,
+        D::BC3_Typeless | D::BC3_UNorm => F::Bc3RgbaUnorm,
+        D::BC3_UNorm_sRGB => F::Bc3RgbaUnormSrgb,
+        D::BC4_Typeless | D::BC4_UNorm => F::Bc4RUnorm,
+        D::BC4_SNorm => F::Bc4RSnorm,
+        D::BC5_Typeless | D::BC5_UNorm => F::Bc5RgUnorm,
+        D::BC5_SNorm => F::Bc5RgSnorm,
+        D::BC6H_Typeless | D::BC6H_UF16 => F::Bc6hRgbUfloat,
+        D::BC6H_SF16 => F::Bc6hRgbFloat,
+        D::BC7_Typeless | D::BC7_UNorm => F::Bc7RgbaUnorm,
+        D::BC7_UNorm_sRGB => F::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
@@ -0,0 +1,88 @@
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use crate::{
+    asset_manager::{handle::Handle, texture_cache::TextureCache},
+    gpu::context::GpuContext,
+    texture::Texture,
+};
+
+const CHECKER_SIZE: u32 = 64;
+const CHECKER_CELL: u32 = 8;
+
+/// Built-in textures bound in place of absent or broken ones. They live as long as the
+/// `AssetManager` and are never released with a model.
+pub struct FallbackTextures {
+    /// Untextured materials, and maps that multiply (occlusion, metallic-roughness)
+    pub white: Handle<Texture>,
+    /// Tangent space (0, 0, 1), for materials without a normal map
+    pub flat_normal: Handle<Texture>,
+    /// Magenta/black checkerboard, for files that are missing or fail to decode
+    pub missing: Handle<Texture>,
+}
+
+impl FallbackTextures {
+    pub(crate) fn new(
+        gpu_context: &GpuContext,
+        textures: &mut TextureCache,
+    ) -> anyhow::Result<Self> {
+        let mut upload = |label: &str, image: RgbaImage, format, sampler| {
+            Texture::from_image_with(
+                &gpu_context.device,
+                &gpu_context.queue,
+                &DynamicImage::ImageRgba8(image),
+                Some(label),
+                format,
+                &sampler,
+            )
+            .map(|texture| {
+                // Held by the AssetManager itself, so releasing a model never frees it
+                let handle = textures.insert(texture);
+                textures.acquire(handle);
+                handle
+            })
+        };
+
+        let white = upload(
+            "fallback white",
+            RgbaImage::from_pixel(1, 1, Rgba([255; 4])),
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            Texture::default_sampler(),
+        )?;
+        let flat_normal = upload(
+            "fallback flat normal",
+            RgbaImage::from_pixel(1, 1, Rgba([128, 128, 255, 255])),
+            wgpu::TextureFormat::Rgba8Unorm,
+            Texture::default_sampler(),
+        )?;
+        let checker = RgbaImage::from_fn(CHECKER_SIZE, CHECKER_SIZE, |x, y| {
+            if (x / CHECKER_CELL + y / CHECKER_CELL).is_multiple_of(2) {
+                Rgba([255, 0, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        // Repeat with sharp edges so the checkerboard stays obvious on any UV layout
+        let missing = upload(
+            "fallback missing",
+            checker,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::Repeat,
+                address_mode_v: wgpu::AddressMode::Repeat,
+                mag_filter: wgpu::FilterMode::Nearest,
+                min_filter: wgpu::FilterMode::Nearest,
+                ..Texture::default_sampler()
+            },
+        )?;
+
+        Ok(Self {
+            white,
+            flat_normal,
+            missing,
+        })
+    }
+
+    pub fn contains(&self, handle: Handle<Texture>) -> bool {
+        [self.white, self.flat_normal, self.missing].contains(&handle)
+    }
+}
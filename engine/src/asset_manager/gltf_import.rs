@@ -0,0 +1,814 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, bail};
+use base64::Engine as _;
+use bevy_ecs::{entity::Entity, hierarchy::ChildOf, world::World};
+use cgmath::{Matrix4, SquareMatrix as _};
+use gltf::{
+    mesh::Mode,
+    texture::{MagFilter, MinFilter, WrappingMode},
+};
+
+use crate::{
+    animation::{
+        clip::{AnimationClip, Channel, ChannelValues, Interpolation},
+        player::AnimationPlayer,
+        skeleton::{Joint, Skeleton},
+    },
+    asset_manager::{
+        handle::Handle,
+        import::{
+            ImportError, ImportedMaterial, ImportedMesh, ImportedMorphTarget, ImportedTexture,
+            TextureData,
+        },
+        io::Io,
+    },
+    components::{
+        JointMatrices, MeshHandle, MorphWeights, Name, SkinnedMesh, SourceNode, Transform,
+    },
+    model::{AlphaMode, Bounds, MaterialParams, Model, ModelVertex, RenderState, SkinVertex},
+};
+
+/// One node of the glTF hierarchy. `mesh` indexes the meshes of the same file.
+#[derive(Clone, Debug)]
+pub struct GltfNode {
+    pub name: String,
+    /// Relative to the parent node
+    pub transform: Transform,
+    pub mesh: Option<usize>,
+    /// Index into the skeletons of the same file
+    pub skin: Option<usize>,
+    /// Initial morph target weights, empty when the mesh has no targets
+    pub morph_weights: Vec<f32>,
+    pub children: Vec<usize>,
+}
+
+/// Decoded glTF file, before anything is on the GPU.
+/// Materials and textures are shared by all meshes of the file.
+pub struct ImportedScene {
+    pub name: String,
+    pub textures: Vec<ImportedTexture>,
+    pub materials: Vec<ImportedMaterial>,
+    /// One entry per glTF mesh, its primitives become [`ImportedMesh`]es
+    pub meshes: Vec<(String, Vec<ImportedMesh>)>,
+    pub skeletons: Vec<Skeleton>,
+    pub animations: Vec<AnimationClip>,
+    pub nodes: Vec<GltfNode>,
+    pub roots: Vec<usize>,
+    /// External buffers and images, relative to the assets folder
+    pub dependencies: Vec<PathBuf>,
+}
+
+/// Uploaded glTF file, every glTF mesh is a [`Model`] in the `AssetManager`.
+pub struct GltfScene {
+    pub name: String,
+    pub models: Vec<Handle<Model>>,
+    pub skeletons: Vec<Handle<Skeleton>>,
+    pub animations: Vec<Handle<AnimationClip>>,
+    pub nodes: Vec<GltfNode>,
+    pub roots: Vec<usize>,
+}
+
+impl GltfScene {
+    /// Spawns one entity per node under a new root entity placed at `root`.
+    /// Parents are linked with `ChildOf`, transforms are baked to world space at spawn time.
+    /// Skinned and morphed nodes get an empty [`AnimationPlayer`], play one of
+    /// [`Self::animations`] on it. Every entity keeps its node index in [`SourceNode`].
+    pub fn spawn(&self, world: &mut World, root: Transform) -> Entity {
+        let root_matrix = root.matrix();
+        let root_entity = world.spawn((root, Name(self.name.clone()))).id();
+
+        let mut stack: Vec<(usize, Entity, Matrix4<f32>)> = self
+            .roots
+            .iter()
+            .map(|&node| (node, root_entity, root_matrix))
+            .collect();
+        while let Some((index, parent, parent_matrix)) = stack.pop() {
+            let node = &self.nodes[index];
+            let world_matrix = parent_matrix * node.transform.matrix();
+            let skeleton = node.skin.and_then(|skin| self.skeletons.get(skin));
+            // The skeleton places a skinned mesh by itself, its own node transform is ignored
+            let transform = match skeleton {
+                Some(_) => root,
+                None => Transform::from_matrix(world_matrix),
+            };
+
+            let mut entity = world.spawn((
+                transform,
+                Name(node.name.clone()),
+                SourceNode(index),
+                ChildOf(parent),
+            ));
+            if let Some(model) = node.mesh.and_then(|mesh| self.models.get(mesh)) {
+                entity.insert(MeshHandle(*model));
+            }
+            if let Some(&skeleton) = skeleton {
+                entity.insert((
+                    SkinnedMesh { skeleton },
+                    JointMatrices::default(),
+                    AnimationPlayer::default(),
+                ));
+            }
+            if !node.morph_weights.is_empty() {
+                entity.insert(MorphWeights(node.morph_weights.clone()));
+                if !entity.contains::<AnimationPlayer>() {
+                    entity.insert(AnimationPlayer::default());
+                }
+            }
+
+            let id = entity.id();
+            stack.extend(node.children.iter().map(|&child| (child, id, world_matrix)));
+        }
+
+        root_entity
+    }
+}
+
+pub struct GltfLoader;
+
+impl GltfLoader {
+    /// Reads `.gltf` (with embedded or external buffers and images) and `.glb` files.
+    pub async fn load_scene(path: &Path) -> anyhow::Result<ImportedScene> {
+        let parent_path = path.parent().unwrap_or(Path::new(""));
+        let name = path
+            .file_name()
+            .context("NO valid path found")?
+            .to_string_lossy()
+            .to_string();
+
+        let bytes = Io::load_binary(path).await?;
+        let gltf::Gltf { document, mut blob } =
+            gltf::Gltf::from_slice(&bytes).with_context(|| format!("Parsing {name}"))?;
+
+        let mut dependencies = Vec::new();
+        let mut buffers = Vec::new();
+        for buffer in document.buffers() {
+            let data = match buffer.source() {
+                gltf::buffer::Source::Bin => blob.take().context("GLB has no binary chunk")?,
+                gltf::buffer::Source::Uri(uri) => {
+                    Self::load_uri(parent_path, uri, &mut dependencies).await?
+                }
+            };
+            if data.len() < buffer.length() {
+                bail!(
+                    "{name}: buffer {} is {} bytes, expected {}",
+                    buffer.index(),
+                    data.len(),
+                    buffer.length()
+                );
+            }
+            buffers.push(data);
+        }
+
+        let mut images = Vec::new();
+        // External image files, shared with other models through the texture cache
+        let mut image_sources = Vec::new();
+        for image in document.images() {
+            image_sources.push(match image.source() {
+                gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+                    Some(parent_path.join(percent_decode(uri)))
+                }
+                _ => None,
+            });
+            let data = match image.source() {
+                gltf::image::Source::View { view, .. } => {
+                    let start = view.offset();
+                    let bytes = buffers
+                        .get(view.buffer().index())
+                        .and_then(|buffer| buffer.get(start..start.checked_add(view.length())?));
+                    // Out of range views break the whole file, not only this image
+                    let Some(bytes) = bytes else {
+                        return Err(ImportError::new(
+                            path,
+                            None,
+                            format!(
+                                "image {} reads past the end of buffer {}",
+                                image.index(),
+                                view.buffer().index()
+                            ),
+                        )
+                        .into());
+                    };
+                    Ok(bytes.to_vec())
+                }
+                gltf::image::Source::Uri { uri, .. } => {
+                    Self::load_uri(parent_path, uri, &mut dependencies).await
+                }
+            };
+            // A broken image only costs its textures, they get the missing-texture fallback
+            let decoded = data
+                .and_then(|data| TextureData::decode(&data))
+                .with_context(|| format!("{name}: image {}", image.index()));
+            images.push(match decoded {
+                Ok(image) => Some(image),
+                Err(e) => {
+                    tracing::warn!("Using the missing-texture fallback: {e:#}");
+                    None
+                }
+            });
+        }
+
+        // A glTF texture can be sampled as color and as data, each use gets its own upload
+        let mut textures = Vec::new();
+        let mut texture_slots: HashMap<(usize, bool), usize> = HashMap::new();
+        let mut texture_slot = |texture: gltf::Texture, srgb: bool| -> usize {
+            *texture_slots
+                .entry((texture.index(), srgb))
+                .or_insert_with(|| {
+                    textures.push(ImportedTexture {
+                        label: texture
+                            .name()
+                            .map(str::to_owned)
+                            .unwrap_or_else(|| format!("{name} texture {}", texture.index())),
+                        data: images[texture.source().index()].clone(),
+                        source: image_sources[texture.source().index()].clone(),
+                        srgb,
+                        float_format: wgpu::TextureFormat::Rgba16Float,
+                        sampler: Self::sampler(texture.sampler()),
+                    });
+                    textures.len() - 1
+                })
+        };
+
+        let mut materials: Vec<ImportedMaterial> = document
+            .materials()
+            .map(|m| {
+                let pbr = m.pbr_metallic_roughness();
+                let alpha_mode = match m.alpha_mode() {
+                    gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                    gltf::material::AlphaMode::Mask => {
+                        AlphaMode::Mask(m.alpha_cutoff().unwrap_or(0.5))
+                    }
+                    gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+                };
+                let params = MaterialParams {
+                    base_color: pbr.base_color_factor(),
+                    metallic: pbr.metallic_factor(),
+                    roughness: pbr.roughness_factor(),
+                    emissive: m.emissive_factor(),
+                    normal_scale: m.normal_texture().map_or(1.0, |t| t.scale()),
+                    occlusion_strength: m.occlusion_texture().map_or(1.0, |t| t.strength()),
+                    alpha_mode,
+                    double_sided: m.double_sided(),
+                    ..Default::default()
+                };
+                ImportedMaterial {
+                    name: m
+                        .name()
+                        .map(str::to_owned)
+                        .unwrap_or_else(|| format!("{name} material {}", m.index().unwrap_or(0))),
+                    base_color_texture: pbr
+                        .base_color_texture()
+                        .map(|info| texture_slot(info.texture(), true)),
+                    metallic_roughness_texture: pbr
+                        .metallic_roughness_texture()
+                        .map(|info| texture_slot(info.texture(), false)),
+                    normal_texture: m
+                        .normal_texture()
+                        .map(|info| texture_slot(info.texture(), false)),
+                    occlusion_texture: m
+                        .occlusion_texture()
+                        .map(|info| texture_slot(info.texture(), false)),
+                    emissive_texture: m
+                        .emissive_texture()
+                        .map(|info| texture_slot(info.texture(), true)),
+                    render_state: RenderState::from_params(&params),
+                    params,
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        // Primitives without a material use the spec default, appended on first use
+        let mut default_material = None;
+        let mut meshes = Vec::new();
+        for mesh in document.meshes() {
+            let mesh_name = mesh
+                .name()
+                .map(str::to_owned)
+                .unwrap_or_else(|| format!("{name} mesh {}", mesh.index()));
+
+            let mut primitives = Vec::new();
+            for primitive in mesh.primitives() {
+                let label = format!("{mesh_name} primitive {}", primitive.index());
+                let material = match primitive.material().index() {
+                    Some(index) => index,
+                    None => *default_material.get_or_insert_with(|| {
+                        materials.push(ImportedMaterial {
+                            name: format!("{name} default material"),
+                            params: MaterialParams {
+                                metallic: 1.0,
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        });
+                        materials.len() - 1
+                    }),
+                };
+                match Self::read_primitive(&primitive, &buffers, label, material)? {
+                    Some(imported) => primitives.push(imported),
+                    None => tracing::warn!(
+                        "{name}: skipping {mesh_name} primitive {}, {:?} is not supported",
+                        primitive.index(),
+                        primitive.mode()
+                    ),
+                }
+            }
+            meshes.push((mesh_name, primitives));
+        }
+
+        let nodes = document
+            .nodes()
+            .map(|node| {
+                let (translation, rotation, scale) = node.transform().decomposed();
+                GltfNode {
+                    name: node
+                        .name()
+                        .map(str::to_owned)
+                        .unwrap_or_else(|| format!("node {}", node.index())),
+                    transform: Transform {
+                        position: translation.into(),
+                        // glTF stores quaternions as xyzw
+                        rotation: cgmath::Quaternion::new(
+                            rotation[3],
+                            rotation[0],
+                            rotation[1],
+                            rotation[2],
+                        ),
+                        scale: scale.into(),
+                    },
+                    mesh: node.mesh().map(|mesh| mesh.index()),
+                    skin: node.skin().map(|skin| skin.index()),
+                    morph_weights: Self::morph_weights(&node),
+                    children: node.children().map(|child| child.index()).collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut parents = vec![None; nodes.len()];
+        for (index, node) in nodes.iter().enumerate() {
+            for &child in &node.children {
+                parents[child] = Some(index);
+            }
+        }
+
+        let skeletons = document
+            .skins()
+            .map(|skin| Self::read_skin(&skin, &buffers, &nodes, &parents))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .with_context(|| format!("{name}: reading skins"))?;
+        let animations = document
+            .animations()
+            .map(|animation| Self::read_animation(path, &animation, &buffers))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let roots = match document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+        {
+            Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+            // No scene declared: every node without a parent is a root
+            None => (0..nodes.len()).filter(|&i| parents[i].is_none()).collect(),
+        };
+
+        Ok(ImportedScene {
+            name,
+            textures,
+            materials,
+            meshes,
+            skeletons,
+            animations,
+            nodes,
+            roots,
+            dependencies,
+        })
+    }
+
+    /// Node weights override the mesh ones, both are optional and default to zero.
+    fn morph_weights(node: &gltf::Node) -> Vec<f32> {
+        let Some(mesh) = node.mesh() else {
+            return Vec::new();
+        };
+        let targets = mesh
+            .primitives()
+            .map(|primitive| primitive.morph_targets().len())
+            .max()
+            .unwrap_or(0);
+        let mut weights = node
+            .weights()
+            .or_else(|| mesh.weights())
+            .map(<[f32]>::to_vec)
+            .unwrap_or_default();
+        weights.resize(targets, 0.0);
+        weights
+    }
+
+    /// `None` for point and line primitives.
+    fn read_primitive(
+        primitive: &gltf::Primitive,
+        buffers: &[Vec<u8>],
+        name: String,
+        material: usize,
+    ) -> anyhow::Result<Option<ImportedMesh>> {
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+
+        let positions: Vec<[f32; 3]> = reader
+            .read_positions()
+            .with_context(|| format!("{name} has no POSITION attribute"))?
+            .collect();
+        let normals: Vec<[f32; 3]> = reader
+            .read_normals()
+            .map(|normals| normals.collect())
+            .unwrap_or_default();
+        let tex_coords: Vec<[f32; 2]> = reader
+            .read_tex_coords(0)
+            .map(|uv| uv.into_f32().collect())
+            .unwrap_or_default();
+        // Already linear in glTF
+        let colors: Vec<[f32; 4]> = reader
+            .read_colors(0)
+            .map(|colors| colors.into_rgba_f32().collect())
+            .unwrap_or_default();
+
+        let joints: Option<Vec<[u16; 4]>> = reader
+            .read_joints(0)
+            .map(|joints| joints.into_u16().collect());
+        let weights: Option<Vec<[f32; 4]>> = reader
+            .read_weights(0)
+            .map(|weights| weights.into_f32().collect());
+        let skin = joints.zip(weights).map(|(joints, weights)| {
+            joints
+                .into_iter()
+                .zip(weights)
+                .map(|(joints, weights)| {
+                    // Exporters don't always normalize, the shader expects a sum of one
+                    let sum: f32 = weights.iter().sum();
+                    SkinVertex {
+                        joints,
+                        weights: if sum > 0.0 {
+                            weights.map(|w| w / sum)
+                        } else {
+                            [1.0, 0.0, 0.0, 0.0]
+                        },
+                    }
+                })
+                .collect()
+        });
+
+        // Tangent deltas are dropped, the vertex format has no tangents
+        let morph_targets = reader
+            .read_morph_targets()
+            .map(|(positions, normals, _)| ImportedMorphTarget {
+                positions: positions.map(Iterator::collect).unwrap_or_default(),
+                normals: normals.map(Iterator::collect).unwrap_or_default(),
+            })
+            .collect();
+
+        let vertices: Vec<ModelVertex> = positions
+            .iter()
+            .enumerate()
+            .map(|(i, &position)| ModelVertex {
+                position,
+                // glTF UVs already have their origin in the top left corner
+                tex_coords: tex_coords.get(i).copied().unwrap_or([0.0, 0.0]),
+                normal: normals.get(i).copied().unwrap_or([0.0, 0.0, 0.0]),
+                color: colors.get(i).copied().unwrap_or(ModelVertex::NO_COLOR),
+            })
+            .collect();
+
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        let indices = match primitive.mode() {
+            Mode::Triangles => indices,
+            Mode::TriangleStrip => (0..indices.len().saturating_sub(2))
+                .flat_map(|i| {
+                    // Every other triangle is flipped to keep the winding
+                    if i % 2 == 0 {
+                        [indices[i], indices[i + 1], indices[i + 2]]
+                    } else {
+                        [indices[i + 1], indices[i], indices[i + 2]]
+                    }
+                })
+                .collect(),
+            Mode::TriangleFan => (1..indices.len().saturating_sub(1))
+                .flat_map(|i| [indices[0], indices[i], indices[i + 1]])
+                .collect(),
+            Mode::Points | Mode::Lines | Mode::LineLoop | Mode::LineStrip => return Ok(None),
+        };
+
+        Ok(Some(ImportedMesh {
+            name,
+            bounds: Bounds::from_vertices(&vertices),
+            vertices,
+            indices,
+            material,
+            skin,
+            morph_targets,
+        }))
+    }
+
+    fn read_skin(
+        skin: &gltf::Skin,
+        buffers: &[Vec<u8>],
+        nodes: &[GltfNode],
+        parents: &[Option<usize>],
+    ) -> anyhow::Result<Skeleton> {
+        let joint_nodes: Vec<usize> = skin.joints().map(|node| node.index()).collect();
+        let reader = skin.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+        let inverse_binds: Vec<Matrix4<f32>> = match reader.read_inverse_bind_matrices() {
+            Some(matrices) => matrices.map(Matrix4::from).collect(),
+            None => vec![Matrix4::identity(); joint_nodes.len()],
+        };
+        if inverse_binds.len() < joint_nodes.len() {
+            bail!(
+                "skin {} has fewer inverse bind matrices than joints",
+                skin.index()
+            );
+        }
+
+        let joints = joint_nodes
+            .iter()
+            .zip(inverse_binds)
+            .map(|(&node, inverse_bind)| {
+                // Walk up to the closest joint, collecting the static nodes in between
+                let mut offset = Matrix4::identity();
+                let mut parent = None;
+                let mut ancestor = parents[node];
+                while let Some(index) = ancestor {
+                    if let Some(joint) = joint_nodes.iter().position(|&n| n == index) {
+                        parent = Some(joint);
+                        break;
+                    }
+                    offset = nodes[index].transform.matrix() * offset;
+                    ancestor = parents[index];
+                }
+
+                Joint {
+                    name: nodes[node].name.clone(),
+                    node,
+                    parent,
+                    offset,
+                    rest: nodes[node].transform,
+                    inverse_bind,
+                }
+            })
+            .collect();
+
+        Skeleton::new(joints)
+    }
+
+    /// Rejects channels whose value count doesn't match their keyframes, sampling them
+    /// would index past the values
+    fn read_animation(
+        path: &Path,
+        animation: &gltf::Animation,
+        buffers: &[Vec<u8>],
+    ) -> anyhow::Result<AnimationClip> {
+        use gltf::animation::util::ReadOutputs;
+
+        let name = animation
+            .name()
+            .map(str::to_owned)
+            .unwrap_or_else(|| format!("animation {}", animation.index()));
+        let mut channels = Vec::new();
+        for channel in animation.channels() {
+            let error = |message: String| {
+                ImportError::new(
+                    path,
+                    None,
+                    format!("{name:?} channel {}: {message}", channel.index()),
+                )
+            };
+            let reader = channel.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+            let times: Vec<f32> = reader
+                .read_inputs()
+                .ok_or_else(|| error("no keyframe times".to_owned()))?
+                .collect();
+            let values = match reader
+                .read_outputs()
+                .ok_or_else(|| error("no values".to_owned()))?
+            {
+                ReadOutputs::Translations(values) => {
+                    ChannelValues::Translation(values.map(Into::into).collect())
+                }
+                ReadOutputs::Rotations(values) => ChannelValues::Rotation(
+                    values
+                        .into_f32()
+                        .map(|[x, y, z, w]| cgmath::Quaternion::new(w, x, y, z))
+                        .collect(),
+                ),
+                ReadOutputs::Scales(values) => {
+                    ChannelValues::Scale(values.map(Into::into).collect())
+                }
+                ReadOutputs::MorphTargetWeights(values) => {
+                    ChannelValues::MorphWeights(values.into_f32().collect())
+                }
+            };
+            let interpolation = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                gltf::animation::Interpolation::Step => Interpolation::Step,
+                gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+            };
+
+            // Cubic splines store an in-tangent, the value and an out-tangent per key
+            let slots = match interpolation {
+                Interpolation::CubicSpline => times.len() * 3,
+                Interpolation::Linear | Interpolation::Step => times.len(),
+            };
+            let count = match &values {
+                ChannelValues::Translation(values) | ChannelValues::Scale(values) => values.len(),
+                ChannelValues::Rotation(values) => values.len(),
+                ChannelValues::MorphWeights(values) => values.len(),
+            };
+            // Weights store every morph target of the node's mesh per slot
+            let per_slot = match values {
+                ChannelValues::MorphWeights(_) => channel
+                    .target()
+                    .node()
+                    .mesh()
+                    .and_then(|mesh| mesh.primitives().next())
+                    .map(|primitive| primitive.morph_targets().len())
+                    .unwrap_or_else(|| count.checked_div(slots).unwrap_or(0)),
+                _ => 1,
+            };
+            if count != slots * per_slot {
+                let message = format!("{count} values for {} keyframes", times.len());
+                return Err(error(message).into());
+            }
+
+            channels.push(Channel {
+                node: channel.target().node().index(),
+                interpolation,
+                times,
+                values,
+            });
+        }
+
+        Ok(AnimationClip::new(name, channels))
+    }
+
+    fn sampler(sampler: gltf::texture::Sampler) -> wgpu::SamplerDescriptor<'static> {
+        let address_mode = |mode: WrappingMode| match mode {
+            WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+            WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+            WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+        };
+        let mag_filter = match sampler.mag_filter() {
+            Some(MagFilter::Nearest) => wgpu::FilterMode::Nearest,
+            Some(MagFilter::Linear) | None => wgpu::FilterMode::Linear,
+        };
+        let (min_filter, mipmap_filter) = match sampler.min_filter() {
+            Some(MinFilter::Nearest | MinFilter::NearestMipmapNearest) => {
+                (wgpu::FilterMode::Nearest, wgpu::MipmapFilterMode::Nearest)
+            }
+            Some(MinFilter::NearestMipmapLinear) => {
+                (wgpu::FilterMode::Nearest, wgpu::MipmapFilterMode::Linear)
+            }
+            Some(MinFilter::LinearMipmapNearest) => {
+                (wgpu::FilterMode::Linear, wgpu::MipmapFilterMode::Nearest)
+            }
+            Some(MinFilter::Linear | MinFilter::LinearMipmapLinear) | None => {
+                (wgpu::FilterMode::Linear, wgpu::MipmapFilterMode::Linear)
+            }
+        };
+
+        wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: address_mode(sampler.wrap_s()),
+            address_mode_v: address_mode(sampler.wrap_t()),
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter,
+            min_filter,
+            mipmap_filter,
+            ..Default::default()
+        }
+    }
+
+    /// `data:` URIs are decoded in place, anything else is a path relative to the glTF file.
+    /// Paths read from disk are added to `dependencies`
+    async fn load_uri(
+        parent_path: &Path,
+        uri: &str,
+        dependencies: &mut Vec<PathBuf>,
+    ) -> anyhow::Result<Vec<u8>> {
+        if let Some(data) = uri.strip_prefix("data:") {
+            let (_, payload) = data
+                .split_once(";base64,")
+                .context("Only base64 data URIs are supported")?;
+            return Ok(base64::engine::general_purpose::STANDARD.decode(payload)?);
+        }
+
+        // Watched even if it can't be read yet
+        let path = parent_path.join(percent_decode(uri));
+        dependencies.push(path.clone());
+        Io::load_binary(&path).await
+    }
+}
+
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset_manager::source::MemorySource;
+
+    /// One node translated by a two keyframe animation with `values` outputs
+    fn animated(interpolation: &str, values: usize) -> String {
+        let mut buffer: Vec<u8> = [0.0f32, 1.0].iter().flat_map(|t| t.to_le_bytes()).collect();
+        buffer.extend((0..values * 3).flat_map(|v| (v as f32).to_le_bytes()));
+        let data = base64::engine::general_purpose::STANDARD.encode(&buffer);
+        format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "nodes": [{{"name": "box"}}],
+                "buffers": [{{"byteLength": {length}, "uri": "data:application/octet-stream;base64,{data}"}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteOffset": 0, "byteLength": 8}},
+                    {{"buffer": 0, "byteOffset": 8, "byteLength": {values_length}}}
+                ],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 2, "type": "SCALAR", "min": [0.0], "max": [1.0]}},
+                    {{"bufferView": 1, "componentType": 5126, "count": {values}, "type": "VEC3"}}
+                ],
+                "animations": [{{
+                    "name": "slide",
+                    "samplers": [{{"input": 0, "output": 1, "interpolation": "{interpolation}"}}],
+                    "channels": [{{"sampler": 0, "target": {{"node": 0, "path": "translation"}}}}]
+                }}]
+            }}"#,
+            length = buffer.len(),
+            values_length = values * 12,
+        )
+    }
+
+    fn load(name: &str, gltf: String) -> anyhow::Result<ImportedScene> {
+        let prefix = format!("gltf-tests-{name}");
+        let mount = Io::mount(
+            &prefix,
+            0,
+            MemorySource::new().with_file("scene.gltf", gltf),
+        );
+        let scene = pollster::block_on(GltfLoader::load_scene(
+            &Path::new(&prefix).join("scene.gltf"),
+        ));
+        Io::unmount(mount);
+        scene
+    }
+
+    #[test]
+    fn animations_with_a_value_per_key_load() {
+        let scene = load("linear", animated("LINEAR", 2)).unwrap();
+        let [clip] = scene.animations.as_slice() else {
+            panic!("one animation");
+        };
+        assert_eq!(clip.name, "slide");
+        assert_eq!(clip.duration, 1.0);
+
+        let scene = load("cubic", animated("CUBICSPLINE", 6)).unwrap();
+        assert_eq!(scene.animations.len(), 1);
+    }
+
+    #[test]
+    fn value_counts_must_match_the_keyframes() {
+        for (name, interpolation, values) in [
+            ("short", "LINEAR", 1),
+            ("step", "STEP", 3),
+            ("tangents", "CUBICSPLINE", 2),
+        ] {
+            let error = load(name, animated(interpolation, values)).err().unwrap();
+            let error = error.to_string();
+            assert!(
+                error.ends_with(&format!(
+                    "scene.gltf: \"slide\" channel 0: {values} values for 2 keyframes"
+                )),
+                "{error}"
+            );
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::{fmt, hash::Hash, marker::PhantomData, sync::Arc};
+
+/// Typed reference to an asset stored in [`Assets`].
+///
+/// A slot index plus the generation it was issued for. Once the asset is removed the slot
+/// generation moves on, so an old handle never aliases whatever is stored there next.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(index: u32, generation: u32) -> Self {
+        Self {
+            index,
+            generation,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+// Manual impls: derives would put the bounds on `T`, which is never stored
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = std::any::type_name::<T>();
+        let name = name.rsplit("::").next().unwrap_or(name);
+        write!(f, "Handle<{name}>({}v{})", self.index, self.generation)
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<Arc<T>>,
+}
+
+/// Generational storage for one asset type. Freed slots are reused with a bumped generation.
+pub struct Assets<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    len: usize,
+}
+
+impl<T> Default for Assets<T> {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<T> Assets<T> {
+    pub fn insert(&mut self, value: T) -> Handle<T> {
+        self.insert_shared(Arc::new(value))
+    }
+
+    /// Like [`Self::insert`] for a value that may already live in other slots.
+    pub fn insert_shared(&mut self, value: Arc<T>) -> Handle<T> {
+        self.len += 1;
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.value = Some(value);
+            return Handle::new(index, slot.generation);
+        }
+
+        let index = self.slots.len() as u32;
+        self.slots.push(Slot {
+            generation: 0,
+            value: Some(value),
+        });
+        Handle::new(index, 0)
+    }
+
+    /// Swaps the asset behind `handle`, every copy of the handle sees the new value.
+    /// Returns the previous one, `None` (and drops `value`) if the handle is stale.
+    pub fn replace(&mut self, handle: Handle<T>, value: impl Into<Arc<T>>) -> Option<Arc<T>> {
+        let slot = self
+            .slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)?;
+        let previous = slot.value.take()?;
+        slot.value = Some(value.into());
+        Some(previous)
+    }
+
+    /// `None` if the handle was never issued here or its asset has been removed.
+    pub fn get(&self, handle: Handle<T>) -> Option<&Arc<T>> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_ref())
+    }
+
+    pub fn contains(&self, handle: Handle<T>) -> bool {
+        self.get(handle).is_some()
+    }
+
+    /// Frees the slot and invalidates every copy of `handle`.
+    pub fn remove(&mut self, handle: Handle<T>) -> Option<Arc<T>> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        let value = slot.value.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &Arc<T>)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.value
+                .as_ref()
+                .map(|value| (Handle::new(index as u32, slot.generation), value))
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_handles_stay_stale_after_the_slot_is_reused() {
+        let mut assets = Assets::default();
+        let first = assets.insert("first");
+        assert_eq!(assets.remove(first).as_deref(), Some(&"first"));
+        assert!(assets.get(first).is_none());
+
+        let second = assets.insert("second");
+        // Same slot, newer generation
+        assert_eq!(second.index(), first.index());
+        assert_ne!(second.generation(), first.generation());
+        assert_ne!(second, first);
+        assert!(assets.get(first).is_none());
+        assert_eq!(assets.get(second).map(|v| **v), Some("second"));
+
+        // The stale handle can't remove or replace the new asset either
+        assert!(assets.remove(first).is_none());
+        assert!(assets.replace(first, "stale").is_none());
+        assert_eq!(assets.get(second).map(|v| **v), Some("second"));
+        assert_eq!(assets.len(), 1);
+    }
+
+    #[test]
+    fn replace_keeps_the_handle_valid() {
+        let mut assets = Assets::default();
+        let handle = assets.insert(1);
+        let copy = handle;
+        assert_eq!(assets.replace(handle, 2).as_deref(), Some(&1));
+        assert_eq!(assets.get(copy).map(|v| **v), Some(2));
+        assert_eq!(assets.len(), 1);
+
+        assets.remove(handle);
+        assert!(assets.replace(handle, 3).is_none());
+        assert!(assets.is_empty());
+    }
+
+    #[test]
+    fn generations_do_not_alias_across_reuses() {
+        let mut assets = Assets::default();
+        let mut issued = Vec::new();
+        for value in 0..8 {
+            let handle = assets.insert(value);
+            issued.push(handle);
+            assets.remove(handle);
+        }
+        // One slot, a new generation every time
+        assert!(issued.iter().all(|handle| handle.index() == 0));
+        for (i, handle) in issued.iter().enumerate() {
+            assert_eq!(handle.generation(), i as u32);
+        }
+        let live = assets.insert(8);
+        assert!(issued.iter().all(|&handle| !assets.contains(handle)));
+        assert_eq!(
+            assets.iter().map(|(handle, _)| handle).collect::<Vec<_>>(),
+            [live]
+        );
+    }
+
+    #[test]
+    fn handles_from_other_slots_do_not_match() {
+        let mut assets = Assets::default();
+        let a = assets.insert('a');
+        let b = assets.insert('b');
+        assets.remove(a);
+        let c = assets.insert('c');
+        assert_eq!(c.index(), a.index());
+        assert_eq!(assets.get(b).map(|v| **v), Some('b'));
+        assert_eq!(assets.len(), 2);
+        assert_eq!(format!("{c:?}"), "Handle<char>(0v1)");
+    }
+}
@@ -0,0 +1,501 @@
+// CPU side result of an importer. Decoding happens here, GPU resources are only created
+// when the AssetManager uploads it, so every format goes through the same path.
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use wgpu::util::DeviceExt as _;
+
+use crate::{
+    asset_manager::{
+        compressed::MipChain,
+        fallback::FallbackTextures,
+        handle::Handle,
+        obj_import::{ObjImportOptions, ObjLoader},
+        ply_import::PlyLoader,
+        stl_import::StlLoader,
+        texture_cache::TextureCache,
+    },
+    gpu::context::GpuContext,
+    model::{
+        Bounds, Material, MaterialParams, MaterialUniform, Mesh, Model, ModelVertex, MorphDelta,
+        MorphInfo, MorphTargets, OPTIONAL_MAPS, RenderState, SkinVertex, map_flags,
+    },
+    texture::Texture,
+};
+
+/// Why a file could not be imported. `line` is 1-based, `None` when the problem is not
+/// tied to a line (unreadable file, ...)
+#[derive(Debug, Clone)]
+pub struct ImportError {
+    pub file: PathBuf,
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+impl ImportError {
+    pub fn new(file: impl Into<PathBuf>, line: Option<u32>, message: impl fmt::Display) -> Self {
+        Self {
+            file: file.into(),
+            line,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{line}: {}", self.file.display(), self.message),
+            None => write!(f, "{}: {}", self.file.display(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Model files [`AssetManager::load_model`](super::AssetManager::load_model) imports, picked
+/// by extension
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModelFormat {
+    Obj,
+    /// Stanford PLY, ASCII or binary, with vertex colors
+    Ply,
+    /// STL, ASCII or binary, with facet normals
+    Stl,
+}
+
+impl ModelFormat {
+    /// `None` for extensions no importer handles
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "obj" => Some(Self::Obj),
+            "ply" => Some(Self::Ply),
+            "stl" => Some(Self::Stl),
+            _ => None,
+        }
+    }
+
+    pub(crate) async fn import(
+        path: &Path,
+        obj_options: &ObjImportOptions,
+    ) -> anyhow::Result<ImportedModel> {
+        match Self::from_path(path) {
+            Some(Self::Obj) => ObjLoader::load_model(path, obj_options).await,
+            Some(Self::Ply) => PlyLoader::load_model(path).await,
+            Some(Self::Stl) => StlLoader::load_model(path).await,
+            None => Err(ImportError::new(path, None, "unknown model format").into()),
+        }
+    }
+}
+
+/// Pixels of an [`ImportedTexture`]
+#[derive(Clone)]
+pub enum TextureData {
+    Image(DynamicImage),
+    /// KTX2/DDS content with its mip levels, uploaded without conversion when the device
+    /// supports the format
+    Compressed(MipChain),
+}
+
+impl TextureData {
+    /// KTX2 and DDS are recognized by their magic number, anything else goes to `image`
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        match MipChain::from_container(bytes) {
+            Some(chain) => Ok(Self::Compressed(chain?)),
+            None => Ok(Self::Image(image::load_from_memory(bytes)?)),
+        }
+    }
+}
+
+pub struct ImportedTexture {
+    pub label: String,
+    /// `None` when the file was missing or failed to decode, the missing-texture fallback
+    /// is bound in its place
+    pub data: Option<TextureData>,
+    /// File the image was read from, relative to the assets folder. Textures with the same
+    /// source share one GPU texture, embedded and generated images have none.
+    pub source: Option<PathBuf>,
+    /// Color data is stored as sRGB, normal/metallic-roughness/occlusion maps are linear
+    pub srgb: bool,
+    /// `Rgba16Float` or `Rgba32Float`, used for floating-point (HDR/EXR) images only
+    pub float_format: wgpu::TextureFormat,
+    pub sampler: wgpu::SamplerDescriptor<'static>,
+}
+
+impl ImportedTexture {
+    /// 1x1 texture, stands in for a missing map so the material can still be bound.
+    pub fn solid(label: impl Into<String>, color: [f32; 4]) -> Self {
+        let texel = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        Self {
+            label: label.into(),
+            data: Some(TextureData::Image(DynamicImage::ImageRgba8(
+                RgbaImage::from_pixel(1, 1, Rgba(texel)),
+            ))),
+            source: None,
+            srgb: true,
+            float_format: wgpu::TextureFormat::Rgba16Float,
+            sampler: Texture::default_sampler(),
+        }
+    }
+
+    /// Stand-in for a texture that could not be read. Logs `error` once, here.
+    pub fn missing(label: impl Into<String>, error: &anyhow::Error) -> Self {
+        let label = label.into();
+        tracing::warn!("Texture {label} is unavailable, using the fallback: {error:#}");
+        Self {
+            label,
+            data: None,
+            source: None,
+            srgb: true,
+            float_format: wgpu::TextureFormat::Rgba16Float,
+            sampler: Texture::default_sampler(),
+        }
+    }
+}
+
+/// Vertex colors in files are sRGB like the images, [`ModelVertex::color`] is linear
+pub(crate) fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Texture slots index into [`ImportedModel::textures`]
+#[derive(Default)]
+pub struct ImportedMaterial {
+    pub name: String,
+    pub base_color_texture: Option<usize>,
+    pub normal_texture: Option<usize>,
+    pub metallic_roughness_texture: Option<usize>,
+    pub occlusion_texture: Option<usize>,
+    pub emissive_texture: Option<usize>,
+    /// MTL `map_Ka`
+    pub ambient_texture: Option<usize>,
+    /// MTL `map_Ks`
+    pub specular_texture: Option<usize>,
+    /// MTL `map_Ns`
+    pub shininess_texture: Option<usize>,
+    /// MTL `map_d`
+    pub dissolve_texture: Option<usize>,
+    /// MTL `disp`
+    pub displacement_texture: Option<usize>,
+    /// MTL `refl`
+    pub reflection_texture: Option<usize>,
+    pub params: MaterialParams,
+    pub render_state: RenderState,
+    /// Bound with the base color instead of the texture's own sampler, which belongs to
+    /// whoever uploaded the file first
+    pub sampler: Option<wgpu::SamplerDescriptor<'static>>,
+}
+
+pub struct ImportedMesh {
+    pub name: String,
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    pub material: usize,
+    pub bounds: Bounds,
+    /// Same length as `vertices` when the mesh is skinned
+    pub skin: Option<Vec<SkinVertex>>,
+    pub morph_targets: Vec<ImportedMorphTarget>,
+}
+
+/// Per-vertex deltas of one blend shape, empty when the target doesn't move that attribute
+#[derive(Default)]
+pub struct ImportedMorphTarget {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+}
+
+#[derive(Default)]
+pub struct ImportedModel {
+    pub name: String,
+    pub meshes: Vec<ImportedMesh>,
+    pub materials: Vec<ImportedMaterial>,
+    pub textures: Vec<ImportedTexture>,
+    /// Files read besides the model itself (material libraries, textures), relative to the
+    /// assets folder. Hot reloading watches them.
+    pub dependencies: Vec<PathBuf>,
+}
+
+impl ImportedModel {
+    pub(crate) fn upload(
+        self,
+        gpu_context: &GpuContext,
+        layout: &wgpu::BindGroupLayout,
+        morph_layout: &wgpu::BindGroupLayout,
+        textures: &mut TextureCache,
+        fallbacks: &FallbackTextures,
+    ) -> anyhow::Result<Model> {
+        let texture_handles = upload_textures(gpu_context, self.textures, textures, fallbacks)?;
+        let materials = upload_materials(
+            gpu_context,
+            layout,
+            self.materials,
+            &texture_handles,
+            textures,
+            fallbacks,
+        )?;
+        let meshes = self
+            .meshes
+            .iter()
+            .map(|mesh| upload_mesh(&gpu_context.device, morph_layout, mesh))
+            .collect();
+
+        Ok(Model { meshes, materials })
+    }
+}
+
+pub(crate) fn upload_textures(
+    gpu_context: &GpuContext,
+    imported: Vec<ImportedTexture>,
+    textures: &mut TextureCache,
+    fallbacks: &FallbackTextures,
+) -> anyhow::Result<Vec<Handle<Texture>>> {
+    imported
+        .into_iter()
+        .map(|t| match &t.data {
+            Some(data) => match textures.get_or_upload(gpu_context, &t, data) {
+                Ok(handle) => Ok(handle),
+                // A compressed format the device lacks and that has no CPU decoder
+                Err(e) => {
+                    tracing::warn!(
+                        "Texture {} is unavailable, using the fallback: {e:#}",
+                        t.label
+                    );
+                    Ok(fallbacks.missing)
+                }
+            },
+            None => Ok(fallbacks.missing),
+        })
+        .collect()
+}
+
+pub(crate) fn upload_materials(
+    gpu_context: &GpuContext,
+    layout: &wgpu::BindGroupLayout,
+    imported: Vec<ImportedMaterial>,
+    texture_handles: &[Handle<Texture>],
+    textures: &mut TextureCache,
+    fallbacks: &FallbackTextures,
+) -> anyhow::Result<Vec<Material>> {
+    let slot = |index: Option<usize>| index.map(|i| texture_handles[i]);
+
+    imported
+        .into_iter()
+        .map(|m| {
+            // The base color factor is multiplied in the shader
+            let diffuse_texture = slot(m.base_color_texture).unwrap_or(fallbacks.white);
+            let get = |handle| {
+                textures
+                    .get(handle)
+                    .expect("texture uploaded with this model")
+                    .clone()
+            };
+            let texture = get(diffuse_texture);
+
+            // Same order as `MaterialUniform::MAP_*`
+            let optional = [
+                m.normal_texture,
+                m.metallic_roughness_texture,
+                m.occlusion_texture,
+                m.emissive_texture,
+                m.ambient_texture,
+                m.specular_texture,
+                m.shininess_texture,
+                m.dissolve_texture,
+                m.displacement_texture,
+                m.reflection_texture,
+            ]
+            .map(slot);
+            let sampler = m
+                .sampler
+                .as_ref()
+                .map(|sampler| gpu_context.device.create_sampler(sampler));
+            // Absent maps are bound too, the `MAP_*` bits tell the shader to skip them
+            let maps = std::array::from_fn::<_, OPTIONAL_MAPS, _>(|i| {
+                let fallback = if i == 0 {
+                    fallbacks.flat_normal
+                } else {
+                    fallbacks.white
+                };
+                get(optional[i].unwrap_or(fallback))
+            });
+            let uniform = MaterialUniform::new(&m.params, map_flags(&optional));
+            let params_buffer =
+                gpu_context
+                    .device
+                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some(&format!("{:?} material buffer", m.name)),
+                        contents: bytemuck::bytes_of(&uniform),
+                        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    });
+            let entries = [
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(
+                        sampler.as_ref().unwrap_or(&texture.sampler),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ]
+            .into_iter()
+            .chain(
+                maps.iter()
+                    .zip(Material::FIRST_MAP_BINDING..)
+                    .map(|(map, binding)| wgpu::BindGroupEntry {
+                        binding,
+                        resource: wgpu::BindingResource::TextureView(&map.view),
+                    }),
+            )
+            .collect::<Vec<_>>();
+            let bind_group = gpu_context
+                .device
+                .create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout,
+                    entries: &entries,
+                    label: Some(&format!("{:?} material bind group", m.name)),
+                });
+
+            let [
+                normal_texture,
+                metallic_roughness_texture,
+                occlusion_texture,
+                emissive_texture,
+                ambient_texture,
+                specular_texture,
+                shininess_texture,
+                dissolve_texture,
+                displacement_texture,
+                reflection_texture,
+            ] = optional;
+            Ok(Material {
+                diffuse_texture,
+                normal_texture,
+                metallic_roughness_texture,
+                occlusion_texture,
+                emissive_texture,
+                ambient_texture,
+                specular_texture,
+                shininess_texture,
+                dissolve_texture,
+                displacement_texture,
+                reflection_texture,
+                params: m.params,
+                params_buffer,
+                bind_group,
+                render_state: m.render_state,
+                name: m.name,
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn upload_mesh(
+    device: &wgpu::Device,
+    morph_layout: &wgpu::BindGroupLayout,
+    mesh: &ImportedMesh,
+) -> Mesh {
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Vertex Buffer", mesh.name)),
+        contents: bytemuck::cast_slice(&mesh.vertices),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Index Buffer", mesh.name)),
+        contents: bytemuck::cast_slice(&mesh.indices),
+        usage: wgpu::BufferUsages::INDEX,
+    });
+    let skin_buffer = mesh.skin.as_ref().map(|skin| {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Skin Buffer", mesh.name)),
+            contents: bytemuck::cast_slice(skin),
+            usage: wgpu::BufferUsages::VERTEX,
+        })
+    });
+
+    Mesh {
+        name: mesh.name.clone(),
+        vertex_buffer,
+        index_buffer,
+        num_elements: mesh.indices.len() as u32,
+        material: mesh.material,
+        bounds: mesh.bounds,
+        skin_buffer,
+        morph_targets: upload_morph_targets(device, morph_layout, mesh),
+    }
+}
+
+fn upload_morph_targets(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    mesh: &ImportedMesh,
+) -> Option<MorphTargets> {
+    if mesh.morph_targets.is_empty() {
+        return None;
+    }
+    let vertex_count = mesh.vertices.len();
+    let delta = |values: &[[f32; 3]], vertex: usize| {
+        let [x, y, z] = values.get(vertex).copied().unwrap_or_default();
+        [x, y, z, 0.0]
+    };
+    let deltas: Vec<MorphDelta> = mesh
+        .morph_targets
+        .iter()
+        .flat_map(|target| {
+            (0..vertex_count).map(move |vertex| MorphDelta {
+                position: delta(&target.positions, vertex),
+                normal: delta(&target.normals, vertex),
+            })
+        })
+        .collect();
+    let info = MorphInfo {
+        vertex_count: vertex_count as u32,
+        target_count: mesh.morph_targets.len() as u32,
+        _padding: [0; 2],
+    };
+
+    let deltas = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Morph Deltas", mesh.name)),
+        contents: bytemuck::cast_slice(&deltas),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+    let info = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Morph Info", mesh.name)),
+        contents: bytemuck::cast_slice(&[info]),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: deltas.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: info.as_entire_binding(),
+            },
+        ],
+        label: Some(&format!("{:?} Morph Bind Group", mesh.name)),
+    });
+
+    Some(MorphTargets {
+        count: mesh.morph_targets.len() as u32,
+        deltas,
+        info,
+        bind_group,
+    })
+}
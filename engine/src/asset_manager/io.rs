@@ -1,61 +1,317 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, RwLock},
+};
 
+use anyhow::{Context, bail};
 use cfg_if::cfg_if;
 
-pub struct Io;
+use crate::asset_manager::{
+    archive::{Archive, entry_name},
+    source::AssetSource,
+};
 
-impl Io {
-    #[cfg(target_arch = "wasm32")]
-    fn format_url<A: AsRef<Path>>(file_name: A) -> Url {
-        let window = web_sys::window().unwrap();
-        let location = window.location();
-        let mut origin = location.origin().unwrap();
-        if !origin.ends_with("learn-wgpu") {
-            origin = format!("{}/learn-wgpu", origin);
-        }
-        let base = Url::parse(&format!("{}/", origin,)).unwrap();
-        base.join(file_name).unwrap()
-    }
+/// Priority of the default assets folder (the page's `assets/` on the web)
+pub const ASSETS_PRIORITY: i32 = 0;
+/// Priority of the discovered `assets.pak`, above loose files
+pub const ARCHIVE_PRIORITY: i32 = 100;
 
-    pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
+static MOUNTS: LazyLock<RwLock<Mounts>> = LazyLock::new(|| RwLock::new(Mounts::with_defaults()));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MountId(u64);
+
+struct Mount {
+    id: MountId,
+    prefix: String,
+    priority: i32,
+    source: Arc<dyn AssetSource>,
+}
+
+/// Kept sorted: highest priority first, the later mount first within a priority
+#[derive(Default)]
+struct Mounts {
+    next_id: u64,
+    list: Vec<Mount>,
+}
+
+impl Mounts {
+    fn with_defaults() -> Self {
+        let mut mounts = Self::default();
         cfg_if! {
             if #[cfg(target_arch = "wasm32")] {
-                let url = format_url(file_name);
-                let txt = reqwest::get(url)
-                    .await?
-                    .text()
-                    .await?;
+                match super::source::HttpSource::from_page() {
+                    Ok(source) => {
+                        mounts.add("", ASSETS_PRIORITY, Arc::new(source));
+                    }
+                    Err(e) => tracing::error!("No default asset source: {e:#}"),
+                }
             } else {
-                let path = get_assets_folder()?.join(file_name);
-                let txt = std::fs::read_to_string(path)?;
+                match get_assets_folder() {
+                    Ok(folder) => {
+                        mounts.add(
+                            "",
+                            ASSETS_PRIORITY,
+                            Arc::new(super::source::DirSource::new(folder)),
+                        );
+                    }
+                    Err(e) => tracing::error!("No default asset source: {e:#}"),
+                }
+                if let Some(archive) = discover_archive() {
+                    mounts.add("", ARCHIVE_PRIORITY, Arc::new(archive));
+                }
             }
         }
+        mounts
+    }
 
-        Ok(txt)
+    fn add(&mut self, prefix: &str, priority: i32, source: Arc<dyn AssetSource>) -> MountId {
+        let id = MountId(self.next_id);
+        self.next_id += 1;
+        let index = self.list.partition_point(|mount| mount.priority > priority);
+        self.list.insert(
+            index,
+            Mount {
+                id,
+                prefix: entry_name(Path::new(prefix)),
+                priority,
+                source,
+            },
+        );
+        id
+    }
+}
+
+/// Virtual file system the importers read assets through. Sources are mounted at a prefix
+/// (`""` for the root) and asked in priority order until one has the file, so a patch or
+/// mod folder mounted above the assets folder overrides single files.
+///
+/// Native builds start with the assets folder (`ASSETS`, or `./assets`) at
+/// [`ASSETS_PRIORITY`] and `assets.pak` at [`ARCHIVE_PRIORITY`], the web with the page's
+/// `assets/` folder over HTTP.
+pub struct Io;
+
+impl Io {
+    /// Higher priorities are asked first, within a priority the latest mount wins
+    pub fn mount(
+        prefix: impl AsRef<Path>,
+        priority: i32,
+        source: impl AssetSource + 'static,
+    ) -> MountId {
+        let prefix = prefix.as_ref();
+        tracing::info!(
+            "Mounting {} at /{} (priority {priority})",
+            source.describe(),
+            prefix.display()
+        );
+        MOUNTS.write().unwrap_or_else(|e| e.into_inner()).add(
+            &prefix.to_string_lossy(),
+            priority,
+            Arc::new(source),
+        )
+    }
+
+    /// Returns false when `id` was already unmounted
+    pub fn unmount(id: MountId) -> bool {
+        let mut mounts = MOUNTS.write().unwrap_or_else(|e| e.into_inner());
+        let before = mounts.list.len();
+        mounts.list.retain(|mount| mount.id != id);
+        mounts.list.len() != before
+    }
+
+    /// Removes every mount, including the defaults. Tests start from an empty file system.
+    pub fn unmount_all() {
+        MOUNTS
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .list
+            .clear();
+    }
+
+    /// Serves assets from `archive` above loose files
+    pub fn mount_archive(archive: Archive) -> MountId {
+        Self::mount("", ARCHIVE_PRIORITY, archive)
+    }
+
+    pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
+        let data = Self::load_binary(file_name).await?;
+        String::from_utf8(data).with_context(|| format!("{file_name} is not UTF-8"))
     }
 
     pub async fn load_binary<A: AsRef<Path>>(path: A) -> anyhow::Result<Vec<u8>> {
-        cfg_if! {
-            if #[cfg(target_arch = "wasm32")] {
-                let url = format_url(file_name);
-                let data = reqwest::get(url)
-                    .await?
-                    .bytes()
-                    .await?
-                    .to_vec();
-            } else {
-                let path = get_assets_folder()?.join(path);
-                let data = std::fs::read(path)?;
+        let name = entry_name(path.as_ref());
+        // The lock is not held across reads, a source may be slow
+        let candidates: Vec<_> = {
+            let mounts = MOUNTS.read().unwrap_or_else(|e| e.into_inner());
+            mounts
+                .list
+                .iter()
+                .filter_map(|mount| {
+                    let relative = if mount.prefix.is_empty() {
+                        name.as_str()
+                    } else {
+                        name.strip_prefix(&mount.prefix)?.strip_prefix('/')?
+                    };
+                    Some((relative.to_owned(), mount.source.clone()))
+                })
+                .collect()
+        };
+        for (relative, source) in candidates {
+            if let Some(data) = source
+                .read(&relative)
+                .await
+                .with_context(|| format!("Reading {name} from {}", source.describe()))?
+            {
+                return Ok(data);
             }
         }
+        bail!("{name} not found in any asset source")
+    }
+}
 
-        Ok(data)
+/// `ASSETS_ARCHIVE`, or `assets.pak` next to the executable
+#[cfg(not(target_arch = "wasm32"))]
+fn discover_archive() -> Option<Archive> {
+    let path = match std::env::var("ASSETS_ARCHIVE") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => std::env::current_exe().ok()?.parent()?.join("assets.pak"),
+    };
+    if !path.is_file() {
+        return None;
+    }
+    match Archive::open(&path) {
+        Ok(archive) => {
+            tracing::info!("Reading assets from {}", path.display());
+            Some(archive)
+        }
+        Err(e) => {
+            tracing::error!("Ignoring asset archive: {e:#}");
+            None
+        }
     }
 }
 
-fn get_assets_folder() -> anyhow::Result<PathBuf> {
+pub(crate) fn get_assets_folder() -> anyhow::Result<PathBuf> {
     Ok(match std::env::var("ASSETS") {
         Ok(path) => PathBuf::from(Path::new(&path)),
         Err(_) => std::env::current_dir()?.join("assets"),
     })
 }
+
+/// Where generated data (pipeline cache, ...) is written. `ASSETS_CACHE` overrides `./.cache`
+pub(crate) fn get_cache_folder() -> anyhow::Result<PathBuf> {
+    Ok(match std::env::var("ASSETS_CACHE") {
+        Ok(path) => PathBuf::from(Path::new(&path)),
+        Err(_) => std::env::current_dir()?.join(".cache"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::asset_manager::source::MemorySource;
+
+    // The mounts are global, every test mounts below its own prefix and unmounts at the end
+
+    fn load(path: &str) -> anyhow::Result<Vec<u8>> {
+        pollster::block_on(Io::load_binary(path))
+    }
+
+    struct BrokenSource;
+
+    #[async_trait]
+    impl AssetSource for BrokenSource {
+        async fn read(&self, _path: &str) -> anyhow::Result<Option<Vec<u8>>> {
+            bail!("disk on fire")
+        }
+
+        fn describe(&self) -> String {
+            "broken".to_owned()
+        }
+    }
+
+    #[test]
+    fn higher_priority_and_later_mounts_win() {
+        let low = Io::mount(
+            "io-priority",
+            0,
+            MemorySource::new()
+                .with_file("a.txt", "low")
+                .with_file("b.txt", "low"),
+        );
+        let high = Io::mount(
+            "io-priority",
+            10,
+            MemorySource::new().with_file("a.txt", "high"),
+        );
+        assert_eq!(load("io-priority/a.txt").unwrap(), b"high");
+        // Missing in the high priority source, found below
+        assert_eq!(load("io-priority/b.txt").unwrap(), b"low");
+
+        let latest = Io::mount(
+            "io-priority",
+            10,
+            MemorySource::new().with_file("a.txt", "latest"),
+        );
+        assert_eq!(load("io-priority/a.txt").unwrap(), b"latest");
+
+        assert!(Io::unmount(latest));
+        assert!(Io::unmount(high));
+        assert!(!Io::unmount(high));
+        assert_eq!(load("io-priority/a.txt").unwrap(), b"low");
+        Io::unmount(low);
+        assert!(load("io-priority/a.txt").is_err());
+    }
+
+    #[test]
+    fn sources_only_see_paths_below_their_prefix() {
+        let outer = Io::mount(
+            "io-prefix",
+            0,
+            MemorySource::new().with_file("a.txt", "outer"),
+        );
+        let inner = Io::mount(
+            "io-prefix/inner",
+            0,
+            MemorySource::new().with_file("a.txt", "inner"),
+        );
+        assert_eq!(load("io-prefix/a.txt").unwrap(), b"outer");
+        assert_eq!(load("io-prefix/inner/a.txt").unwrap(), b"inner");
+        // The prefix matches whole components
+        assert!(load("io-prefix-other/a.txt").is_err());
+        assert!(load("io-prefixa.txt").is_err());
+        Io::unmount(outer);
+        Io::unmount(inner);
+    }
+
+    #[test]
+    fn paths_and_prefixes_are_normalized() {
+        let id = Io::mount(
+            "./io-normalize/skipped/../",
+            0,
+            MemorySource::new().with_file("dir/../a.txt", "a"),
+        );
+        assert_eq!(load("io-normalize/a.txt").unwrap(), b"a");
+        assert_eq!(load("io-normalize/./dir/../a.txt").unwrap(), b"a");
+        assert_eq!(load("/io-normalize/x/y/../../a.txt").unwrap(), b"a");
+        assert_eq!(
+            pollster::block_on(Io::load_string("io-normalize/./a.txt")).unwrap(),
+            "a"
+        );
+        Io::unmount(id);
+    }
+
+    #[test]
+    fn errors_stop_the_lookup() {
+        let fallback = Io::mount("io-error", 0, MemorySource::new().with_file("a.txt", "a"));
+        let broken = Io::mount("io-error", 10, BrokenSource);
+        let error = load("io-error/a.txt").unwrap_err();
+        assert!(format!("{error:#}").contains("disk on fire"), "{error:#}");
+
+        Io::unmount(broken);
+        assert_eq!(load("io-error/a.txt").unwrap(), b"a");
+        Io::unmount(fallback);
+    }
+}
@@ -0,0 +1,123 @@
+// Background decoding. Importers run on worker threads (on the web as local futures), the
+// AssetManager picks up the results once per frame and uploads them on the main thread.
+use std::{future::Future, path::PathBuf, sync::mpsc};
+
+use crate::{
+    asset_manager::{
+        gltf_import::{GltfScene, ImportedScene},
+        handle::Handle,
+        import::ImportedModel,
+        material_file::ImportedMaterialFile,
+    },
+    model::{Material, Model},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadStatus {
+    Loading,
+    Loaded,
+    /// The placeholder stays in place, the message is the full error chain
+    Failed(String),
+}
+
+/// Output of a background load, applied by [`super::AssetManager::poll_loads`]
+pub(crate) enum Decoded {
+    Model {
+        path: PathBuf,
+        handle: Handle<Model>,
+        result: anyhow::Result<ImportedModel>,
+    },
+    /// Only reloads, glTF files are loaded with the awaited `load_gltf`
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    Scene {
+        path: PathBuf,
+        handle: Handle<GltfScene>,
+        result: anyhow::Result<ImportedScene>,
+    },
+    /// Only reloads, material files are loaded with the awaited `load_material`
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    Material {
+        path: PathBuf,
+        handle: Handle<Material>,
+        result: anyhow::Result<Box<ImportedMaterialFile>>,
+    },
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+type Job = Box<dyn FnOnce() + Send>;
+
+pub(crate) struct LoadQueue {
+    sender: mpsc::Sender<Decoded>,
+    receiver: mpsc::Receiver<Decoded>,
+    #[cfg(not(target_arch = "wasm32"))]
+    jobs: mpsc::Sender<Job>,
+}
+
+impl LoadQueue {
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let jobs = {
+            let (jobs, queue) = mpsc::channel::<Job>();
+            let queue = std::sync::Arc::new(std::sync::Mutex::new(queue));
+            let workers = std::thread::available_parallelism().map_or(2, |n| n.get().min(4));
+            for i in 0..workers {
+                let queue = queue.clone();
+                std::thread::Builder::new()
+                    .name(format!("asset loader {i}"))
+                    .spawn(move || {
+                        loop {
+                            // The lock is only held while waiting, jobs run unlocked
+                            let job = queue
+                                .lock()
+                                .map_err(drop)
+                                .and_then(|q| q.recv().map_err(drop));
+                            match job {
+                                Ok(job) => job(),
+                                // The AssetManager is gone
+                                Err(()) => break,
+                            }
+                        }
+                    })
+                    .expect("spawning asset loader thread");
+            }
+            jobs
+        };
+
+        Self {
+            sender,
+            receiver,
+            #[cfg(not(target_arch = "wasm32"))]
+            jobs,
+        }
+    }
+
+    /// Runs `task` off the main thread, its result shows up in [`Self::finished`].
+    pub(crate) fn spawn<F, Fut>(&self, task: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Decoded> + 'static,
+    {
+        let sender = self.sender.clone();
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                wasm_bindgen_futures::spawn_local(async move {
+                    let _ = sender.send(task().await);
+                });
+            } else {
+                let job: Job = Box::new(move || {
+                    let _ = sender.send(pollster::block_on(task()));
+                });
+                if self.jobs.send(job).is_err() {
+                    tracing::error!("Asset loader threads are gone, load dropped");
+                }
+            }
+        }
+    }
+
+    /// Results that arrived since the last call, never blocks.
+    pub(crate) fn finished(&self) -> impl Iterator<Item = Decoded> + '_ {
+        self.receiver.try_iter()
+    }
+}
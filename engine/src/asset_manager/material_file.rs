@@ -0,0 +1,440 @@
+//! Hand-written RON material files. They describe one material without a model, which
+//! entities put over their model's imported materials with `components::MaterialOverride`.
+//! Every path in the file (shader and textures) is relative to the assets folder.
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::{
+    asset_manager::{
+        import::{ImportError, ImportedMaterial, ImportedTexture},
+        io::Io,
+        obj_import::ObjLoader,
+    },
+    gpu::{
+        pipeline_cache::BlendMode,
+        shader::{SCENE_SHADER, ShaderKey},
+    },
+    model::{AlphaMode, MaterialParams, RenderState},
+    texture::Texture,
+};
+
+/// Contents of a `.ron` material file. Every field may be left out:
+///
+/// ```ron
+/// (
+///     shader: "shaders/shader.wgsl",
+///     defines: ["UNLIT", "TINT=2"],
+///     params: (base_color: (1.0, 0.6, 0.2, 0.5), roughness: 0.4),
+///     textures: (
+///         base_color: (path: "textures/bricks.png", sampler: (address_mode: Repeat)),
+///         normal: (path: "textures/bricks-normal.png"),
+///     ),
+///     blend: Alpha,
+///     cull: None,
+/// )
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaterialFile {
+    /// The file name when missing
+    pub name: Option<String>,
+    /// WGSL entry file, following the scene shader's interface (see [`RenderState::shader`])
+    pub shader: String,
+    /// `NAME` or `NAME=VALUE`, picks the shading model of shaders that have several
+    pub defines: Vec<String>,
+    pub params: ParamsFile,
+    pub textures: TexturesFile,
+    pub blend: Blend,
+    pub cull: Cull,
+}
+
+impl Default for MaterialFile {
+    fn default() -> Self {
+        Self {
+            name: None,
+            shader: SCENE_SHADER.to_owned(),
+            defines: Vec::new(),
+            params: ParamsFile::default(),
+            textures: TexturesFile::default(),
+            blend: Blend::default(),
+            cull: Cull::default(),
+        }
+    }
+}
+
+/// [`MaterialParams`] without the parts the blend and cull modes decide
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParamsFile {
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    /// Fragments with a lower alpha are discarded, ignored by blended materials
+    pub alpha_cutoff: Option<f32>,
+    pub ambient: [f32; 3],
+    pub specular: [f32; 3],
+    pub shininess: f32,
+    pub ior: f32,
+    pub illum: u32,
+}
+
+impl Default for ParamsFile {
+    fn default() -> Self {
+        let params = MaterialParams::default();
+        Self {
+            base_color: params.base_color,
+            metallic: params.metallic,
+            roughness: params.roughness,
+            emissive: params.emissive,
+            normal_scale: params.normal_scale,
+            occlusion_strength: params.occlusion_strength,
+            alpha_cutoff: None,
+            ambient: params.ambient,
+            specular: params.specular,
+            shininess: params.shininess,
+            ior: params.ior,
+            illum: params.illum,
+        }
+    }
+}
+
+/// Same slots as [`ImportedMaterial`]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TexturesFile {
+    pub base_color: Option<TextureFile>,
+    pub normal: Option<TextureFile>,
+    pub metallic_roughness: Option<TextureFile>,
+    pub occlusion: Option<TextureFile>,
+    pub emissive: Option<TextureFile>,
+    pub ambient: Option<TextureFile>,
+    pub specular: Option<TextureFile>,
+    pub shininess: Option<TextureFile>,
+    pub dissolve: Option<TextureFile>,
+    pub displacement: Option<TextureFile>,
+    pub reflection: Option<TextureFile>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextureFile {
+    pub path: String,
+    /// Defaults to sRGB for the color slots (base color, emissive, ambient, specular) and
+    /// linear for the data maps
+    #[serde(default)]
+    pub srgb: Option<bool>,
+    /// Only the base color's sampler is bound, the material has one sampler slot
+    #[serde(default)]
+    pub sampler: SamplerFile,
+}
+
+/// Overrides of [`Texture::default_sampler`]. `address_mode` sets both axes, `address_mode_u`
+/// and `address_mode_v` win over it.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SamplerFile {
+    pub address_mode: Option<AddressMode>,
+    pub address_mode_u: Option<AddressMode>,
+    pub address_mode_v: Option<AddressMode>,
+    pub mag_filter: Option<Filter>,
+    pub min_filter: Option<Filter>,
+    pub mipmap_filter: Option<Filter>,
+}
+
+impl SamplerFile {
+    pub fn descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        let default = Texture::default_sampler();
+        let address = |axis: Option<AddressMode>, fallback: wgpu::AddressMode| {
+            axis.or(self.address_mode)
+                .map_or(fallback, AddressMode::to_wgpu)
+        };
+        let filter = |filter: Option<Filter>, fallback: wgpu::FilterMode| {
+            filter.map_or(fallback, Filter::to_wgpu)
+        };
+        wgpu::SamplerDescriptor {
+            address_mode_u: address(self.address_mode_u, default.address_mode_u),
+            address_mode_v: address(self.address_mode_v, default.address_mode_v),
+            mag_filter: filter(self.mag_filter, default.mag_filter),
+            min_filter: filter(self.min_filter, default.min_filter),
+            mipmap_filter: match self.mipmap_filter {
+                Some(Filter::Nearest) => wgpu::MipmapFilterMode::Nearest,
+                Some(Filter::Linear) => wgpu::MipmapFilterMode::Linear,
+                None => default.mipmap_filter,
+            },
+            ..default
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum AddressMode {
+    ClampToEdge,
+    Repeat,
+    MirrorRepeat,
+}
+
+impl AddressMode {
+    fn to_wgpu(self) -> wgpu::AddressMode {
+        match self {
+            AddressMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+            AddressMode::Repeat => wgpu::AddressMode::Repeat,
+            AddressMode::MirrorRepeat => wgpu::AddressMode::MirrorRepeat,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+impl Filter {
+    fn to_wgpu(self) -> wgpu::FilterMode {
+        match self {
+            Filter::Nearest => wgpu::FilterMode::Nearest,
+            Filter::Linear => wgpu::FilterMode::Linear,
+        }
+    }
+}
+
+/// [`BlendMode`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum Blend {
+    #[default]
+    Opaque,
+    Alpha,
+    Premultiplied,
+    Additive,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum Cull {
+    None,
+    Front,
+    #[default]
+    Back,
+}
+
+/// Optional fields take the value itself, `name: "glass"` rather than `name: Some("glass")`
+pub fn parse(text: &str) -> Result<MaterialFile, ron::error::SpannedError> {
+    ron::Options::default()
+        .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+        .from_str(text)
+}
+
+/// A material file ready for upload, texture slots index into `textures`
+pub struct ImportedMaterialFile {
+    pub material: ImportedMaterial,
+    pub textures: Vec<ImportedTexture>,
+    /// Texture files, relative to the assets folder
+    pub dependencies: Vec<PathBuf>,
+}
+
+pub struct MaterialLoader;
+
+impl MaterialLoader {
+    /// Syntax errors and unknown fields are [`ImportError`]s with the line. Textures that
+    /// can't be read are replaced by the missing-texture fallback like in models.
+    pub async fn load(path: &Path) -> anyhow::Result<ImportedMaterialFile> {
+        let text = Io::load_string(path.to_string_lossy().as_ref())
+            .await
+            .map_err(|e| ImportError::new(path, None, format!("{e:#}")))?;
+        let file = parse(&text).map_err(|e| {
+            ImportError::new(path, Some(e.span.start.line as u32), e.code.to_string())
+        })?;
+
+        let mut textures = Vec::new();
+        let mut dependencies = Vec::new();
+        let mut load = async |texture: &Option<TextureFile>, srgb: bool| -> Option<usize> {
+            let texture = texture.as_ref()?;
+            let sampler = texture.sampler.descriptor();
+            let srgb = texture.srgb.unwrap_or(srgb);
+            let imported = match ObjLoader::load_texture(&texture.path, srgb).await {
+                Ok(imported) => ImportedTexture {
+                    sampler,
+                    ..imported
+                },
+                Err(e) => ImportedTexture::missing(&texture.path, &e),
+            };
+            dependencies.push(PathBuf::from(&texture.path));
+            textures.push(imported);
+            Some(textures.len() - 1)
+        };
+        let slots = &file.textures;
+        let base_color_texture = load(&slots.base_color, true).await;
+        let normal_texture = load(&slots.normal, false).await;
+        let metallic_roughness_texture = load(&slots.metallic_roughness, false).await;
+        let occlusion_texture = load(&slots.occlusion, false).await;
+        let emissive_texture = load(&slots.emissive, true).await;
+        let ambient_texture = load(&slots.ambient, true).await;
+        let specular_texture = load(&slots.specular, true).await;
+        let shininess_texture = load(&slots.shininess, false).await;
+        let dissolve_texture = load(&slots.dissolve, false).await;
+        let displacement_texture = load(&slots.displacement, false).await;
+        let reflection_texture = load(&slots.reflection, false).await;
+
+        let shader = file
+            .defines
+            .iter()
+            .fold(ShaderKey::new(&file.shader), |key, define| {
+                match define.split_once('=') {
+                    Some((name, value)) => key.define_value(name.trim(), value.trim()),
+                    None => key.define(define.trim()),
+                }
+            });
+        let file_params = &file.params;
+        let params = MaterialParams {
+            base_color: file_params.base_color,
+            metallic: file_params.metallic,
+            roughness: file_params.roughness,
+            emissive: file_params.emissive,
+            normal_scale: file_params.normal_scale,
+            occlusion_strength: file_params.occlusion_strength,
+            alpha_mode: match (file.blend, file_params.alpha_cutoff) {
+                (Blend::Opaque, Some(cutoff)) => AlphaMode::Mask(cutoff),
+                (Blend::Opaque, None) => AlphaMode::Opaque,
+                _ => AlphaMode::Blend,
+            },
+            double_sided: file.cull == Cull::None,
+            ambient: file_params.ambient,
+            specular: file_params.specular,
+            shininess: file_params.shininess,
+            ior: file_params.ior,
+            illum: file_params.illum,
+        };
+        // Same mapping as imported materials, plus the modes `MaterialParams` can't express
+        let mut render_state = RenderState {
+            shader,
+            ..RenderState::from_params(&params)
+        };
+        match file.blend {
+            Blend::Premultiplied => render_state.blend = BlendMode::Premultiplied,
+            Blend::Additive => render_state.blend = BlendMode::Additive,
+            Blend::Opaque | Blend::Alpha => {}
+        }
+        if file.cull == Cull::Front {
+            render_state.cull_mode = Some(wgpu::Face::Front);
+        }
+
+        let material = ImportedMaterial {
+            name: file.name.clone().unwrap_or_else(|| {
+                path.file_stem()
+                    .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+            }),
+            base_color_texture,
+            normal_texture,
+            metallic_roughness_texture,
+            occlusion_texture,
+            emissive_texture,
+            ambient_texture,
+            specular_texture,
+            shininess_texture,
+            dissolve_texture,
+            displacement_texture,
+            reflection_texture,
+            params,
+            render_state,
+            sampler: slots
+                .base_color
+                .as_ref()
+                .map(|texture| texture.sampler.descriptor()),
+        };
+
+        Ok(ImportedMaterialFile {
+            material,
+            textures,
+            dependencies,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn left_out_fields_take_the_defaults() {
+        let file = parse("()").unwrap();
+        assert_eq!(file.name, None);
+        assert_eq!(file.shader, SCENE_SHADER);
+        assert!(file.defines.is_empty());
+        assert_eq!(file.blend, Blend::Opaque);
+        assert_eq!(file.cull, Cull::Back);
+        assert!(file.textures.base_color.is_none());
+
+        let defaults = MaterialParams::default();
+        let params = parse("(params: (roughness: 0.25))").unwrap().params;
+        assert_eq!(params.roughness, 0.25);
+        assert_eq!(params.base_color, defaults.base_color);
+        assert_eq!(params.ior, defaults.ior);
+        assert_eq!(params.alpha_cutoff, None);
+    }
+
+    #[test]
+    fn optional_fields_take_the_value_itself() {
+        let file = parse(
+            r#"(
+                name: "glass",
+                params: (alpha_cutoff: 0.5),
+                textures: (normal: (path: "normal.png", srgb: true)),
+            )"#,
+        )
+        .unwrap();
+        assert_eq!(file.name.as_deref(), Some("glass"));
+        assert_eq!(file.params.alpha_cutoff, Some(0.5));
+        let normal = file.textures.normal.unwrap();
+        assert_eq!(normal.path, "normal.png");
+        assert_eq!(normal.srgb, Some(true));
+    }
+
+    #[test]
+    fn unknown_fields_are_errors() {
+        for text in [
+            "(colour: (1.0, 1.0, 1.0, 1.0))",
+            "(params: (rougness: 0.5))",
+            r#"(textures: (diffuse: (path: "bricks.png")))"#,
+            r#"(textures: (base_color: (path: "bricks.png", sampler: (wrap: Repeat))))"#,
+        ] {
+            let error = parse(text).unwrap_err();
+            assert!(
+                error.to_string().contains("Unexpected field"),
+                "{text}: {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn sampler_axes_win_over_the_address_mode() {
+        let default = Texture::default_sampler();
+        let sampler = SamplerFile::default().descriptor();
+        assert_eq!(sampler.address_mode_u, default.address_mode_u);
+        assert_eq!(sampler.address_mode_v, default.address_mode_v);
+        assert_eq!(sampler.mag_filter, default.mag_filter);
+
+        let sampler = SamplerFile {
+            address_mode: Some(AddressMode::Repeat),
+            address_mode_v: Some(AddressMode::MirrorRepeat),
+            mag_filter: Some(Filter::Nearest),
+            ..Default::default()
+        }
+        .descriptor();
+        assert_eq!(sampler.address_mode_u, wgpu::AddressMode::Repeat);
+        assert_eq!(sampler.address_mode_v, wgpu::AddressMode::MirrorRepeat);
+        assert_eq!(sampler.mag_filter, wgpu::FilterMode::Nearest);
+        assert_eq!(sampler.min_filter, default.min_filter);
+
+        let sampler = SamplerFile {
+            address_mode: Some(AddressMode::MirrorRepeat),
+            address_mode_u: Some(AddressMode::ClampToEdge),
+            ..Default::default()
+        }
+        .descriptor();
+        assert_eq!(sampler.address_mode_u, wgpu::AddressMode::ClampToEdge);
+        assert_eq!(sampler.address_mode_v, wgpu::AddressMode::MirrorRepeat);
+    }
+}
@@ -0,0 +1,497 @@
+// Engine-native binary copy of imported models, so large text formats are parsed once.
+// Entries live in `<cache folder>/meshes`, named after the hash of the source files and the
+// importer version; when either changes the old entry is simply never read again.
+//
+// Little endian: magic "CHUMESH\0", format version: u32, then the model. Strings are u32
+// length + UTF-8, optional indices are u32 with u32::MAX for none. Texture pixels are not
+// stored, textures come back with `data: None` for the importer to load from `source`.
+use std::path::PathBuf;
+
+use anyhow::{Context, bail, ensure};
+
+use crate::{
+    asset_manager::{
+        import::{ImportedMaterial, ImportedMesh, ImportedModel, ImportedTexture},
+        io::get_cache_folder,
+    },
+    model::{AlphaMode, Bounds, MaterialParams, ModelVertex, RenderState},
+    texture::Texture,
+};
+
+const MAGIC: [u8; 8] = *b"CHUMESH\0";
+const FORMAT_VERSION: u32 = 3;
+const NONE: u32 = u32::MAX;
+
+/// Hash of everything the import reads. `importer_version` is bumped whenever an importer
+/// changes its output.
+pub(crate) fn key<'a>(importer_version: u32, sources: impl IntoIterator<Item = &'a [u8]>) -> u64 {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    hasher.update(&importer_version.to_le_bytes());
+    for source in sources {
+        hasher.update(&(source.len() as u64).to_le_bytes());
+        hasher.update(source);
+    }
+    hasher.digest()
+}
+
+fn entry_path(key: u64) -> anyhow::Result<PathBuf> {
+    Ok(get_cache_folder()?
+        .join("meshes")
+        .join(format!("{key:016x}.mesh")))
+}
+
+/// `None` on a miss. A corrupt entry is logged and treated as a miss.
+pub(crate) fn load(key: u64) -> Option<ImportedModel> {
+    let path = entry_path(key).ok()?;
+    let bytes = std::fs::read(&path).ok()?;
+    match decode(&bytes) {
+        Ok(model) => {
+            tracing::debug!("Mesh cache hit {}", path.display());
+            Some(model)
+        }
+        Err(e) => {
+            tracing::warn!("Ignoring mesh cache entry {}: {e:#}", path.display());
+            None
+        }
+    }
+}
+
+/// Best effort, the import already succeeded and a failed write only costs the next start
+pub(crate) fn store(key: u64, model: &ImportedModel) {
+    let result = entry_path(key).and_then(|path| {
+        if model
+            .meshes
+            .iter()
+            .any(|m| m.skin.is_some() || !m.morph_targets.is_empty())
+        {
+            bail!("skinned and morphed meshes are not cached");
+        }
+        std::fs::create_dir_all(path.parent().expect("entry is inside the cache folder"))?;
+        std::fs::write(&path, encode(model))?;
+        Ok(path)
+    });
+    match result {
+        Ok(path) => tracing::debug!("Mesh cache wrote {}", path.display()),
+        Err(e) => tracing::debug!("Mesh cache not written for {}: {e:#}", model.name),
+    }
+}
+
+fn encode(model: &ImportedModel) -> Vec<u8> {
+    let mut out = Encoder(MAGIC.to_vec());
+    out.u32(FORMAT_VERSION);
+    out.str(&model.name);
+
+    out.u32(model.dependencies.len() as u32);
+    for dependency in &model.dependencies {
+        out.str(&dependency.to_string_lossy());
+    }
+
+    out.u32(model.textures.len() as u32);
+    for texture in &model.textures {
+        out.str(&texture.label);
+        out.str(
+            &texture
+                .source
+                .as_ref()
+                .map_or(String::new(), |s| s.to_string_lossy().into_owned()),
+        );
+        out.u8(texture.srgb as u8);
+    }
+
+    out.u32(model.materials.len() as u32);
+    for material in &model.materials {
+        out.str(&material.name);
+        for slot in [
+            material.base_color_texture,
+            material.normal_texture,
+            material.metallic_roughness_texture,
+            material.occlusion_texture,
+            material.emissive_texture,
+            material.ambient_texture,
+            material.specular_texture,
+            material.shininess_texture,
+            material.dissolve_texture,
+            material.displacement_texture,
+            material.reflection_texture,
+        ] {
+            out.u32(slot.map_or(NONE, |i| i as u32));
+        }
+        let params = &material.params;
+        out.f32s(&params.base_color);
+        out.f32s(&[params.metallic, params.roughness]);
+        out.f32s(&params.emissive);
+        out.f32s(&[params.normal_scale, params.occlusion_strength]);
+        match params.alpha_mode {
+            AlphaMode::Opaque => out.u8(0),
+            AlphaMode::Mask(cutoff) => {
+                out.u8(1);
+                out.f32s(&[cutoff]);
+            }
+            AlphaMode::Blend => out.u8(2),
+        }
+        out.u8(params.double_sided as u8);
+        out.f32s(&params.ambient);
+        out.f32s(&params.specular);
+        out.f32s(&[params.shininess, params.ior]);
+        out.u32(params.illum);
+    }
+
+    out.u32(model.meshes.len() as u32);
+    for mesh in &model.meshes {
+        out.str(&mesh.name);
+        out.u32(mesh.material as u32);
+        out.f32s(&mesh.bounds.min);
+        out.f32s(&mesh.bounds.max);
+        out.u32(mesh.vertices.len() as u32);
+        out.0
+            .extend_from_slice(bytemuck::cast_slice(&mesh.vertices));
+        out.u32(mesh.indices.len() as u32);
+        out.0.extend_from_slice(bytemuck::cast_slice(&mesh.indices));
+    }
+    out.0
+}
+
+fn decode(bytes: &[u8]) -> anyhow::Result<ImportedModel> {
+    let mut input = Decoder(bytes);
+    ensure!(input.take(MAGIC.len())? == MAGIC, "not a mesh cache entry");
+    let version = input.u32()?;
+    ensure!(version == FORMAT_VERSION, "format version {version}");
+    let name = input.str()?;
+
+    let dependencies = (0..input.u32()?)
+        .map(|_| Ok(PathBuf::from(input.str()?)))
+        .collect::<anyhow::Result<_>>()?;
+
+    let textures = (0..input.u32()?)
+        .map(|_| {
+            let label = input.str()?;
+            let source = Some(input.str()?)
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from);
+            Ok(ImportedTexture {
+                label,
+                data: None,
+                source,
+                srgb: input.u8()? != 0,
+                float_format: wgpu::TextureFormat::Rgba16Float,
+                sampler: Texture::default_sampler(),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let materials = (0..input.u32()?)
+        .map(|_| {
+            let name = input.str()?;
+            let mut slot = || -> anyhow::Result<Option<usize>> {
+                let index = input.u32()?;
+                ensure!(
+                    index == NONE || (index as usize) < textures.len(),
+                    "texture index {index} out of range"
+                );
+                Ok((index != NONE).then_some(index as usize))
+            };
+            let (base_color_texture, normal_texture, metallic_roughness_texture) =
+                (slot()?, slot()?, slot()?);
+            let (occlusion_texture, emissive_texture) = (slot()?, slot()?);
+            let (ambient_texture, specular_texture, shininess_texture) =
+                (slot()?, slot()?, slot()?);
+            let (dissolve_texture, displacement_texture, reflection_texture) =
+                (slot()?, slot()?, slot()?);
+            let [base_color] = input.f32s::<4, 1>()?;
+            let [metallic, roughness] = input.f32s::<1, 2>()?.map(|[v]| v);
+            let [emissive] = input.f32s::<3, 1>()?;
+            let [normal_scale, occlusion_strength] = input.f32s::<1, 2>()?.map(|[v]| v);
+            let alpha_mode = match input.u8()? {
+                0 => AlphaMode::Opaque,
+                1 => AlphaMode::Mask(input.f32s::<1, 1>()?[0][0]),
+                2 => AlphaMode::Blend,
+                other => bail!("alpha mode {other}"),
+            };
+            let double_sided = input.u8()? != 0;
+            let [ambient, specular] = input.f32s::<3, 2>()?;
+            let [shininess, ior] = input.f32s::<1, 2>()?.map(|[v]| v);
+            let illum = input.u32()?;
+            let params = MaterialParams {
+                base_color,
+                metallic,
+                roughness,
+                emissive,
+                normal_scale,
+                occlusion_strength,
+                alpha_mode,
+                double_sided,
+                ambient,
+                specular,
+                shininess,
+                ior,
+                illum,
+            };
+            Ok(ImportedMaterial {
+                name,
+                base_color_texture,
+                normal_texture,
+                metallic_roughness_texture,
+                occlusion_texture,
+                emissive_texture,
+                ambient_texture,
+                specular_texture,
+                shininess_texture,
+                dissolve_texture,
+                displacement_texture,
+                reflection_texture,
+                // Not stored, importers derive it from the params alone
+                render_state: RenderState::from_params(&params),
+                params,
+                ..Default::default()
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let meshes = (0..input.u32()?)
+        .map(|_| {
+            let name = input.str()?;
+            let material = input.u32()? as usize;
+            ensure!(
+                material < materials.len(),
+                "{name}: material {material} out of range"
+            );
+            let [min, max] = input.f32s::<3, 2>()?;
+            let vertex_count = input.u32()? as usize;
+            let vertices: Vec<ModelVertex> = bytemuck::pod_collect_to_vec(
+                input.take(vertex_count * std::mem::size_of::<ModelVertex>())?,
+            );
+            let index_count = input.u32()? as usize;
+            let indices: Vec<u32> = bytemuck::pod_collect_to_vec(input.take(index_count * 4)?);
+            ensure!(
+                indices.iter().all(|&i| (i as usize) < vertex_count),
+                "{name}: index out of range"
+            );
+            Ok(ImportedMesh {
+                name,
+                vertices,
+                indices,
+                material,
+                bounds: Bounds { min, max },
+                skin: None,
+                morph_targets: Vec::new(),
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+    ensure!(input.0.is_empty(), "trailing bytes");
+
+    Ok(ImportedModel {
+        name,
+        meshes,
+        materials,
+        textures,
+        dependencies,
+    })
+}
+
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn f32s(&mut self, values: &[f32]) {
+        for value in values {
+            self.0.extend(value.to_le_bytes());
+        }
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.0.extend(value.as_bytes());
+    }
+}
+
+struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        ensure!(self.0.len() >= len, "truncated");
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    /// `M` arrays of `N` floats
+    fn f32s<const N: usize, const M: usize>(&mut self) -> anyhow::Result<[[f32; N]; M]> {
+        let mut values = [[0.0; N]; M];
+        for value in values.iter_mut().flatten() {
+            *value = f32::from_le_bytes(self.take(4)?.try_into()?);
+        }
+        Ok(values)
+    }
+
+    fn str(&mut self) -> anyhow::Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).context("invalid UTF-8")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: [f32; 3]) -> ModelVertex {
+        ModelVertex {
+            position,
+            tex_coords: [position[0], position[1]],
+            normal: [0.0, 0.0, 1.0],
+            color: ModelVertex::NO_COLOR,
+        }
+    }
+
+    fn model() -> ImportedModel {
+        let vertices = vec![
+            vertex([0.0, 0.0, 0.0]),
+            vertex([1.0, 0.0, 0.0]),
+            vertex([0.0, 1.0, 0.0]),
+        ];
+        ImportedModel {
+            name: "model.obj".to_owned(),
+            meshes: vec![ImportedMesh {
+                name: "triangle".to_owned(),
+                bounds: Bounds::from_vertices(&vertices),
+                vertices,
+                indices: vec![0, 1, 2],
+                material: 1,
+                skin: None,
+                morph_targets: Vec::new(),
+            }],
+            materials: vec![
+                ImportedMaterial {
+                    name: "cutout".to_owned(),
+                    base_color_texture: Some(0),
+                    params: MaterialParams {
+                        alpha_mode: AlphaMode::Mask(0.25),
+                        illum: 2,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                ImportedMaterial {
+                    name: "glass".to_owned(),
+                    dissolve_texture: Some(0),
+                    params: MaterialParams {
+                        base_color: [0.5, 0.5, 1.0, 0.3],
+                        alpha_mode: AlphaMode::Blend,
+                        double_sided: true,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            ],
+            textures: vec![ImportedTexture {
+                label: "bricks".to_owned(),
+                data: None,
+                source: Some(PathBuf::from("textures/bricks.png")),
+                srgb: true,
+                float_format: wgpu::TextureFormat::Rgba16Float,
+                sampler: Texture::default_sampler(),
+            }],
+            dependencies: vec![PathBuf::from("model.mtl")],
+        }
+    }
+
+    #[test]
+    fn decode_returns_what_was_encoded() {
+        let model = model();
+        let decoded = decode(&encode(&model)).unwrap();
+        assert_eq!(decoded.name, model.name);
+        assert_eq!(decoded.dependencies, model.dependencies);
+
+        let [texture] = decoded.textures.as_slice() else {
+            panic!("one texture");
+        };
+        assert_eq!(texture.label, "bricks");
+        assert_eq!(texture.source, model.textures[0].source);
+        assert!(texture.srgb);
+
+        for (decoded, material) in decoded.materials.iter().zip(&model.materials) {
+            assert_eq!(decoded.name, material.name);
+            assert_eq!(decoded.base_color_texture, material.base_color_texture);
+            assert_eq!(decoded.dissolve_texture, material.dissolve_texture);
+            assert_eq!(decoded.normal_texture, None);
+            assert_eq!(decoded.params, material.params);
+            assert_eq!(
+                decoded.render_state,
+                RenderState::from_params(&material.params)
+            );
+        }
+
+        let [mesh] = decoded.meshes.as_slice() else {
+            panic!("one mesh");
+        };
+        let original = &model.meshes[0];
+        assert_eq!(mesh.name, original.name);
+        assert_eq!(mesh.material, 1);
+        assert_eq!(mesh.bounds, original.bounds);
+        assert_eq!(mesh.indices, original.indices);
+        assert_eq!(
+            bytemuck::cast_slice::<_, u8>(&mesh.vertices),
+            bytemuck::cast_slice::<_, u8>(&original.vertices)
+        );
+    }
+
+    #[test]
+    fn truncated_entries_are_rejected() {
+        let bytes = encode(&model());
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "decoded {len} bytes");
+        }
+        let mut longer = bytes.clone();
+        longer.push(0);
+        let error = decode(&longer).err().unwrap();
+        assert_eq!(error.to_string(), "trailing bytes");
+    }
+
+    #[test]
+    fn other_files_and_versions_are_rejected() {
+        let mut bytes = encode(&model());
+        bytes[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        let error = decode(&bytes).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!("format version {}", FORMAT_VERSION + 1)
+        );
+
+        let error = decode(b"CHUMESH").err().unwrap();
+        assert_eq!(error.to_string(), "truncated");
+        let error = decode(b"not a cache entry").err().unwrap();
+        assert_eq!(error.to_string(), "not a mesh cache entry");
+    }
+
+    #[test]
+    fn out_of_range_indices_are_rejected() {
+        let mut broken = model();
+        broken.materials[1].normal_texture = Some(1);
+        let error = decode(&encode(&broken)).err().unwrap();
+        assert_eq!(error.to_string(), "texture index 1 out of range");
+
+        let mut broken = model();
+        broken.meshes[0].material = 2;
+        let error = decode(&encode(&broken)).err().unwrap();
+        assert_eq!(error.to_string(), "triangle: material 2 out of range");
+
+        let mut broken = model();
+        broken.meshes[0].indices[2] = 3;
+        let error = decode(&encode(&broken)).err().unwrap();
+        assert_eq!(error.to_string(), "triangle: index out of range");
+    }
+}
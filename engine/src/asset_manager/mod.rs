@@ -4,45 +4,615 @@ use std::{
     sync::Arc,
 };
 
+use anyhow::{Context as _, bail};
+
 use crate::{
-    asset_manager::obj_import::ObjLoader, gpu::context::GpuContext, model::Model, texture::Texture,
+    animation::{clip::AnimationClip, skeleton::Skeleton},
+    asset_manager::{
+        fallback::FallbackTextures,
+        gltf_import::{GltfLoader, GltfScene, ImportedScene},
+        handle::{Assets, Handle},
+        import::{
+            ImportedTexture, ModelFormat, TextureData, upload_materials, upload_mesh,
+            upload_textures,
+        },
+        io::Io,
+        loader::{Decoded, LoadQueue, LoadStatus},
+        material_file::{ImportedMaterialFile, MaterialLoader},
+        obj_import::ObjImportOptions,
+        placeholder::placeholder_cube,
+        texture_cache::{TextureCache, TextureMemory},
+    },
+    gpu::context::GpuContext,
+    model::{Material, Model, MorphTargets},
+    texture::Texture,
 };
 
-pub(crate) mod io;
+pub mod archive;
+mod block_decode;
+pub mod compressed;
+pub mod fallback;
+pub mod gltf_import;
+pub mod handle;
+pub mod import;
+pub mod io;
+pub mod loader;
+pub mod material_file;
+mod mesh_cache;
+pub mod normals;
 pub mod obj_import;
+pub mod optimize;
+pub mod placeholder;
+pub mod ply_import;
+mod reload;
+pub mod source;
+pub mod stl_import;
+pub mod texture_cache;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod watcher;
 
 pub struct AssetManager {
     pub gpu_context: Arc<GpuContext>,
-    pub model_cache: HashMap<PathBuf, Arc<Model>>,
-    texture_layout: wgpu::BindGroupLayout,
+    models: Assets<Model>,
+    scenes: Assets<GltfScene>,
+    skeletons: Assets<Skeleton>,
+    animations: Assets<AnimationClip>,
+    materials: Assets<Material>,
+    pub model_cache: HashMap<PathBuf, Handle<Model>>,
+    pub scene_cache: HashMap<PathBuf, Handle<GltfScene>>,
+    pub material_cache: HashMap<PathBuf, Handle<Material>>,
+    pub texture_cache: TextureCache,
+    /// Used by every OBJ load started after a change, including hot reloads
+    pub obj_options: ObjImportOptions,
+    material_layout: wgpu::BindGroupLayout,
+    morph_layout: wgpu::BindGroupLayout,
+    loads: LoadQueue,
+    load_status: HashMap<PathBuf, LoadStatus>,
+    fallbacks: FallbackTextures,
+    // Shared by every model slot that is still loading
+    placeholder: Arc<Model>,
+    // Debug builds watch loaded files and swap in re-exported versions
+    #[cfg(not(target_arch = "wasm32"))]
+    hot_reload: Option<reload::HotReload>,
 }
 
 impl AssetManager {
     pub fn new(gpu_context: Arc<GpuContext>) -> Self {
-        let model_cache: HashMap<PathBuf, Arc<Model>> = HashMap::new();
-        let texture_layout = Texture::create_bind_group_layout(&gpu_context.device);
+        let model_cache: HashMap<PathBuf, Handle<Model>> = HashMap::new();
+        let material_layout = Material::create_bind_group_layout(&gpu_context.device);
+        let morph_layout = MorphTargets::create_bind_group_layout(&gpu_context.device);
+
+        let mut texture_cache = TextureCache::default();
+        let fallbacks = FallbackTextures::new(&gpu_context, &mut texture_cache)
+            .expect("fallback textures are built in code");
+        let placeholder = placeholder_cube()
+            .upload(
+                &gpu_context,
+                &material_layout,
+                &morph_layout,
+                &mut texture_cache,
+                &fallbacks,
+            )
+            .expect("placeholder model is built in code");
+        // The placeholder is never released, neither are its textures
+        placeholder
+            .materials
+            .iter()
+            .flat_map(|m| m.textures())
+            .for_each(|texture| texture_cache.acquire(texture));
 
         Self {
             gpu_context,
+            models: Assets::default(),
+            scenes: Assets::default(),
+            skeletons: Assets::default(),
+            animations: Assets::default(),
+            materials: Assets::default(),
             model_cache,
-            texture_layout,
+            scene_cache: HashMap::new(),
+            material_cache: HashMap::new(),
+            texture_cache,
+            obj_options: ObjImportOptions::default(),
+            material_layout,
+            morph_layout,
+            loads: LoadQueue::new(),
+            load_status: HashMap::new(),
+            fallbacks,
+            placeholder: Arc::new(placeholder),
+            #[cfg(not(target_arch = "wasm32"))]
+            hot_reload: cfg!(debug_assertions).then(reload::HotReload::default),
+        }
+    }
+
+    /// Loads an OBJ, PLY or STL file, the importer is picked by extension (see
+    /// [`ModelFormat`]).
+    pub async fn load_model(&mut self, path: impl AsRef<Path>) -> anyhow::Result<Handle<Model>> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(&handle) = self.model_cache.get(&path)
+            && self.models.contains(handle)
+        {
+            return Ok(handle);
         }
+
+        let mut imported = ModelFormat::import(&path, &self.obj_options).await?;
+        let dependencies = std::mem::take(&mut imported.dependencies);
+        let model = imported.upload(
+            &self.gpu_context,
+            &self.material_layout,
+            &self.morph_layout,
+            &mut self.texture_cache,
+            &self.fallbacks,
+        )?;
+        let handle = self.store_model(None, Arc::new(model));
+
+        self.watch(&path, &dependencies);
+        self.model_cache.insert(path, handle);
+
+        Ok(handle)
     }
-    pub async fn load_obj(&mut self, path: impl AsRef<Path>) -> anyhow::Result<Arc<Model>> {
+
+    /// Starts decoding a model file in the background and returns right away. The handle
+    /// draws a placeholder cube until [`Self::poll_loads`] swaps the model in, and keeps
+    /// drawing it if the load fails. Progress is reported by [`Self::load_status`].
+    pub fn load_model_async(&mut self, path: impl AsRef<Path>) -> Handle<Model> {
         let path = path.as_ref().to_path_buf();
-        if let Some(model) = self.model_cache.get(&path) {
-            return Ok(model.clone());
+        let cached = self
+            .model_cache
+            .get(&path)
+            .copied()
+            .filter(|&handle| self.models.contains(handle));
+        // A failed load is retried into the same handle
+        let handle = match cached {
+            Some(handle) if !matches!(self.load_status.get(&path), Some(LoadStatus::Failed(_))) => {
+                return handle;
+            }
+            Some(handle) => handle,
+            None => self.store_model(None, self.placeholder.clone()),
+        };
+        self.model_cache.insert(path.clone(), handle);
+        self.spawn_model_load(path, handle);
+        handle
+    }
+
+    fn spawn_model_load(&mut self, path: PathBuf, handle: Handle<Model>) {
+        self.load_status.insert(path.clone(), LoadStatus::Loading);
+        let options = self.obj_options.clone();
+        self.loads.spawn(move || async move {
+            let result = ModelFormat::import(&path, &options).await;
+            Decoded::Model {
+                path,
+                handle,
+                result,
+            }
+        });
+    }
+
+    /// Uploads the background loads that finished since the last call and queues reloads of
+    /// files that changed on disk. Call once per frame.
+    pub fn poll_loads(&mut self) {
+        self.queue_reloads();
+
+        let finished: Vec<Decoded> = self.loads.finished().collect();
+        for decoded in finished {
+            match decoded {
+                Decoded::Model {
+                    path,
+                    handle,
+                    result,
+                } => {
+                    let Some(previous) = self.models.get(handle).cloned() else {
+                        // Removed while the file was decoding
+                        continue;
+                    };
+                    let status = match result
+                        .and_then(|imported| self.replace_model(&path, handle, imported))
+                    {
+                        Ok(()) => {
+                            tracing::info!("Loaded {}", path.display());
+                            LoadStatus::Loaded
+                        }
+                        Err(e) if Arc::ptr_eq(&previous, &self.placeholder) => {
+                            tracing::error!("Loading {}: {e:#}", path.display());
+                            LoadStatus::Failed(format!("{e:#}"))
+                        }
+                        Err(e) => {
+                            tracing::error!(
+                                "Reloading {} failed, keeping the previous version: {e:#}",
+                                path.display()
+                            );
+                            LoadStatus::Failed(format!("{e:#}"))
+                        }
+                    };
+                    self.load_status.insert(path, status);
+                }
+                Decoded::Scene {
+                    path,
+                    handle,
+                    result,
+                } => {
+                    if !self.scenes.contains(handle) {
+                        continue;
+                    }
+                    let status = match result
+                        .and_then(|imported| self.replace_scene(&path, handle, imported))
+                    {
+                        Ok(()) => {
+                            tracing::info!("Reloaded {}", path.display());
+                            LoadStatus::Loaded
+                        }
+                        Err(e) => {
+                            tracing::error!(
+                                "Reloading {} failed, keeping the previous version: {e:#}",
+                                path.display()
+                            );
+                            LoadStatus::Failed(format!("{e:#}"))
+                        }
+                    };
+                    self.load_status.insert(path, status);
+                }
+                Decoded::Material {
+                    path,
+                    handle,
+                    result,
+                } => {
+                    if !self.materials.contains(handle) {
+                        continue;
+                    }
+                    let status = match result
+                        .and_then(|imported| self.replace_material(&path, handle, *imported))
+                    {
+                        Ok(()) => {
+                            tracing::info!("Reloaded {}", path.display());
+                            LoadStatus::Loaded
+                        }
+                        Err(e) => {
+                            tracing::error!(
+                                "Reloading {} failed, keeping the previous version: {e:#}",
+                                path.display()
+                            );
+                            LoadStatus::Failed(format!("{e:#}"))
+                        }
+                    };
+                    self.load_status.insert(path, status);
+                }
+            }
+        }
+    }
+
+    /// `None` for paths that were never loaded with one of the `_async` methods.
+    pub fn load_status(&self, path: impl AsRef<Path>) -> Option<&LoadStatus> {
+        self.load_status.get(path.as_ref())
+    }
+
+    /// Status of every background load, for the UI
+    pub fn load_statuses(&self) -> impl Iterator<Item = (&Path, &LoadStatus)> {
+        self.load_status
+            .iter()
+            .map(|(path, status)| (path.as_path(), status))
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.load_status
+            .values()
+            .any(|status| *status == LoadStatus::Loading)
+    }
+
+    /// Loads a `.gltf`/`.glb` file. Each glTF mesh becomes a [`Model`], the node tree can be
+    /// spawned into a world with [`GltfScene::spawn`].
+    pub async fn load_gltf(&mut self, path: impl AsRef<Path>) -> anyhow::Result<Handle<GltfScene>> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(&handle) = self.scene_cache.get(&path)
+            && self.scenes.contains(handle)
+        {
+            return Ok(handle);
+        }
+
+        let mut imported = GltfLoader::load_scene(&path).await?;
+        let dependencies = std::mem::take(&mut imported.dependencies);
+        let scene = self.upload_scene(imported, None)?;
+        let handle = self.scenes.insert(scene);
+
+        self.watch(&path, &dependencies);
+        self.scene_cache.insert(path, handle);
+
+        Ok(handle)
+    }
+
+    /// With `previous`, its model, skeleton and animation handles are reused in order so
+    /// spawned entities pick up the new data.
+    fn upload_scene(
+        &mut self,
+        imported: ImportedScene,
+        previous: Option<&GltfScene>,
+    ) -> anyhow::Result<GltfScene> {
+        let texture_handles = upload_textures(
+            &self.gpu_context,
+            imported.textures,
+            &mut self.texture_cache,
+            &self.fallbacks,
+        )?;
+        let materials = upload_materials(
+            &self.gpu_context,
+            &self.material_layout,
+            imported.materials,
+            &texture_handles,
+            &mut self.texture_cache,
+            &self.fallbacks,
+        )?;
+
+        let mut models = Vec::new();
+        for (index, (_, primitives)) in imported.meshes.iter().enumerate() {
+            let model = Model {
+                meshes: primitives
+                    .iter()
+                    .map(|mesh| upload_mesh(&self.gpu_context.device, &self.morph_layout, mesh))
+                    .collect(),
+                materials: materials.clone(),
+            };
+            let previous = previous.and_then(|scene| scene.models.get(index).copied());
+            models.push(self.store_model(previous, Arc::new(model)));
+        }
+
+        let skeletons = imported
+            .skeletons
+            .into_iter()
+            .enumerate()
+            .map(|(index, skeleton)| {
+                let previous = previous.and_then(|scene| scene.skeletons.get(index).copied());
+                insert_or_replace(&mut self.skeletons, previous, skeleton).0
+            })
+            .collect();
+        let animations = imported
+            .animations
+            .into_iter()
+            .enumerate()
+            .map(|(index, clip)| {
+                let previous = previous.and_then(|scene| scene.animations.get(index).copied());
+                insert_or_replace(&mut self.animations, previous, clip).0
+            })
+            .collect();
+
+        Ok(GltfScene {
+            name: imported.name,
+            models,
+            skeletons,
+            animations,
+            nodes: imported.nodes,
+            roots: imported.roots,
+        })
+    }
+
+    pub fn gltf_scene(&self, handle: Handle<GltfScene>) -> Option<&Arc<GltfScene>> {
+        self.scenes.get(handle)
+    }
+
+    /// Loads a RON material file (see [`MaterialFile`](material_file::MaterialFile)).
+    /// Entities draw their model with it through
+    /// [`MaterialOverride`](crate::components::MaterialOverride).
+    pub async fn load_material(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<Handle<Material>> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(&handle) = self.material_cache.get(&path)
+            && self.materials.contains(handle)
+        {
+            return Ok(handle);
+        }
+
+        let mut imported = MaterialLoader::load(&path).await?;
+        let dependencies = std::mem::take(&mut imported.dependencies);
+        let material = self.upload_material(imported)?;
+        let handle = self.store_material(None, material);
+
+        self.watch(&path, &dependencies);
+        self.material_cache.insert(path, handle);
+
+        Ok(handle)
+    }
+
+    fn upload_material(&mut self, imported: ImportedMaterialFile) -> anyhow::Result<Material> {
+        let texture_handles = upload_textures(
+            &self.gpu_context,
+            imported.textures,
+            &mut self.texture_cache,
+            &self.fallbacks,
+        )?;
+        let mut materials = upload_materials(
+            &self.gpu_context,
+            &self.material_layout,
+            vec![imported.material],
+            &texture_handles,
+            &mut self.texture_cache,
+            &self.fallbacks,
+        )?;
+        Ok(materials.remove(0))
+    }
+
+    /// Like [`Self::store_model`], the material holds a reference to each of its textures.
+    fn store_material(
+        &mut self,
+        slot: Option<Handle<Material>>,
+        material: Material,
+    ) -> Handle<Material> {
+        for texture in material.textures() {
+            self.texture_cache.acquire(texture);
+        }
+        let (handle, old) = insert_or_replace(&mut self.materials, slot, material);
+        for texture in old.iter().flat_map(|m| m.textures()) {
+            self.texture_cache.release(texture);
+        }
+        handle
+    }
+
+    /// `None` for a stale handle
+    pub fn material(&self, handle: Handle<Material>) -> Option<&Arc<Material>> {
+        self.materials.get(handle)
+    }
+
+    /// Drops the material and releases its textures. Entities still overriding with it draw
+    /// their model's own materials.
+    pub fn remove_material(&mut self, handle: Handle<Material>) -> Option<Arc<Material>> {
+        let material = self.materials.remove(handle)?;
+        self.material_cache.retain(|path, h| {
+            let keep = *h != handle;
+            if !keep {
+                self.load_status.remove(path);
+            }
+            keep
+        });
+        for texture in material.textures() {
+            self.texture_cache.release(texture);
+        }
+        Some(material)
+    }
+
+    pub fn add_model(&mut self, model: Model) -> Handle<Model> {
+        self.store_model(None, Arc::new(model))
+    }
+
+    /// The texture is not reference counted until a model uses it
+    pub fn add_texture(&mut self, texture: Texture) -> Handle<Texture> {
+        self.texture_cache.insert(texture)
+    }
+
+    /// Loads a Radiance `.hdr` or OpenEXR file keeping its range, for environment maps,
+    /// lightmaps and data textures. `format` is `Rgba16Float` or `Rgba32Float` (filterable
+    /// only with `Features::FLOAT32_FILTERABLE`). The handle holds a reference in the
+    /// texture cache, release it there when done.
+    pub async fn load_hdr_texture(
+        &mut self,
+        path: impl AsRef<Path>,
+        format: wgpu::TextureFormat,
+    ) -> anyhow::Result<Handle<Texture>> {
+        let path = path.as_ref();
+        if !matches!(
+            format,
+            wgpu::TextureFormat::Rgba16Float | wgpu::TextureFormat::Rgba32Float
+        ) {
+            bail!("{format:?} is not a floating-point RGBA format");
+        }
+        let bytes = Io::load_binary(path).await?;
+        let imported = ImportedTexture {
+            label: path.to_string_lossy().into_owned(),
+            data: Some(TextureData::decode(&bytes).with_context(|| format!("Decoding {path:?}"))?),
+            source: Some(path.to_owned()),
+            srgb: false,
+            float_format: format,
+            sampler: wgpu::SamplerDescriptor {
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..Texture::default_sampler()
+            },
+        };
+        let data = imported.data.as_ref().expect("decoded above");
+        let handle = self
+            .texture_cache
+            .get_or_upload(&self.gpu_context, &imported, data)?;
+        self.texture_cache.acquire(handle);
+        Ok(handle)
+    }
+
+    /// Puts `model` into `slot` if it is still alive, a new slot otherwise. The model holds a
+    /// reference to each of its textures, the one it replaces hands its references back.
+    fn store_model(&mut self, slot: Option<Handle<Model>>, model: Arc<Model>) -> Handle<Model> {
+        for texture in model.materials.iter().flat_map(|m| m.textures()) {
+            self.texture_cache.acquire(texture);
+        }
+        match slot.filter(|&handle| self.models.contains(handle)) {
+            Some(handle) => {
+                if let Some(old) = self.models.replace(handle, model) {
+                    self.release_textures(&old);
+                }
+                handle
+            }
+            None => self.models.insert_shared(model),
         }
+    }
+
+    /// `None` for a stale handle, i.e. the model has been removed since the handle was issued.
+    pub fn model(&self, handle: Handle<Model>) -> Option<&Arc<Model>> {
+        self.models.get(handle)
+    }
 
-        let model =
-            Arc::new(ObjLoader::load_model(&path, &self.gpu_context, &self.texture_layout).await?);
+    pub fn texture(&self, handle: Handle<Texture>) -> Option<&Arc<Texture>> {
+        self.texture_cache.get(handle)
+    }
 
-        self.model_cache.insert(path, model.clone());
+    /// Drops the model and releases its textures, the ones no other model uses are freed.
+    /// Outstanding handles to them become stale.
+    pub fn remove_model(&mut self, handle: Handle<Model>) -> Option<Arc<Model>> {
+        let model = self.models.remove(handle)?;
+        self.model_cache.retain(|path, h| {
+            let keep = *h != handle;
+            if !keep {
+                self.load_status.remove(path);
+            }
+            keep
+        });
 
-        Ok(model)
+        self.release_textures(&model);
+        Some(model)
     }
 
-    pub fn texture_layout(&self) -> &wgpu::BindGroupLayout {
-        &self.texture_layout
+    fn release_textures(&mut self, model: &Model) {
+        for texture in model.materials.iter().flat_map(|m| m.textures()) {
+            self.texture_cache.release(texture);
+        }
+    }
+
+    pub fn skeleton(&self, handle: Handle<Skeleton>) -> Option<&Arc<Skeleton>> {
+        self.skeletons.get(handle)
+    }
+
+    pub fn animation(&self, handle: Handle<AnimationClip>) -> Option<&Arc<AnimationClip>> {
+        self.animations.get(handle)
+    }
+
+    pub fn add_skeleton(&mut self, skeleton: Skeleton) -> Handle<Skeleton> {
+        self.skeletons.insert(skeleton)
+    }
+
+    pub fn add_animation(&mut self, clip: AnimationClip) -> Handle<AnimationClip> {
+        self.animations.insert(clip)
+    }
+
+    pub fn animations(&self) -> &Assets<AnimationClip> {
+        &self.animations
+    }
+
+    pub fn models(&self) -> &Assets<Model> {
+        &self.models
+    }
+
+    pub fn textures(&self) -> &Assets<Texture> {
+        self.texture_cache.textures()
+    }
+
+    pub fn texture_memory(&self) -> TextureMemory {
+        self.texture_cache.memory()
+    }
+
+    pub fn fallback_textures(&self) -> &FallbackTextures {
+        &self.fallbacks
+    }
+
+    pub fn material_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.material_layout
+    }
+}
+
+/// Reuses `previous` when it is still alive, returns the value it held.
+fn insert_or_replace<T>(
+    assets: &mut Assets<T>,
+    previous: Option<Handle<T>>,
+    value: T,
+) -> (Handle<T>, Option<Arc<T>>) {
+    match previous.filter(|&handle| assets.contains(handle)) {
+        Some(handle) => {
+            let old = assets.replace(handle, value);
+            (handle, old)
+        }
+        None => (assets.insert(value), None),
     }
 }
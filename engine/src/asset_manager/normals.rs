@@ -0,0 +1,249 @@
+// Vertex normals for meshes that come without them. Corners are matched by position, not by
+// index, so UV seams do not show up as hard edges; vertices are split where the corners
+// sharing them end up with different normals.
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace as _, Vector3, Zero as _};
+
+use crate::model::ModelVertex;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GeneratedNormals {
+    /// Average of the faces around a vertex, weighted by their angle at it. Faces meeting at more than
+    /// `crease_angle` degrees keep a hard edge, 180 smooths everything.
+    Smooth { crease_angle: f32 },
+    /// Every triangle gets its own face normal
+    Flat,
+}
+
+impl Default for GeneratedNormals {
+    fn default() -> Self {
+        Self::Smooth { crease_angle: 60.0 }
+    }
+}
+
+/// Overwrites the normals of a triangle list, adding vertices where a corner needs a normal
+/// the vertex does not have
+pub fn generate_normals(
+    vertices: &mut Vec<ModelVertex>,
+    indices: &mut [u32],
+    mode: GeneratedNormals,
+) {
+    let position = |index: u32| Vector3::from(vertices[index as usize].position);
+    let face_normals: Vec<Vector3<f32>> = indices
+        .chunks_exact(3)
+        .map(|c| (position(c[1]) - position(c[0])).cross(position(c[2]) - position(c[0])))
+        .collect();
+    let unit = |n: Vector3<f32>| {
+        if n.magnitude2() > 0.0 {
+            n.normalize()
+        } else {
+            n
+        }
+    };
+
+    let corner_normals: Vec<Vector3<f32>> = match mode {
+        GeneratedNormals::Flat => (0..indices.len())
+            .map(|corner| unit(face_normals[corner / 3]))
+            .collect(),
+        GeneratedNormals::Smooth { crease_angle } => {
+            let min_cos = crease_angle.clamp(0.0, 180.0).to_radians().cos();
+            let corner_angle = |corner: usize| {
+                let face = corner - corner % 3;
+                let at = position(indices[corner]);
+                let next = position(indices[face + (corner + 1) % 3]) - at;
+                let previous = position(indices[face + (corner + 2) % 3]) - at;
+                if next.magnitude2() > 0.0 && previous.magnitude2() > 0.0 {
+                    next.angle(previous).0
+                } else {
+                    0.0
+                }
+            };
+            let mut corners_at: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+            for (corner, &index) in indices.iter().enumerate() {
+                let key = vertices[index as usize].position.map(f32::to_bits);
+                corners_at.entry(key).or_default().push(corner);
+            }
+            (0..indices.len())
+                .map(|corner| {
+                    let own = unit(face_normals[corner / 3]);
+                    let key = vertices[indices[corner] as usize]
+                        .position
+                        .map(f32::to_bits);
+                    let sum = corners_at[&key]
+                        .iter()
+                        .filter_map(|&other| {
+                            let normal = unit(face_normals[other / 3]);
+                            (other / 3 == corner / 3 || own.dot(normal) >= min_cos)
+                                .then(|| normal * corner_angle(other))
+                        })
+                        .fold(Vector3::zero(), |sum, weighted| sum + weighted);
+                    unit(sum)
+                })
+                .collect()
+        }
+    };
+
+    // One vertex per (original vertex, normal) pair
+    let mut split: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+    let mut result = Vec::with_capacity(vertices.len());
+    for (index, normal) in indices.iter_mut().zip(corner_normals) {
+        // Degenerate triangles get some unit vector rather than NaNs in the shader
+        let normal: [f32; 3] = if normal.magnitude2() > 0.0 {
+            normal.into()
+        } else {
+            [0.0, 1.0, 0.0]
+        };
+        *index = *split
+            .entry((*index, normal.map(f32::to_bits)))
+            .or_insert_with(|| {
+                result.push(ModelVertex {
+                    normal,
+                    ..vertices[*index as usize]
+                });
+                result.len() as u32 - 1
+            });
+    }
+    *vertices = result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: [f32; 3], tex_coords: [f32; 2]) -> ModelVertex {
+        ModelVertex {
+            position,
+            tex_coords,
+            normal: [0.0; 3],
+            color: ModelVertex::NO_COLOR,
+        }
+    }
+
+    fn assert_near(actual: [f32; 3], expected: [f32; 3]) {
+        let distance = (Vector3::from(actual) - Vector3::from(expected)).magnitude();
+        assert!(distance < 1e-5, "{actual:?} != {expected:?}");
+    }
+
+    /// Two triangles sharing the edge along Y, the second folded up by `fold` degrees
+    fn folded(fold: f32) -> (Vec<ModelVertex>, Vec<u32>) {
+        let (sin, cos) = fold.to_radians().sin_cos();
+        let vertices = vec![
+            vertex([0.0, 0.0, 0.0], [0.0; 2]),
+            vertex([0.0, 1.0, 0.0], [0.0; 2]),
+            vertex([-1.0, 0.0, 0.0], [0.0; 2]),
+            vertex([cos, 0.0, sin], [0.0; 2]),
+        ];
+        (vertices, vec![0, 1, 2, 0, 3, 1])
+    }
+
+    /// Unit cube, every face with its own four vertices like an exporter that split UVs
+    fn cube() -> (Vec<ModelVertex>, Vec<u32>) {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for axis in 0..3 {
+            for side in [0.0, 1.0] {
+                let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                // Counter-clockwise seen from outside
+                let (u, v) = if side == 1.0 { (u, v) } else { (v, u) };
+                let base = vertices.len() as u32;
+                for [a, b] in [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]] {
+                    let mut position = [0.0; 3];
+                    position[axis] = side;
+                    position[u] = a;
+                    position[v] = b;
+                    vertices.push(vertex(position, [a, b]));
+                }
+                indices.extend([0, 1, 2, 0, 2, 3].map(|i| base + i));
+            }
+        }
+        (vertices, indices)
+    }
+
+    #[test]
+    fn counter_clockwise_faces_the_viewer() {
+        let mut vertices = vec![
+            vertex([0.0, 0.0, 0.0], [0.0; 2]),
+            vertex([1.0, 0.0, 0.0], [0.0; 2]),
+            vertex([0.0, 1.0, 0.0], [0.0; 2]),
+        ];
+        let mut indices = vec![0, 1, 2];
+        generate_normals(&mut vertices, &mut indices, GeneratedNormals::Flat);
+        assert_eq!(vertices.len(), 3);
+        for vertex in &vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn shallow_folds_are_smoothed_and_sharp_ones_split() {
+        let (mut vertices, mut indices) = folded(30.0);
+        generate_normals(&mut vertices, &mut indices, GeneratedNormals::default());
+        // The shared edge bends halfway, nothing is split
+        assert_eq!(vertices.len(), 4);
+        let (sin, cos) = 15f32.to_radians().sin_cos();
+        assert_near(vertices[0].normal, [-sin, 0.0, cos]);
+        assert_near(vertices[1].normal, [-sin, 0.0, cos]);
+        assert_near(vertices[2].normal, [0.0, 0.0, 1.0]);
+
+        let (mut vertices, mut indices) = folded(90.0);
+        generate_normals(&mut vertices, &mut indices, GeneratedNormals::default());
+        assert_eq!(vertices.len(), 6);
+        assert_near(vertices[indices[0] as usize].normal, [0.0, 0.0, 1.0]);
+        assert_near(vertices[indices[3] as usize].normal, [-1.0, 0.0, 0.0]);
+        assert_ne!(indices[0], indices[3]);
+    }
+
+    #[test]
+    fn flat_normals_split_every_shared_vertex() {
+        let (mut vertices, mut indices) = folded(30.0);
+        generate_normals(&mut vertices, &mut indices, GeneratedNormals::Flat);
+        assert_eq!(vertices.len(), 6);
+        assert_near(vertices[indices[0] as usize].normal, [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn corners_are_matched_by_position_across_seams() {
+        let (mut vertices, mut indices) = cube();
+        generate_normals(
+            &mut vertices,
+            &mut indices,
+            GeneratedNormals::Smooth {
+                crease_angle: 180.0,
+            },
+        );
+        // Every face corner angle is a right angle in total, so each corner points outwards
+        // along the diagonal
+        let third = 1.0 / 3f32.sqrt();
+        for vertex in &vertices {
+            let expected = vertex
+                .position
+                .map(|p| if p > 0.5 { third } else { -third });
+            assert_near(vertex.normal, expected);
+        }
+        assert_eq!(vertices.len(), 24);
+
+        let (mut vertices, mut indices) = cube();
+        generate_normals(&mut vertices, &mut indices, GeneratedNormals::default());
+        for (corner, &index) in indices.iter().enumerate() {
+            let face = corner / 6;
+            let mut expected = [0.0; 3];
+            expected[face / 2] = if face % 2 == 1 { 1.0 } else { -1.0 };
+            assert_near(vertices[index as usize].normal, expected);
+        }
+    }
+
+    #[test]
+    fn degenerate_triangles_get_a_unit_normal() {
+        let mut vertices = vec![
+            vertex([0.0, 0.0, 0.0], [0.0; 2]),
+            vertex([1.0, 1.0, 1.0], [0.0; 2]),
+            vertex([2.0, 2.0, 2.0], [0.0; 2]),
+        ];
+        let mut indices = vec![0, 1, 2];
+        generate_normals(&mut vertices, &mut indices, GeneratedNormals::default());
+        for vertex in &vertices {
+            assert_eq!(vertex.normal, [0.0, 1.0, 0.0]);
+        }
+    }
+}
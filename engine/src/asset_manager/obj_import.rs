@@ -1,143 +1,663 @@
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
-use anyhow::Context;
 use futures_lite::io::{BufReader, Cursor};
-use wgpu::util::DeviceExt as _;
 
-use crate::{asset_manager::io::Io, gpu::context::GpuContext, model, texture};
+use crate::{
+    asset_manager::{
+        import::{
+            ImportError, ImportedMaterial, ImportedMesh, ImportedModel, ImportedTexture,
+            TextureData, srgb_to_linear,
+        },
+        io::Io,
+        mesh_cache,
+        normals::{GeneratedNormals, generate_normals},
+        optimize,
+    },
+    model::{self, AlphaMode, Bounds, MaterialParams, RenderState},
+    texture::Texture,
+};
+
+/// Part of the mesh cache key, bump it when the output of [`ObjLoader::load_model`] changes
+pub const IMPORTER_VERSION: u32 = 7;
+
+/// Extra work done on imported OBJ meshes. Part of the mesh cache key, changing an option
+/// re-imports the models.
+#[derive(Clone, Debug, Default)]
+pub struct ObjImportOptions {
+    /// Merge identical vertices and reorder triangles and vertices for the vertex cache,
+    /// overdraw and fetch locality (see [`optimize`](crate::asset_manager::optimize)). Makes
+    /// the first import slower, before/after statistics are logged.
+    pub optimize: bool,
+    /// How normals are made for meshes without `vn` data
+    pub generated_normals: GeneratedNormals,
+    /// Flat normals even where the file has its own
+    pub force_flat: bool,
+    /// Texture coordinates for meshes without `vt` data
+    pub generated_uvs: GeneratedUvs,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GeneratedUvs {
+    /// Every vertex samples the texture's corner
+    Zero,
+    /// Positions projected onto the plane of the two longest sides of the bounding box,
+    /// the longest side spans 0..1
+    #[default]
+    Planar,
+}
+
+impl ObjImportOptions {
+    fn cache_key(&self) -> Vec<u8> {
+        let mut key = vec![
+            self.optimize as u8,
+            self.force_flat as u8,
+            self.generated_uvs as u8,
+        ];
+        match self.generated_normals {
+            GeneratedNormals::Smooth { crease_angle } => {
+                key.push(0);
+                key.extend(crease_angle.to_le_bytes());
+            }
+            GeneratedNormals::Flat => key.push(1),
+        }
+        key
+    }
+}
 
 pub struct ObjLoader;
 
 impl ObjLoader {
-    pub async fn load_texture(
-        file_name: &str,
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-    ) -> anyhow::Result<texture::Texture> {
+    /// `srgb` for color maps, data maps (normal, bump, dissolve, ...) are linear
+    pub async fn load_texture(file_name: &str, srgb: bool) -> anyhow::Result<ImportedTexture> {
         let data = Io::load_binary(file_name).await?;
-        texture::Texture::from_bytes(device, queue, &data, file_name)
+        Ok(ImportedTexture {
+            label: file_name.to_owned(),
+            data: Some(TextureData::decode(&data)?),
+            source: Some(file_name.into()),
+            srgb,
+            float_format: wgpu::TextureFormat::Rgba16Float,
+            sampler: Texture::default_sampler(),
+        })
     }
 
+    /// Every failure is an [`ImportError`] naming the file, and the line where there is one.
+    /// A missing or broken material library only costs its materials, meshes using them
+    /// get a default material.
     pub async fn load_model(
         path: &Path,
-        gpu_context: &GpuContext,
-        layout: &wgpu::BindGroupLayout,
-    ) -> anyhow::Result<model::Model> {
+        options: &ObjImportOptions,
+    ) -> anyhow::Result<ImportedModel> {
         let parent_path = path.parent().unwrap_or(Path::new(""));
         let file_name = path
             .file_name()
-            .context("NO valid path found")?
+            .ok_or_else(|| ImportError::new(path, None, "not a file path"))?
             .to_string_lossy()
             .to_string();
-        let obj_text =
-            Io::load_string(parent_path.join(&file_name).to_string_lossy().as_ref()).await?;
-        let obj_cursor = Cursor::new(obj_text);
-        let mut obj_reader = BufReader::new(obj_cursor);
-
-        let (models, obj_materials) = tobj::futures::load_obj_buf(
-            &mut obj_reader,
-            &tobj::LoadOptions {
-                triangulate: true,
-                single_index: true,
-                ..Default::default()
-            },
-            |p| async move {
-                let mat_text = Io::load_string(parent_path.join(p).to_string_lossy().as_ref())
-                    .await
-                    .unwrap();
-                tobj::futures::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))).await
+        let obj_text = Io::load_string(path.to_string_lossy().as_ref())
+            .await
+            .map_err(|e| ImportError::new(path, None, format!("{e:#}")))?;
+        let mut dependencies: Vec<PathBuf> = obj_text
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("mtllib "))
+            .map(|lib| parent_path.join(lib.trim()))
+            .collect();
+        // Read up front, the material libraries are part of the mesh cache key
+        let mut mtl_texts = HashMap::new();
+        for lib in &dependencies {
+            let text = Io::load_string(lib.to_string_lossy().as_ref())
+                .await
+                .inspect_err(|e| {
+                    tracing::warn!("{}: {e:#}, using the default material", lib.display());
+                })
+                .ok();
+            mtl_texts.insert(lib.clone(), text);
+        }
+
+        let options_key = options.cache_key();
+        let cache_key = mesh_cache::key(
+            IMPORTER_VERSION,
+            [options_key.as_slice(), obj_text.as_bytes()]
+                .into_iter()
+                .chain(dependencies.iter().map(|lib| {
+                    mtl_texts[lib]
+                        .as_ref()
+                        .map_or(&[][..], |text| text.as_bytes())
+                })),
+        );
+        if let Some(mut model) = mesh_cache::load(cache_key) {
+            // Textures are not cached, they go through the texture cache by path anyway
+            for texture in &mut model.textures {
+                let path = texture
+                    .source
+                    .clone()
+                    .unwrap_or(texture.label.clone().into());
+                let label = path.to_string_lossy().to_string();
+                *texture = match Self::load_texture(&label, texture.srgb).await {
+                    Ok(texture) => texture,
+                    Err(e) => ImportedTexture {
+                        srgb: texture.srgb,
+                        ..ImportedTexture::missing(label, &e)
+                    },
+                };
+            }
+            return Ok(model);
+        }
+
+        // Parsed here rather than in the tobj callback to know which library failed
+        let mut libraries = HashMap::new();
+        for (lib, text) in &mtl_texts {
+            let Some(text) = text else {
+                continue;
+            };
+            match parse_mtl(text.clone()).await {
+                Ok(materials) => {
+                    libraries.insert(lib.clone(), materials);
+                }
+                Err(e) => {
+                    let line =
+                        failing_line(text, |prefix| async { parse_mtl(prefix).await.is_ok() })
+                            .await;
+                    let error = ImportError::new(lib, line, e);
+                    tracing::warn!("{error}, using the default material");
+                }
+            }
+        }
+
+        let load_options = tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        };
+        let parsed = tobj::futures::load_obj_buf(
+            BufReader::new(Cursor::new(obj_text.as_str())),
+            &load_options,
+            |p| {
+                let materials = libraries.get(&parent_path.join(p)).cloned();
+                async move { Ok(materials.unwrap_or_default()) }
             },
         )
-        .await?;
+        .await;
+        let (models, obj_materials) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                let line = failing_line(&obj_text, |prefix| {
+                    let load_options = &load_options;
+                    async move {
+                        tobj::futures::load_obj_buf(
+                            BufReader::new(Cursor::new(prefix)),
+                            load_options,
+                            |_| async { Ok(Default::default()) },
+                        )
+                        .await
+                        .is_ok()
+                    }
+                })
+                .await;
+                return Err(ImportError::new(path, line, e).into());
+            }
+        };
+        // Only fails when there are no materials at all, which the default covers
+        let obj_materials = obj_materials.unwrap_or_default();
 
+        let mut textures = ModelTextures::default();
         let mut materials = Vec::new();
-        for m in obj_materials? {
-            let diffuse_texture = Self::load_texture(
-                parent_path
-                    .join(
-                        m.diffuse_texture
-                            .unwrap_or("default_diffuse_texture".to_owned()),
-                    )
-                    .to_string_lossy()
-                    .as_ref(),
-                &gpu_context.device,
-                &gpu_context.queue,
-            )
-            .await?;
-            let bind_group = gpu_context
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    layout,
-                    entries: &[
-                        wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 1,
-                            resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                        },
-                    ],
-                    label: None,
-                });
+        for m in obj_materials {
+            // tobj keeps what it does not know as text
+            let unknown = |key: &str| m.unknown_param.get(key).map(String::as_str);
+            // Colors are sRGB, the other maps hold data
+            let statements = [
+                (m.diffuse_texture.as_deref(), true),
+                (m.ambient_texture.as_deref(), true),
+                (m.specular_texture.as_deref(), true),
+                (m.shininess_texture.as_deref(), false),
+                (m.dissolve_texture.as_deref(), false),
+                (m.normal_texture.as_deref(), false),
+                (unknown("map_Ke"), true),
+                (unknown("disp"), false),
+                (unknown("refl"), true),
+            ];
+            let mut slots = [None; 9];
+            for (slot, (statement, srgb)) in slots.iter_mut().zip(statements) {
+                let Some(statement) = statement else {
+                    continue;
+                };
+                let map = MapStatement::parse(statement);
+                if map.file.is_empty() {
+                    tracing::warn!("Material {}: no file in map {statement:?}", m.name);
+                    continue;
+                }
+                let path = parent_path.join(map.file);
+                *slot = Some(textures.load(path, srgb, &mut dependencies).await);
+            }
+            let [
+                base_color_texture,
+                ambient_texture,
+                specular_texture,
+                shininess_texture,
+                dissolve_texture,
+                normal_texture,
+                emissive_texture,
+                displacement_texture,
+                reflection_texture,
+            ] = slots;
+            // Other maps may carry `-bm` too, it only scales the bump map
+            let normal_scale = m
+                .normal_texture
+                .as_deref()
+                .and_then(|statement| MapStatement::parse(statement).bump_multiplier)
+                .unwrap_or(1.0);
+
+            // No map_Kd: the material is drawn with its Kd color
+            let [r, g, b] = m.diffuse.unwrap_or([1.0; 3]);
+            // `Tr` is the older spelling of 1 - d
+            let alpha = m
+                .dissolve
+                .or_else(|| Some(1.0 - unknown("Tr")?.parse::<f32>().ok()?))
+                .unwrap_or(1.0);
+            let alpha_mode = if alpha < 1.0 {
+                AlphaMode::Blend
+            } else if dissolve_texture.is_some() {
+                AlphaMode::Mask(0.5)
+            } else {
+                AlphaMode::Opaque
+            };
+            let defaults = MaterialParams::default();
+            let params = MaterialParams {
+                base_color: [r, g, b, alpha],
+                emissive: unknown("Ke").and_then(parse_color).unwrap_or([0.0; 3]),
+                normal_scale,
+                alpha_mode,
+                ambient: m.ambient.unwrap_or(defaults.ambient),
+                specular: m.specular.unwrap_or(defaults.specular),
+                shininess: m.shininess.unwrap_or(defaults.shininess),
+                ior: m.optical_density.unwrap_or(defaults.ior),
+                illum: m.illumination_model.map_or(defaults.illum, u32::from),
+                ..defaults
+            };
 
-            materials.push(model::Material {
-                name: m.name,
-                diffuse_texture,
-                bind_group,
+            materials.push(ImportedMaterial {
+                name: m.name.clone(),
+                base_color_texture,
+                normal_texture,
+                emissive_texture,
+                ambient_texture,
+                specular_texture,
+                shininess_texture,
+                dissolve_texture,
+                displacement_texture,
+                reflection_texture,
+                render_state: RenderState::from_params(&params),
+                params,
+                ..Default::default()
             })
         }
 
-        let meshes = models
-            .into_iter()
-            .map(|m| {
-                let vertices = (0..m.mesh.positions.len() / 3) // vertex is 3d so we divide be 3
-                    .map(|i| model::ModelVertex {
-                        position: [
-                            m.mesh.positions[i * 3],
-                            m.mesh.positions[i * 3 + 1],
-                            m.mesh.positions[i * 3 + 2],
-                        ],
-                        tex_coords: [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]],
-                        normal: if m.mesh.normals.is_empty() {
-                            [0.0, 0.0, 0.0]
-                        } else {
-                            [
-                                m.mesh.normals[i * 3],
-                                m.mesh.normals[i * 3 + 1],
-                                m.mesh.normals[i * 3 + 2],
-                            ]
-                        },
-                    })
-                    .collect::<Vec<_>>();
-
-                let vertex_buffer =
-                    gpu_context
-                        .device
-                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                            label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                            contents: bytemuck::cast_slice(&vertices),
-                            usage: wgpu::BufferUsages::VERTEX,
-                        });
-                let index_buffer =
-                    gpu_context
-                        .device
-                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                            label: Some(&format!("{:?} Index Buffer", file_name)),
-                            contents: bytemuck::cast_slice(&m.mesh.indices),
-                            usage: wgpu::BufferUsages::INDEX,
-                        });
-
-                model::Mesh {
-                    name: file_name.to_string(),
-                    vertex_buffer,
-                    index_buffer,
-                    num_elements: m.mesh.indices.len() as u32,
-                    material: m.mesh.material_id.unwrap_or(0),
+        // Meshes without a (known) material share one default material
+        let mut default_material = None;
+        let mut meshes = Vec::with_capacity(models.len());
+        for m in models {
+            let vertex_count = m.mesh.positions.len() / 3;
+            let has_uvs = m.mesh.texcoords.len() == vertex_count * 2;
+            let has_normals = m.mesh.normals.len() == vertex_count * 3;
+            // `v x y z r g b`, tobj rejects files that color only some vertices
+            let has_colors = m.mesh.vertex_color.len() == vertex_count * 3;
+            let mut vertices = (0..vertex_count)
+                .map(|i| model::ModelVertex {
+                    position: [
+                        m.mesh.positions[i * 3],
+                        m.mesh.positions[i * 3 + 1],
+                        m.mesh.positions[i * 3 + 2],
+                    ],
+                    tex_coords: if has_uvs {
+                        [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]]
+                    } else {
+                        [0.0, 0.0]
+                    },
+                    normal: if has_normals {
+                        [
+                            m.mesh.normals[i * 3],
+                            m.mesh.normals[i * 3 + 1],
+                            m.mesh.normals[i * 3 + 2],
+                        ]
+                    } else {
+                        [0.0, 0.0, 0.0]
+                    },
+                    color: if has_colors {
+                        let [r, g, b] =
+                            [0, 1, 2].map(|k| srgb_to_linear(m.mesh.vertex_color[i * 3 + k]));
+                        [r, g, b, 1.0]
+                    } else {
+                        model::ModelVertex::NO_COLOR
+                    },
+                })
+                .collect::<Vec<_>>();
+            if !has_uvs && options.generated_uvs == GeneratedUvs::Planar {
+                planar_uvs(&mut vertices);
+            }
+            let mut indices = m.mesh.indices;
+            if options.force_flat {
+                generate_normals(&mut vertices, &mut indices, GeneratedNormals::Flat);
+            } else if !has_normals {
+                generate_normals(&mut vertices, &mut indices, options.generated_normals);
+            }
+
+            let material = match m.mesh.material_id {
+                Some(material) if material < materials.len() => material,
+                _ => *default_material.get_or_insert_with(|| {
+                    materials.push(ImportedMaterial {
+                        name: "default".to_owned(),
+                        ..Default::default()
+                    });
+                    materials.len() - 1
+                }),
+            };
+
+            meshes.push(ImportedMesh {
+                // `o` or `g` name, tobj names the objects before the first one "unnamed_object"
+                name: match m.name.as_str() {
+                    "" | "unnamed_object" => file_name.to_string(),
+                    _ => m.name,
+                },
+                bounds: Bounds::from_vertices(&vertices),
+                vertices,
+                indices,
+                material,
+                skin: None,
+                morph_targets: Vec::new(),
+            });
+        }
+
+        if options.optimize {
+            for mesh in &mut meshes {
+                optimize::optimize_mesh(mesh);
+            }
+        }
+
+        let model = ImportedModel {
+            name: file_name,
+            meshes,
+            materials,
+            textures: textures.textures,
+            dependencies,
+        };
+        mesh_cache::store(cache_key, &model);
+        Ok(model)
+    }
+}
+
+/// Textures of one model, each file is loaded once per color space
+#[derive(Default)]
+struct ModelTextures {
+    textures: Vec<ImportedTexture>,
+    loaded: HashMap<(PathBuf, bool), usize>,
+}
+
+impl ModelTextures {
+    async fn load(&mut self, path: PathBuf, srgb: bool, dependencies: &mut Vec<PathBuf>) -> usize {
+        if let Some(&index) = self.loaded.get(&(path.clone(), srgb)) {
+            return index;
+        }
+        let label = path.to_string_lossy().to_string();
+        let texture = match ObjLoader::load_texture(&label, srgb).await {
+            Ok(texture) => texture,
+            Err(e) => ImportedTexture {
+                srgb,
+                ..ImportedTexture::missing(label, &e)
+            },
+        };
+        // Watched even when broken, fixing the file reloads the model
+        if !dependencies.contains(&path) {
+            dependencies.push(path.clone());
+        }
+        self.textures.push(texture);
+        self.loaded.insert((path, srgb), self.textures.len() - 1);
+        self.textures.len() - 1
+    }
+}
+
+/// A texture map line: options, then the file name
+/// (`map_Bump -bm 0.5 -clamp on bricks normal.png`)
+struct MapStatement {
+    file: String,
+    /// `-bm`, scales bump and normal maps
+    bump_multiplier: Option<f32>,
+}
+
+impl MapStatement {
+    fn parse(statement: &str) -> Self {
+        let mut words = statement.split_whitespace().peekable();
+        let mut bump_multiplier = None;
+        while let Some(option) = words.next_if(|word| word.starts_with('-')) {
+            // -o, -s and -t take one to three numbers
+            let (required, optional) = match option {
+                "-mm" => (2, 0),
+                "-o" | "-s" | "-t" => (1, 2),
+                _ => (1, 0),
+            };
+            for _ in 0..required {
+                let argument = words.next();
+                if option == "-bm" {
+                    bump_multiplier = argument.and_then(|a| a.parse().ok());
                 }
-            })
-            .collect::<Vec<_>>();
+            }
+            for _ in 0..optional {
+                words.next_if(|word| word.parse::<f32>().is_ok());
+            }
+        }
+        Self {
+            file: words.collect::<Vec<_>>().join(" "),
+            bump_multiplier,
+        }
+    }
+}
+
+/// `r g b`, or a single value for gray
+fn parse_color(text: &str) -> Option<[f32; 3]> {
+    let values = text
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<Vec<f32>, _>>()
+        .ok()?;
+    match values[..] {
+        [gray] => Some([gray; 3]),
+        [r, g, b] => Some([r, g, b]),
+        _ => None,
+    }
+}
+
+fn planar_uvs(vertices: &mut [model::ModelVertex]) {
+    let bounds = Bounds::from_vertices(vertices);
+    let size = [0, 1, 2].map(|k| bounds.max[k] - bounds.min[k]);
+    let mut axes = [0, 1, 2];
+    axes.sort_by(|&a, &b| size[b].total_cmp(&size[a]));
+    let [u, v, _] = axes;
+    let scale = if size[u] > 0.0 { 1.0 / size[u] } else { 0.0 };
+    for vertex in vertices {
+        vertex.tex_coords = [
+            (vertex.position[u] - bounds.min[u]) * scale,
+            1.0 - (vertex.position[v] - bounds.min[v]) * scale,
+        ];
+    }
+}
+
+async fn parse_mtl(text: String) -> tobj::MTLLoadResult {
+    tobj::futures::load_mtl_buf(BufReader::new(Cursor::new(text))).await
+}
+
+/// tobj stops at the first line it cannot parse but does not say which one. Parsing longer
+/// and longer prefixes finds it, only on the error path.
+async fn failing_line<F, Fut>(text: &str, parses: F) -> Option<u32>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let lines: Vec<&str> = text.lines().collect();
+    if parses(text.to_owned()).await {
+        return None;
+    }
+    // The first `ok` lines parse, the first `bad` do not
+    let (mut ok, mut bad) = (0, lines.len());
+    while bad - ok > 1 {
+        let mid = (ok + bad) / 2;
+        if parses(lines[..mid].join("\n")).await {
+            ok = mid;
+        } else {
+            bad = mid;
+        }
+    }
+    Some(bad as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset_manager::source::MemorySource;
+
+    const TRIANGLE: &str = "v 0 0 0\nv 2 0 0\nv 0 1 0\nf 1 2 3\n";
+
+    /// Imports `mesh.obj` from a mount of its own holding `files`, the mounts are global
+    fn load(
+        name: &str,
+        files: &[(&str, &str)],
+        options: &ObjImportOptions,
+    ) -> anyhow::Result<ImportedModel> {
+        let prefix = format!("obj-tests-{name}");
+        let source = files
+            .iter()
+            .fold(MemorySource::new(), |source, (file, text)| {
+                source.with_file(file, *text)
+            });
+        let mount = Io::mount(&prefix, 0, source);
+        let model = pollster::block_on(ObjLoader::load_model(
+            &Path::new(&prefix).join("mesh.obj"),
+            options,
+        ));
+        Io::unmount(mount);
+        model
+    }
+
+    fn uv_at(model: &ImportedModel, position: [f32; 3]) -> [f32; 2] {
+        model.meshes[0]
+            .vertices
+            .iter()
+            .find(|v| v.position == position)
+            .expect("vertex at position")
+            .tex_coords
+    }
+
+    #[test]
+    fn missing_uvs_are_generated() {
+        let planar = load("planar-uvs", &[("mesh.obj", TRIANGLE)], &Default::default()).unwrap();
+        // x is the longest side and spans 0..1, y gets the same scale
+        assert_eq!(uv_at(&planar, [0.0, 0.0, 0.0]), [0.0, 1.0]);
+        assert_eq!(uv_at(&planar, [2.0, 0.0, 0.0]), [1.0, 1.0]);
+        assert_eq!(uv_at(&planar, [0.0, 1.0, 0.0]), [0.0, 0.5]);
+
+        let options = ObjImportOptions {
+            generated_uvs: GeneratedUvs::Zero,
+            ..Default::default()
+        };
+        let zero = load("zero-uvs", &[("mesh.obj", TRIANGLE)], &options).unwrap();
+        assert!(
+            zero.meshes[0]
+                .vertices
+                .iter()
+                .all(|v| v.tex_coords == [0.0, 0.0])
+        );
+    }
+
+    #[test]
+    fn broken_material_libraries_get_the_default_material() {
+        let obj = format!("mtllib materials.mtl\nusemtl red\n{TRIANGLE}");
+        let missing = load("missing-mtl", &[("mesh.obj", &obj)], &Default::default()).unwrap();
+        let unparsable = load(
+            "unparsable-mtl",
+            &[
+                ("mesh.obj", &obj),
+                ("materials.mtl", "newmtl red\nKd 1 0 0\nNs shiny\n"),
+            ],
+            &Default::default(),
+        )
+        .unwrap();
+
+        for model in [missing, unparsable] {
+            assert_eq!(model.materials.len(), 1);
+            assert_eq!(model.materials[0].name, "default");
+            assert_eq!(model.meshes[0].material, 0);
+            assert!(
+                model
+                    .dependencies
+                    .iter()
+                    .any(|d| d.ends_with("materials.mtl"))
+            );
+        }
+    }
+
+    #[test]
+    fn errors_name_the_failing_line() {
+        let obj = "# triangle\nv 0 0 0\nv 1 0 0\nv x 1 0\nf 1 2 3\n";
+        let error = load("failing-line", &[("mesh.obj", obj)], &Default::default())
+            .err()
+            .unwrap();
+        let error = error.downcast_ref::<ImportError>().unwrap().to_string();
+        assert!(error.contains("mesh.obj:4: "), "{error}");
+
+        let mtl = "newmtl a\nKd 1 1 1\n\nNs shiny\nd 1\n";
+        let line = pollster::block_on(failing_line(mtl, |prefix| async {
+            parse_mtl(prefix).await.is_ok()
+        }));
+        assert_eq!(line, Some(4));
+        let line = pollster::block_on(failing_line("newmtl a\n", |prefix| async {
+            parse_mtl(prefix).await.is_ok()
+        }));
+        assert_eq!(line, None);
+    }
+
+    #[test]
+    fn map_options_are_skipped() {
+        let map = MapStatement::parse(
+            "-o 1 2 3 -s 2 -t 0.5 0.5 -mm 0 1 -bm 0.25 -clamp on old bricks.png",
+        );
+        assert_eq!(map.file, "old bricks.png");
+        assert_eq!(map.bump_multiplier, Some(0.25));
+
+        // -o, -s and -t take up to three numbers, the file name is not one of them
+        let map = MapStatement::parse("-s 2 2 1.png");
+        assert_eq!(map.file, "1.png");
+        let map = MapStatement::parse("-o 1 bricks.png");
+        assert_eq!(map.file, "bricks.png");
+        assert_eq!(map.bump_multiplier, None);
+    }
+
+    #[test]
+    fn bump_multiplier_only_scales_the_bump_map() {
+        let obj =
+            format!("mtllib materials.mtl\nusemtl bumped\n{TRIANGLE}usemtl plain\n{TRIANGLE}");
+        let mtl = "newmtl bumped\nmap_Kd -bm 2 color.png\nmap_Bump -bm 0.5 normal.png\n\
+                   newmtl plain\nmap_Kd -bm 2 color.png\n";
+        let model = load(
+            "bump-multiplier",
+            &[("mesh.obj", &obj), ("materials.mtl", mtl)],
+            &Default::default(),
+        )
+        .unwrap();
+
+        let scale = |name: &str| {
+            let material = model.materials.iter().find(|m| m.name == name).unwrap();
+            material.params.normal_scale
+        };
+        assert_eq!(scale("bumped"), 0.5);
+        assert_eq!(scale("plain"), 1.0);
+    }
 
-        Ok(model::Model { meshes, materials })
+    #[test]
+    fn colors_can_be_a_single_gray_value() {
+        assert_eq!(parse_color("0.5"), Some([0.5; 3]));
+        assert_eq!(parse_color("1 0.5 0"), Some([1.0, 0.5, 0.0]));
+        assert_eq!(parse_color("1 0.5"), None);
+        assert_eq!(parse_color("bright"), None);
     }
 }
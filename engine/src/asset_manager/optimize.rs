@@ -0,0 +1,578 @@
+// Import-time reordering of triangle lists for the GPU, after the passes of meshoptimizer
+// (https://github.com/zeux/meshoptimizer):
+//
+//   1. identical vertices are merged
+//   2. triangles are reordered for the post-transform vertex cache (Tipsify, Sander et al.
+//      2007, "Fast Triangle Reordering for Vertex Locality and Reduced Overdraw")
+//   3. runs of triangles that keep the cache warm are sorted outside-in, so the front
+//      surfaces are drawn first and hide what is behind them
+//   4. vertices are renumbered in first-use order for fetch locality
+//
+// The statistics simulate a 16 entry FIFO cache, a 16 KiB vertex fetch cache with 64 byte
+// lines, and rasterize the mesh from the six axis directions to measure overdraw.
+use std::{collections::HashMap, fmt};
+
+use cgmath::{InnerSpace as _, Vector3, Zero as _};
+
+use crate::{asset_manager::import::ImportedMesh, model::ModelVertex};
+
+const CACHE_SIZE: u32 = 16;
+/// How much worse than the cache optimized order the overdraw pass may make the ACMR
+const OVERDRAW_THRESHOLD: f32 = 1.05;
+const FETCH_LINE: usize = 64;
+const FETCH_LINES: usize = 256;
+const OVERDRAW_RESOLUTION: usize = 256;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MeshStats {
+    pub vertices: usize,
+    pub triangles: usize,
+    /// Average cache miss ratio: transformed vertices per triangle, 0.5 is the ideal
+    pub acmr: f32,
+    /// Average transformed to vertex ratio, 1.0 is the ideal
+    pub atvr: f32,
+    /// Fragments that passed the depth test per covered pixel, 1.0 is the ideal
+    pub overdraw: f32,
+    /// Vertex buffer bytes fetched per byte in the buffer, 1.0 is the ideal
+    pub overfetch: f32,
+}
+
+impl MeshStats {
+    pub fn of(vertices: &[ModelVertex], indices: &[u32]) -> Self {
+        let triangles = indices.len() / 3;
+        let mut cache = FifoCache::new(vertices.len());
+        let mut transformed = 0;
+        let mut tags = [usize::MAX; FETCH_LINES];
+        let mut fetched = 0;
+        let stride = std::mem::size_of::<ModelVertex>();
+        for &index in indices {
+            if !cache.touch(index) {
+                continue;
+            }
+            transformed += 1;
+            let start = index as usize * stride;
+            for line in start / FETCH_LINE..=(start + stride - 1) / FETCH_LINE {
+                let slot = &mut tags[line % FETCH_LINES];
+                if *slot != line {
+                    *slot = line;
+                    fetched += 1;
+                }
+            }
+        }
+        let buffer_lines = std::mem::size_of_val(vertices).div_ceil(FETCH_LINE);
+
+        Self {
+            vertices: vertices.len(),
+            triangles,
+            acmr: ratio(transformed, triangles),
+            atvr: ratio(transformed, vertices.len()),
+            overdraw: overdraw(vertices, indices),
+            overfetch: ratio(fetched, buffer_lines),
+        }
+    }
+}
+
+impl fmt::Display for MeshStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} vertices, {} triangles, ACMR {:.3}, ATVR {:.3}, overdraw {:.3}, overfetch {:.3}",
+            self.vertices, self.triangles, self.acmr, self.atvr, self.overdraw, self.overfetch
+        )
+    }
+}
+
+/// Runs every pass on a triangle list and logs the statistics before and after. Skinned
+/// and morphed meshes keep their order, their per-vertex data is not remapped.
+pub fn optimize_mesh(mesh: &mut ImportedMesh) -> Option<(MeshStats, MeshStats)> {
+    if mesh.skin.is_some() || !mesh.morph_targets.is_empty() {
+        return None;
+    }
+    let before = MeshStats::of(&mesh.vertices, &mesh.indices);
+
+    let mut indices = std::mem::take(&mut mesh.indices);
+    let vertices = deduplicate(&mesh.vertices, &mut indices);
+    let mut indices = optimize_vertex_cache(&indices, vertices.len());
+    optimize_overdraw(&mut indices, &vertices);
+    mesh.vertices = optimize_vertex_fetch(&vertices, &mut indices);
+    mesh.indices = indices;
+
+    let after = MeshStats::of(&mesh.vertices, &mesh.indices);
+    tracing::info!("Optimized {}: {before} -> {after}", mesh.name);
+    Some((before, after))
+}
+
+/// Bitwise identical vertices share one index
+fn deduplicate(vertices: &[ModelVertex], indices: &mut [u32]) -> Vec<ModelVertex> {
+    let mut unique = Vec::with_capacity(vertices.len());
+    let mut seen: HashMap<&[u8], u32> = HashMap::with_capacity(vertices.len());
+    let remap: Vec<u32> = vertices
+        .iter()
+        .map(|vertex| {
+            *seen.entry(bytemuck::bytes_of(vertex)).or_insert_with(|| {
+                unique.push(*vertex);
+                unique.len() as u32 - 1
+            })
+        })
+        .collect();
+    for index in indices {
+        *index = remap[*index as usize];
+    }
+    unique
+}
+
+/// Tipsify: fans out from one vertex at a time, then continues with the neighbour that
+/// stays in the cache the longest
+fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    let mut live = vec![0u32; vertex_count];
+    for &index in indices {
+        live[index as usize] += 1;
+    }
+    let mut offsets = Vec::with_capacity(vertex_count + 1);
+    offsets.push(0);
+    for &count in &live {
+        offsets.push(offsets.last().copied().unwrap_or(0) + count as usize);
+    }
+    let mut adjacency = vec![0u32; indices.len()];
+    let mut fill = offsets.clone();
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &index in corners {
+            adjacency[fill[index as usize]] = triangle as u32;
+            fill[index as usize] += 1;
+        }
+    }
+
+    let mut result = Vec::with_capacity(triangle_count * 3);
+    let mut emitted = vec![false; triangle_count];
+    let mut timestamps = vec![0u32; vertex_count];
+    let mut time = CACHE_SIZE + 1;
+    let mut dead_end = Vec::new();
+    let mut candidates = Vec::new();
+    let mut cursor = 0;
+    let mut current = indices.first().copied();
+
+    while let Some(vertex) = current {
+        candidates.clear();
+        for &triangle in &adjacency[offsets[vertex as usize]..offsets[vertex as usize + 1]] {
+            if std::mem::replace(&mut emitted[triangle as usize], true) {
+                continue;
+            }
+            for &index in &indices[triangle as usize * 3..triangle as usize * 3 + 3] {
+                result.push(index);
+                dead_end.push(index);
+                candidates.push(index);
+                live[index as usize] -= 1;
+                if time - timestamps[index as usize] > CACHE_SIZE {
+                    timestamps[index as usize] = time;
+                    time += 1;
+                }
+            }
+        }
+
+        // The candidate that is still in the cache after its remaining triangles, oldest first
+        let mut best = None;
+        let mut best_priority = 0;
+        for &candidate in &candidates {
+            let remaining = live[candidate as usize];
+            if remaining == 0 {
+                continue;
+            }
+            let age = time - timestamps[candidate as usize];
+            let priority = if age + 2 * remaining <= CACHE_SIZE {
+                age
+            } else {
+                0
+            };
+            if best.is_none() || priority > best_priority {
+                best = Some(candidate);
+                best_priority = priority;
+            }
+        }
+        current = best.or_else(|| {
+            while let Some(index) = dead_end.pop() {
+                if live[index as usize] > 0 {
+                    return Some(index);
+                }
+            }
+            while cursor < vertex_count {
+                cursor += 1;
+                if live[cursor - 1] > 0 {
+                    return Some(cursor as u32 - 1);
+                }
+            }
+            None
+        });
+    }
+    result
+}
+
+/// Splits the cache optimized order into clusters and sorts them so triangles facing away
+/// from the mesh centre come first
+fn optimize_overdraw(indices: &mut [u32], vertices: &[ModelVertex]) {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return;
+    }
+
+    // Hard boundaries: triangles where the cache had nothing of the previous ones
+    let mut cache = FifoCache::new(vertices.len());
+    let hard: Vec<usize> = (0..triangle_count)
+        .filter(|&triangle| cache.triangle(indices, triangle) == 3 || triangle == 0)
+        .collect();
+
+    // Soft boundaries: split further wherever the cluster so far is already about as cache
+    // friendly as the whole one
+    let mut clusters = Vec::new();
+    for (i, &start) in hard.iter().enumerate() {
+        let end = hard.get(i + 1).copied().unwrap_or(triangle_count);
+        cache.clear();
+        let misses: u32 = (start..end).map(|t| cache.triangle(indices, t)).sum();
+        let target = OVERDRAW_THRESHOLD * misses as f32 / (end - start) as f32;
+
+        cache.clear();
+        let mut cluster_start = start;
+        let mut cluster_misses = 0;
+        for triangle in start..end {
+            cluster_misses += cache.triangle(indices, triangle);
+            let size = triangle + 1 - cluster_start;
+            if triangle + 1 < end && cluster_misses as f32 / size as f32 <= target {
+                clusters.push(cluster_start..triangle + 1);
+                cluster_start = triangle + 1;
+                cluster_misses = 0;
+                cache.clear();
+            }
+        }
+        clusters.push(cluster_start..end);
+    }
+
+    let position = |index: u32| Vector3::from(vertices[index as usize].position);
+    let centre = vertices
+        .iter()
+        .fold(Vector3::zero(), |sum, v| sum + Vector3::from(v.position))
+        / vertices.len().max(1) as f32;
+    let mut keyed: Vec<(f32, std::ops::Range<usize>)> = clusters
+        .into_iter()
+        .map(|cluster| {
+            let mut weighted_centre = Vector3::zero();
+            let mut normal = Vector3::zero();
+            let mut area = 0.0;
+            for triangle in cluster.clone() {
+                let [a, b, c] = [0, 1, 2].map(|k| position(indices[triangle * 3 + k]));
+                let n = (b - a).cross(c - a);
+                let twice_area = n.magnitude();
+                weighted_centre += (a + b + c) / 3.0 * twice_area;
+                normal += n;
+                area += twice_area;
+            }
+            let length = normal.magnitude();
+            let key = if area > 0.0 && length > 0.0 {
+                (weighted_centre / area - centre).dot(normal) / length
+            } else {
+                0.0
+            };
+            (key, cluster)
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let reordered: Vec<u32> = keyed
+        .iter()
+        .flat_map(|(_, cluster)| indices[cluster.start * 3..cluster.end * 3].iter().copied())
+        .collect();
+    indices.copy_from_slice(&reordered);
+}
+
+/// Renumbers vertices in the order the indices first use them, unused ones are dropped
+fn optimize_vertex_fetch(vertices: &[ModelVertex], indices: &mut [u32]) -> Vec<ModelVertex> {
+    let mut remap = vec![u32::MAX; vertices.len()];
+    let mut ordered = Vec::with_capacity(vertices.len());
+    for index in indices {
+        let slot = &mut remap[*index as usize];
+        if *slot == u32::MAX {
+            *slot = ordered.len() as u32;
+            ordered.push(vertices[*index as usize]);
+        }
+        *index = *slot;
+    }
+    ordered
+}
+
+/// Front-to-back depth tested rasterization from +X, -X, +Y, -Y, +Z and -Z in draw order
+fn overdraw(vertices: &[ModelVertex], indices: &[u32]) -> f32 {
+    let (mut min, mut max) = ([f32::MAX; 3], [f32::MIN; 3]);
+    for &index in indices {
+        let p = vertices[index as usize].position;
+        for k in 0..3 {
+            min[k] = min[k].min(p[k]);
+            max[k] = max[k].max(p[k]);
+        }
+    }
+    let extent = (0..3).map(|k| max[k] - min[k]).fold(0.0, f32::max);
+    if extent <= 0.0 {
+        return 1.0;
+    }
+    let scale = (OVERDRAW_RESOLUTION - 1) as f32 / extent;
+
+    let mut covered = 0;
+    let mut shaded = 0;
+    let mut depth = vec![f32::INFINITY; OVERDRAW_RESOLUTION * OVERDRAW_RESOLUTION];
+    for axis in 0..3 {
+        // (u, v, axis) is right handed, so counter-clockwise triangles face the viewer
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for flip in [false, true] {
+            depth.fill(f32::INFINITY);
+            for corners in indices.chunks_exact(3) {
+                let projected = [0, 1, 2].map(|k| {
+                    let p = vertices[corners[k] as usize].position;
+                    let x = (p[u] - min[u]) * scale;
+                    let y = (p[v] - min[v]) * scale;
+                    let z = p[axis] - min[axis];
+                    if flip {
+                        [(OVERDRAW_RESOLUTION - 1) as f32 - x, y, z]
+                    } else {
+                        [x, y, -z]
+                    }
+                });
+                shaded += rasterize(&mut depth, projected);
+            }
+            covered += depth.iter().filter(|d| d.is_finite()).count();
+        }
+    }
+    ratio(shaded, covered.max(1)).max(1.0)
+}
+
+/// Returns the fragments that passed the depth test, back faces are culled
+fn rasterize(depth: &mut [f32], [a, b, c]: [[f32; 3]; 3]) -> usize {
+    let edge = |p: [f32; 3], q: [f32; 3], x: f32, y: f32| {
+        (q[0] - p[0]) * (y - p[1]) - (q[1] - p[1]) * (x - p[0])
+    };
+    let area = edge(a, b, c[0], c[1]);
+    if area <= 0.0 || area.is_nan() {
+        return 0;
+    }
+    let last = (OVERDRAW_RESOLUTION - 1) as f32;
+    let x0 = a[0].min(b[0]).min(c[0]).floor().clamp(0.0, last) as usize;
+    let x1 = a[0].max(b[0]).max(c[0]).ceil().clamp(0.0, last) as usize;
+    let y0 = a[1].min(b[1]).min(c[1]).floor().clamp(0.0, last) as usize;
+    let y1 = a[1].max(b[1]).max(c[1]).ceil().clamp(0.0, last) as usize;
+
+    let mut passed = 0;
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+            let wa = edge(b, c, px, py);
+            let wb = edge(c, a, px, py);
+            let wc = edge(a, b, px, py);
+            if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                continue;
+            }
+            let z = (wa * a[2] + wb * b[2] + wc * c[2]) / area;
+            let stored = &mut depth[y * OVERDRAW_RESOLUTION + x];
+            if z < *stored {
+                *stored = z;
+                passed += 1;
+            }
+        }
+    }
+    passed
+}
+
+/// Post-transform cache that evicts the oldest entry, hits do not refresh
+struct FifoCache {
+    timestamps: Vec<u32>,
+    time: u32,
+}
+
+impl FifoCache {
+    fn new(vertex_count: usize) -> Self {
+        Self {
+            timestamps: vec![0; vertex_count],
+            time: CACHE_SIZE + 1,
+        }
+    }
+
+    /// True on a miss
+    fn touch(&mut self, index: u32) -> bool {
+        let stamp = &mut self.timestamps[index as usize];
+        if self.time - *stamp > CACHE_SIZE {
+            *stamp = self.time;
+            self.time += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Misses of one triangle
+    fn triangle(&mut self, indices: &[u32], triangle: usize) -> u32 {
+        indices[triangle * 3..triangle * 3 + 3]
+            .iter()
+            .map(|&index| self.touch(index) as u32)
+            .sum()
+    }
+
+    fn clear(&mut self) {
+        self.time += CACHE_SIZE + 1;
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f32 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f32 / denominator as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Bounds;
+
+    fn vertex(x: f32, y: f32, z: f32) -> ModelVertex {
+        ModelVertex {
+            position: [x, y, z],
+            tex_coords: [x, y],
+            normal: [0.0, 0.0, 1.0],
+            color: ModelVertex::NO_COLOR,
+        }
+    }
+
+    fn mesh(vertices: Vec<ModelVertex>, indices: Vec<u32>) -> ImportedMesh {
+        ImportedMesh {
+            name: "test".to_owned(),
+            bounds: Bounds::from_vertices(&vertices),
+            vertices,
+            indices,
+            material: 0,
+            skin: None,
+            morph_targets: Vec::new(),
+        }
+    }
+
+    /// `size` x `size` quads in the XY plane facing +Z, triangles shuffled
+    fn shuffled_grid(size: u32) -> ImportedMesh {
+        let vertices = (0..=size)
+            .flat_map(|y| (0..=size).map(move |x| vertex(x as f32, y as f32, 0.0)))
+            .collect();
+        let mut triangles: Vec<[u32; 3]> = (0..size)
+            .flat_map(|y| (0..size).map(move |x| y * (size + 1) + x))
+            .flat_map(|corner| {
+                let above = corner + size + 1;
+                [[corner, corner + 1, above + 1], [corner, above + 1, above]]
+            })
+            .collect();
+        let mut state = 0x2545_f491_u32;
+        for i in (1..triangles.len()).rev() {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            triangles.swap(i, (state >> 8) as usize % (i + 1));
+        }
+        mesh(vertices, triangles.concat())
+    }
+
+    /// Every triangle as its corners' bytes, in a stable order
+    fn triangles(mesh: &ImportedMesh) -> Vec<Vec<u8>> {
+        let mut triangles: Vec<Vec<u8>> = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|corners| {
+                corners
+                    .iter()
+                    .flat_map(|&i| bytemuck::bytes_of(&mesh.vertices[i as usize]).to_vec())
+                    .collect()
+            })
+            .collect();
+        triangles.sort();
+        triangles
+    }
+
+    #[test]
+    fn optimizing_keeps_every_triangle_and_its_winding() {
+        let mut mesh = shuffled_grid(16);
+        let original = triangles(&mesh);
+        let (before, after) = optimize_mesh(&mut mesh).unwrap();
+        assert_eq!(triangles(&mesh), original);
+        assert_eq!((after.vertices, after.triangles), (17 * 17, 512));
+        assert!(after.acmr < before.acmr, "{before} -> {after}");
+        assert!(after.acmr < 1.0, "{after}");
+        assert!(after.overfetch <= before.overfetch, "{before} -> {after}");
+    }
+
+    #[test]
+    fn identical_vertices_are_merged_and_unused_ones_dropped() {
+        let vertices = vec![
+            vertex(0.0, 0.0, 0.0),
+            vertex(1.0, 0.0, 0.0),
+            vertex(1.0, 1.0, 0.0),
+            // Copies of 0 and 2
+            vertex(0.0, 0.0, 0.0),
+            vertex(1.0, 1.0, 0.0),
+            vertex(0.0, 1.0, 0.0),
+            // Unused
+            vertex(5.0, 5.0, 5.0),
+        ];
+        let mut mesh = mesh(vertices, vec![0, 1, 2, 3, 4, 5]);
+        let original = triangles(&mesh);
+        optimize_mesh(&mut mesh).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(triangles(&mesh), original);
+    }
+
+    #[test]
+    fn vertices_are_numbered_in_first_use_order() {
+        let mut mesh = shuffled_grid(4);
+        optimize_mesh(&mut mesh).unwrap();
+        let mut next = 0;
+        for &index in &mesh.indices {
+            assert!(index <= next, "{index} used before {next}");
+            if index == next {
+                next += 1;
+            }
+        }
+        assert_eq!(next as usize, mesh.vertices.len());
+    }
+
+    #[test]
+    fn skinned_and_empty_meshes() {
+        let mut skinned = shuffled_grid(2);
+        skinned.skin = Some(Vec::new());
+        let indices = skinned.indices.clone();
+        assert_eq!(optimize_mesh(&mut skinned), None);
+        assert_eq!(skinned.indices, indices);
+
+        let mut empty = mesh(Vec::new(), Vec::new());
+        let (_, after) = optimize_mesh(&mut empty).unwrap();
+        let nothing = MeshStats {
+            overdraw: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(after, nothing);
+    }
+
+    #[test]
+    fn stats_of_simple_meshes() {
+        let quad = vec![
+            vertex(0.0, 0.0, 0.0),
+            vertex(1.0, 0.0, 0.0),
+            vertex(1.0, 1.0, 0.0),
+            vertex(0.0, 1.0, 0.0),
+        ];
+        let stats = MeshStats::of(&quad, &[0, 1, 2, 0, 2, 3]);
+        assert_eq!((stats.vertices, stats.triangles), (4, 2));
+        assert_eq!(stats.acmr, 2.0);
+        assert_eq!(stats.atvr, 1.0);
+        assert_eq!(stats.overdraw, 1.0);
+
+        // The same quad twice, the far one first: everything is shaded twice from +Z
+        let mut stacked: Vec<ModelVertex> = quad.clone();
+        stacked.extend(
+            quad.iter()
+                .map(|v| vertex(v.position[0], v.position[1], 1.0)),
+        );
+        let back_to_front = MeshStats::of(&stacked, &[0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7]);
+        let front_to_back = MeshStats::of(&stacked, &[4, 5, 6, 4, 6, 7, 0, 1, 2, 0, 2, 3]);
+        assert_eq!(back_to_front.overdraw, 2.0);
+        assert_eq!(front_to_back.overdraw, 1.0);
+    }
+}
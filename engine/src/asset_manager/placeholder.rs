@@ -0,0 +1,58 @@
+use crate::{
+    asset_manager::import::{ImportedMaterial, ImportedMesh, ImportedModel},
+    model::{Bounds, MaterialParams, ModelVertex},
+};
+
+const PLACEHOLDER_COLOR: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+
+/// Unit cube drawn in place of a model that is still loading or failed to load.
+pub fn placeholder_cube() -> ImportedModel {
+    // (normal, tangent u, tangent v) per face
+    let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ];
+
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (n, u, v) in faces {
+        let base = vertices.len() as u32;
+        for (su, sv) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            let position = [0, 1, 2].map(|i| (n[i] + u[i] * su + v[i] * sv) * 0.5);
+            vertices.push(ModelVertex {
+                position,
+                tex_coords: [(su + 1.0) * 0.5, (1.0 - sv) * 0.5],
+                normal: n,
+                color: ModelVertex::NO_COLOR,
+            });
+        }
+        indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+
+    ImportedModel {
+        name: "placeholder".into(),
+        meshes: vec![ImportedMesh {
+            name: "placeholder".into(),
+            bounds: Bounds::from_vertices(&vertices),
+            vertices,
+            indices,
+            material: 0,
+            skin: None,
+            morph_targets: Vec::new(),
+        }],
+        materials: vec![ImportedMaterial {
+            name: "placeholder".into(),
+            params: MaterialParams {
+                base_color: PLACEHOLDER_COLOR,
+                ..Default::default()
+            },
+            ..Default::default()
+        }],
+        textures: Vec::new(),
+        dependencies: Vec::new(),
+    }
+}
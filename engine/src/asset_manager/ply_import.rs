@@ -0,0 +1,558 @@
+// Stanford PLY meshes, as written by scanners and point cloud tools. ASCII and both binary
+// encodings; vertex positions, normals, UVs and colors are read, polygons are triangulated
+// as fans and every other element is skipped.
+use std::path::Path;
+
+use crate::{
+    asset_manager::{
+        import::{ImportError, ImportedMaterial, ImportedMesh, ImportedModel, srgb_to_linear},
+        io::Io,
+        normals::{GeneratedNormals, generate_normals},
+    },
+    model::{Bounds, ModelVertex},
+};
+
+pub struct PlyLoader;
+
+impl PlyLoader {
+    /// Meshes without normals get smooth ones, colors are taken as sRGB. Point clouds
+    /// (no faces) are an error, there is nothing to draw them with.
+    pub async fn load_model(path: &Path) -> anyhow::Result<ImportedModel> {
+        let data = Io::load_binary(path.to_string_lossy().as_ref())
+            .await
+            .map_err(|e| ImportError::new(path, None, format!("{e:#}")))?;
+        let error = |line: Option<u32>, message: String| ImportError::new(path, line, message);
+
+        let header = Header::parse(&data).map_err(|(line, message)| error(Some(line), message))?;
+        let mut body = Body::new(&data[header.body_start..], header.format, header.lines);
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut has_normals = false;
+        for element in &header.elements {
+            match element.name.as_str() {
+                "vertex" => {
+                    has_normals = element.index_of(&["nx"]).is_some();
+                    vertices = read_vertices(&mut body, element)
+                        .map_err(|(line, message)| error(line, message))?;
+                }
+                "face" => {
+                    indices = read_faces(&mut body, element, vertices.len())
+                        .map_err(|(line, message)| error(line, message))?;
+                }
+                _ => {
+                    for _ in 0..element.count {
+                        body.record(element, |_, _| {})
+                            .map_err(|(line, message)| error(line, message))?;
+                    }
+                }
+            }
+        }
+        if indices.is_empty() {
+            return Err(error(None, "no faces, point clouds are not supported".to_owned()).into());
+        }
+        if !has_normals {
+            generate_normals(&mut vertices, &mut indices, GeneratedNormals::default());
+        }
+
+        let name = path
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().to_string());
+        Ok(ImportedModel {
+            name: name.clone(),
+            meshes: vec![ImportedMesh {
+                name,
+                bounds: Bounds::from_vertices(&vertices),
+                vertices,
+                indices,
+                material: 0,
+                skin: None,
+                morph_targets: Vec::new(),
+            }],
+            materials: vec![ImportedMaterial {
+                name: "default".to_owned(),
+                ..Default::default()
+            }],
+            textures: Vec::new(),
+            dependencies: Vec::new(),
+        })
+    }
+}
+
+/// Line (or, in binary files, `None`) and message
+type ParseError = (Option<u32>, String);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    /// Largest value of the integer types, colors are stored as a fraction of it
+    fn range(self) -> f64 {
+        match self {
+            Self::I8 => i8::MAX as f64,
+            Self::U8 => u8::MAX as f64,
+            Self::I16 => i16::MAX as f64,
+            Self::U16 => u16::MAX as f64,
+            Self::I32 => i32::MAX as f64,
+            Self::U32 => u32::MAX as f64,
+            Self::F32 | Self::F64 => 1.0,
+        }
+    }
+}
+
+enum Property {
+    Scalar(Scalar),
+    List { count: Scalar, item: Scalar },
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, Property)>,
+}
+
+impl Element {
+    /// Index of the first property with one of `names`
+    fn index_of(&self, names: &[&str]) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|(name, _)| names.contains(&name.as_str()))
+    }
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+    /// Number of header lines, ASCII bodies count their lines from there
+    lines: u32,
+    body_start: usize,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Self, (u32, String)> {
+        const END: &[u8] = b"end_header";
+        let end = data
+            .windows(END.len())
+            .position(|window| window == END)
+            .ok_or((1, "no end_header".to_owned()))?;
+        let body_start = data[end..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(data.len(), |newline| end + newline + 1);
+        let text = String::from_utf8_lossy(&data[..end]);
+
+        let mut lines = text.lines().zip(1..);
+        if lines.next().map(|(line, _)| line.trim()) != Some("ply") {
+            return Err((1, "not a PLY file".to_owned()));
+        }
+        let mut format = None;
+        let mut elements: Vec<Element> = Vec::new();
+        let mut count = 1;
+        for (line, number) in lines {
+            count = number;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || (number, format!("invalid header line {line:?}"));
+            match words.as_slice() {
+                [] | ["comment" | "obj_info", ..] => {}
+                ["format", encoding, _version] => {
+                    format = Some(match *encoding {
+                        "ascii" => Format::Ascii,
+                        "binary_little_endian" => Format::LittleEndian,
+                        "binary_big_endian" => Format::BigEndian,
+                        _ => return Err((number, format!("unknown format {encoding}"))),
+                    });
+                }
+                ["element", name, element_count] => elements.push(Element {
+                    name: name.to_string(),
+                    count: element_count.parse().map_err(|_| invalid())?,
+                    properties: Vec::new(),
+                }),
+                ["property", "list", count, item, name] => {
+                    let element = elements.last_mut().ok_or_else(invalid)?;
+                    let property = Property::List {
+                        count: Scalar::parse(count).ok_or_else(invalid)?,
+                        item: Scalar::parse(item).ok_or_else(invalid)?,
+                    };
+                    element.properties.push((name.to_string(), property));
+                }
+                ["property", scalar, name] => {
+                    let element = elements.last_mut().ok_or_else(invalid)?;
+                    let property = Property::Scalar(Scalar::parse(scalar).ok_or_else(invalid)?);
+                    element.properties.push((name.to_string(), property));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(Self {
+            format: format.ok_or((1, "no format line".to_owned()))?,
+            elements,
+            // The end_header line
+            lines: count + 1,
+            body_start,
+        })
+    }
+}
+
+/// Reads the element records after the header
+struct Body<'a> {
+    data: &'a [u8],
+    format: Format,
+    position: usize,
+    /// Last line read, ASCII only
+    line: u32,
+}
+
+impl<'a> Body<'a> {
+    fn new(data: &'a [u8], format: Format, header_lines: u32) -> Self {
+        Self {
+            data,
+            format,
+            position: 0,
+            line: header_lines,
+        }
+    }
+
+    /// Every record takes at least a byte, so this bounds the records left whatever the
+    /// header claims
+    fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    fn location(&self) -> Option<u32> {
+        (self.format == Format::Ascii).then_some(self.line)
+    }
+
+    /// Reads one record of `element`, calling `value` with the property index and value for
+    /// each scalar and list item
+    fn record(
+        &mut self,
+        element: &Element,
+        mut value: impl FnMut(usize, f64),
+    ) -> Result<(), ParseError> {
+        if self.format == Format::Ascii {
+            return self.ascii_record(element, value);
+        }
+        for (index, (_, property)) in element.properties.iter().enumerate() {
+            match *property {
+                Property::Scalar(scalar) => value(index, self.binary(scalar)?),
+                Property::List { count, item } => {
+                    for _ in 0..self.binary(count)? as usize {
+                        value(index, self.binary(item)?);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn binary(&mut self, scalar: Scalar) -> Result<f64, ParseError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + scalar.size())
+            .ok_or((None, "file ends in the middle of the data".to_owned()))?;
+        self.position += scalar.size();
+        macro_rules! decode {
+            ($type:ty) => {{
+                let bytes = bytes.try_into().expect("sized by Scalar::size");
+                (if self.format == Format::BigEndian {
+                    <$type>::from_be_bytes(bytes)
+                } else {
+                    <$type>::from_le_bytes(bytes)
+                }) as f64
+            }};
+        }
+        Ok(match scalar {
+            Scalar::I8 => decode!(i8),
+            Scalar::U8 => decode!(u8),
+            Scalar::I16 => decode!(i16),
+            Scalar::U16 => decode!(u16),
+            Scalar::I32 => decode!(i32),
+            Scalar::U32 => decode!(u32),
+            Scalar::F32 => decode!(f32),
+            Scalar::F64 => decode!(f64),
+        })
+    }
+
+    /// One record per line
+    fn ascii_record(
+        &mut self,
+        element: &Element,
+        mut value: impl FnMut(usize, f64),
+    ) -> Result<(), ParseError> {
+        let line = loop {
+            let rest = &self.data[self.position..];
+            if rest.is_empty() {
+                return Err((None, "file ends in the middle of the data".to_owned()));
+            }
+            let length = rest.iter().position(|&byte| byte == b'\n');
+            self.position += length.map_or(rest.len(), |length| length + 1);
+            self.line += 1;
+            let line = String::from_utf8_lossy(&rest[..length.unwrap_or(rest.len())]);
+            if !line.trim().is_empty() {
+                break line;
+            }
+        };
+        let location = self.location();
+        let mut words = line.split_whitespace();
+        let mut next = || -> Result<f64, ParseError> {
+            let word = words
+                .next()
+                .ok_or((location, format!("too few values for a {}", element.name)))?;
+            word.parse()
+                .map_err(|_| (location, format!("invalid number {word:?}")))
+        };
+        for (index, (_, property)) in element.properties.iter().enumerate() {
+            match property {
+                Property::Scalar(_) => value(index, next()?),
+                Property::List { .. } => {
+                    for _ in 0..next()? as usize {
+                        value(index, next()?);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_vertices(body: &mut Body, element: &Element) -> Result<Vec<ModelVertex>, ParseError> {
+    let find = |names: &[&str]| element.index_of(names);
+    let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+    let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+    let tex_coords = [
+        find(&["u", "s", "texture_u", "texture_s"]),
+        find(&["v", "t", "texture_v", "texture_t"]),
+    ];
+    let color = [
+        find(&["red", "r", "diffuse_red"]),
+        find(&["green", "g", "diffuse_green"]),
+        find(&["blue", "b", "diffuse_blue"]),
+        find(&["alpha", "a", "diffuse_alpha"]),
+    ];
+    let range = |index: Option<usize>| match index.map(|i| &element.properties[i].1) {
+        Some(Property::Scalar(scalar)) => scalar.range(),
+        _ => 1.0,
+    };
+    let color_range = color.map(range);
+
+    let mut vertices = Vec::with_capacity(element.count.min(body.remaining()));
+    for _ in 0..element.count {
+        let mut vertex = ModelVertex {
+            position: [0.0; 3],
+            tex_coords: [0.0; 2],
+            normal: [0.0; 3],
+            color: ModelVertex::NO_COLOR,
+        };
+        body.record(element, |index, value| {
+            let value = value as f32;
+            let slot = Some(index);
+            if let Some(k) = position.iter().position(|&p| p == slot) {
+                vertex.position[k] = value;
+            } else if let Some(k) = normal.iter().position(|&p| p == slot) {
+                vertex.normal[k] = value;
+            } else if let Some(k) = tex_coords.iter().position(|&p| p == slot) {
+                // PLY puts the origin at the bottom left like OBJ
+                vertex.tex_coords[k] = if k == 1 { 1.0 - value } else { value };
+            } else if let Some(k) = color.iter().position(|&p| p == slot) {
+                let value = (value / color_range[k] as f32).clamp(0.0, 1.0);
+                vertex.color[k] = if k == 3 { value } else { srgb_to_linear(value) };
+            }
+        })?;
+        vertices.push(vertex);
+    }
+    Ok(vertices)
+}
+
+fn read_faces(
+    body: &mut Body,
+    element: &Element,
+    vertex_count: usize,
+) -> Result<Vec<u32>, ParseError> {
+    let list = element
+        .index_of(&["vertex_indices", "vertex_index"])
+        .ok_or((None, "faces without vertex_indices".to_owned()))?;
+    // Grows past this for polygons, a lying header only costs an error
+    let mut indices = Vec::with_capacity(element.count.min(body.remaining()) * 3);
+    let mut polygon = Vec::new();
+    for face in 0..element.count {
+        polygon.clear();
+        body.record(element, |index, value| {
+            if index == list {
+                polygon.push(value);
+            }
+        })?;
+        let location = body.location();
+        for &index in &polygon {
+            if index < 0.0 || index as usize >= vertex_count {
+                let message = format!("face {face} uses vertex {index}, there are {vertex_count}");
+                return Err((location, message));
+            }
+        }
+        for i in 1..polygon.len().saturating_sub(1) {
+            indices.extend([polygon[0], polygon[i], polygon[i + 1]].map(|index| index as u32));
+        }
+    }
+    Ok(indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset_manager::source::MemorySource;
+
+    /// Reads `data` through a mount of its own, the mounts are global
+    fn load(name: &str, data: impl Into<Vec<u8>>) -> anyhow::Result<ImportedModel> {
+        let prefix = format!("ply-tests-{name}");
+        let mount = Io::mount(&prefix, 0, MemorySource::new().with_file("mesh.ply", data));
+        let model = pollster::block_on(PlyLoader::load_model(&Path::new(&prefix).join("mesh.ply")));
+        Io::unmount(mount);
+        model
+    }
+
+    fn binary(
+        format: &str,
+        vertex: impl Fn(f32) -> [u8; 4],
+        index: impl Fn(u32) -> [u8; 4],
+    ) -> Vec<u8> {
+        let mut data = format!(
+            "ply\nformat {format} 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+             property float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\n\
+             element face 1\nproperty list uchar int vertex_indices\nend_header\n"
+        )
+        .into_bytes();
+        for (position, color) in [
+            ([0.0, 0.0, 0.0], [255, 0, 0]),
+            ([1.0, 0.0, 0.0], [0, 255, 0]),
+            ([0.0, 1.0, 0.0], [0, 0, 255]),
+        ] {
+            data.extend(position.into_iter().flat_map(&vertex));
+            data.extend(color);
+        }
+        data.push(3);
+        data.extend([0, 1, 2].into_iter().flat_map(index));
+        data
+    }
+
+    #[test]
+    fn ascii_polygons_are_triangulated_as_fans() {
+        let model = load(
+            "ascii",
+            "ply\nformat ascii 1.0\ncomment a unit quad\nelement vertex 4\nproperty float x\n\
+             property float y\nproperty float z\nproperty float nx\nproperty float ny\n\
+             property float nz\nelement face 1\nproperty list uchar int vertex_indices\n\
+             end_header\n0 0 0 0 0 1\n1 0 0 0 0 1\n1 1 0 0 0 1\n\n0 1 0 0 0 1\n4 0 1 2 3\n",
+        )
+        .unwrap();
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.vertices[2].position, [1.0, 1.0, 0.0]);
+        assert_eq!(mesh.vertices[2].normal, [0.0, 0.0, 1.0]);
+        assert_eq!(mesh.bounds.max, [1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn both_binary_encodings_read_the_same() {
+        let little = load(
+            "little",
+            binary("binary_little_endian", f32::to_le_bytes, u32::to_le_bytes),
+        )
+        .unwrap();
+        let big = load(
+            "big",
+            binary("binary_big_endian", f32::to_be_bytes, u32::to_be_bytes),
+        )
+        .unwrap();
+        for model in [little, big] {
+            let mesh = &model.meshes[0];
+            assert_eq!(mesh.indices, [0, 1, 2]);
+            assert_eq!(mesh.vertices[1].position, [1.0, 0.0, 0.0]);
+            assert_eq!(mesh.vertices[1].color, [0.0, 1.0, 0.0, 1.0]);
+            // No normals in the file, generated from the winding
+            assert_eq!(mesh.vertices[0].normal, [0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn truncated_binary_body_is_an_error() {
+        let mut data = binary("binary_little_endian", f32::to_le_bytes, u32::to_le_bytes);
+        data.truncate(data.len() - 2);
+        let error = load("truncated", data).err().unwrap();
+        assert!(error.to_string().contains("ends in the middle"), "{error}");
+    }
+
+    #[test]
+    fn header_counts_are_not_trusted() {
+        let data = "ply\nformat binary_little_endian 1.0\nelement vertex 0\nproperty float x\n\
+                    element face 4000000000000000000\nproperty list uchar int vertex_indices\n\
+                    end_header\n";
+        let error = load("huge", data).err().unwrap();
+        assert!(error.to_string().contains("ends in the middle"), "{error}");
+    }
+
+    #[test]
+    fn errors_point_at_the_line() {
+        let data = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\n\
+                    property float z\nelement face 1\nproperty list uchar int vertex_indices\n\
+                    end_header\n0 0 0\n3 0 1 2\n";
+        let error = load("index", data).err().unwrap();
+        assert!(
+            error
+                .to_string()
+                .ends_with(":11: face 0 uses vertex 1, there are 1"),
+            "{error}"
+        );
+
+        let error = load(
+            "header",
+            "ply\nformat ascii 1.0\nelement vertex\nend_header\n",
+        )
+        .err()
+        .unwrap();
+        assert!(
+            error.to_string().contains(":3: invalid header line"),
+            "{error}"
+        );
+
+        let data = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n1\n";
+        let error = load("points", data).err().unwrap();
+        assert!(error.to_string().contains("point clouds"), "{error}");
+    }
+}
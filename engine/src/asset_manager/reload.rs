@@ -0,0 +1,192 @@
+// Hot reloading of models, glTF scenes and material files. Files are polled the same way shaders are, a
+// change queues a background re-import of every asset that read the file and the result
+// replaces the asset behind its existing handles. A failed import leaves the old one alone.
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    asset_manager::{
+        AssetManager,
+        gltf_import::{GltfScene, ImportedScene},
+        handle::Handle,
+        import::ImportedModel,
+        material_file::ImportedMaterialFile,
+    },
+    model::{Material, Model},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(super) struct HotReload {
+    watcher: super::watcher::FileWatcher,
+    // Watched file -> load paths of the assets that read it
+    dependents: std::collections::HashMap<PathBuf, std::collections::HashSet<PathBuf>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for HotReload {
+    fn default() -> Self {
+        Self {
+            watcher: super::watcher::FileWatcher::new(std::time::Duration::from_millis(500)),
+            dependents: Default::default(),
+        }
+    }
+}
+
+impl AssetManager {
+    /// Starts watching the file loaded from `path` and the files its import read.
+    pub(super) fn watch(&mut self, path: &Path, dependencies: &[PathBuf]) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(hot_reload) = &mut self.hot_reload
+            && let Ok(folder) = super::io::get_assets_folder()
+        {
+            for file in std::iter::once(path).chain(dependencies.iter().map(PathBuf::as_path)) {
+                let file = folder.join(file);
+                hot_reload.watcher.watch(file.clone());
+                hot_reload
+                    .dependents
+                    .entry(file)
+                    .or_default()
+                    .insert(path.to_path_buf());
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = (path, dependencies);
+    }
+
+    pub(super) fn queue_reloads(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let Some(hot_reload) = &mut self.hot_reload else {
+                return;
+            };
+            let files = hot_reload.watcher.poll();
+            // Re-imports have to upload the new image instead of reusing the cached texture
+            if let Ok(folder) = super::io::get_assets_folder() {
+                for file in &files {
+                    if let Ok(relative) = file.strip_prefix(&folder) {
+                        self.texture_cache.forget_path(relative);
+                    }
+                }
+            }
+            let changed: std::collections::HashSet<PathBuf> = files
+                .iter()
+                .filter_map(|file| hot_reload.dependents.get(file))
+                .flatten()
+                .cloned()
+                .collect();
+            for path in changed {
+                if let Some(&handle) = self.model_cache.get(&path) {
+                    tracing::info!("{} changed on disk, reloading", path.display());
+                    self.spawn_model_load(path, handle);
+                } else if let Some(&handle) = self.scene_cache.get(&path) {
+                    tracing::info!("{} changed on disk, reloading", path.display());
+                    self.spawn_gltf_load(path, handle);
+                } else if let Some(&handle) = self.material_cache.get(&path) {
+                    tracing::info!("{} changed on disk, reloading", path.display());
+                    self.spawn_material_load(path, handle);
+                }
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn spawn_gltf_load(&mut self, path: PathBuf, handle: Handle<GltfScene>) {
+        use crate::asset_manager::{
+            gltf_import::GltfLoader,
+            loader::{Decoded, LoadStatus},
+        };
+
+        self.load_status.insert(path.clone(), LoadStatus::Loading);
+        self.loads.spawn(move || async move {
+            let result = GltfLoader::load_scene(&path).await;
+            Decoded::Scene {
+                path,
+                handle,
+                result,
+            }
+        });
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn spawn_material_load(&mut self, path: PathBuf, handle: Handle<Material>) {
+        use crate::asset_manager::{
+            loader::{Decoded, LoadStatus},
+            material_file::MaterialLoader,
+        };
+
+        self.load_status.insert(path.clone(), LoadStatus::Loading);
+        self.loads.spawn(move || async move {
+            let result = MaterialLoader::load(&path).await.map(Box::new);
+            Decoded::Material {
+                path,
+                handle,
+                result,
+            }
+        });
+    }
+
+    /// Uploads `imported` into the slot of `handle`, the old model releases its textures.
+    pub(super) fn replace_model(
+        &mut self,
+        path: &Path,
+        handle: Handle<Model>,
+        mut imported: ImportedModel,
+    ) -> anyhow::Result<()> {
+        let dependencies = std::mem::take(&mut imported.dependencies);
+        let model = imported.upload(
+            &self.gpu_context,
+            &self.material_layout,
+            &self.morph_layout,
+            &mut self.texture_cache,
+            &self.fallbacks,
+        )?;
+        self.store_model(Some(handle), Arc::new(model));
+        // A re-export may reference new textures
+        self.watch(path, &dependencies);
+        Ok(())
+    }
+
+    /// Models, skeletons and animations are swapped index by index. Entities already spawned
+    /// from the scene keep their node layout. The ones past the end of a shorter re-export
+    /// are removed, their models release their textures.
+    pub(super) fn replace_scene(
+        &mut self,
+        path: &Path,
+        handle: Handle<GltfScene>,
+        mut imported: ImportedScene,
+    ) -> anyhow::Result<()> {
+        let dependencies = std::mem::take(&mut imported.dependencies);
+        let previous = self.scenes.get(handle).cloned();
+        let scene = self.upload_scene(imported, previous.as_deref())?;
+        if let Some(previous) = &previous {
+            for &model in previous.models.iter().skip(scene.models.len()) {
+                self.remove_model(model);
+            }
+            for &skeleton in previous.skeletons.iter().skip(scene.skeletons.len()) {
+                self.skeletons.remove(skeleton);
+            }
+            for &clip in previous.animations.iter().skip(scene.animations.len()) {
+                self.animations.remove(clip);
+            }
+        }
+        self.scenes.replace(handle, scene);
+        self.watch(path, &dependencies);
+        Ok(())
+    }
+
+    /// Entities overriding with the material pick up the new version through its handle.
+    pub(super) fn replace_material(
+        &mut self,
+        path: &Path,
+        handle: Handle<Material>,
+        mut imported: ImportedMaterialFile,
+    ) -> anyhow::Result<()> {
+        let dependencies = std::mem::take(&mut imported.dependencies);
+        let material = self.upload_material(imported)?;
+        self.store_material(Some(handle), material);
+        self.watch(path, &dependencies);
+        Ok(())
+    }
+}
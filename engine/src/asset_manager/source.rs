@@ -0,0 +1,154 @@
+//! Places `Io` reads assets from. Sources are mounted at a virtual prefix with a priority
+//! (see `Io::mount`); a source only sees the part of the path below its prefix.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use async_trait::async_trait;
+
+use crate::asset_manager::archive::{Archive, entry_name};
+
+/// Paths are relative to the mount point, '/' separated with `.` and `..` already resolved
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait AssetSource: Send + Sync {
+    /// `Ok(None)` when the source has no such file, so the next source is asked. An error
+    /// stops the lookup: the file is there but could not be read.
+    async fn read(&self, path: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Shown in logs and errors
+    fn describe(&self) -> String;
+}
+
+/// Loose files below a folder
+#[cfg(not(target_arch = "wasm32"))]
+pub struct DirSource {
+    root: PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DirSource {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait]
+impl AssetSource for DirSource {
+    async fn read(&self, path: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.root.join(path)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("folder {}", self.root.display())
+    }
+}
+
+/// Files held in memory, for tests and generated assets
+#[derive(Default)]
+pub struct MemorySource {
+    files: RwLock<HashMap<String, Vec<u8>>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_file(self, path: impl AsRef<Path>, data: impl Into<Vec<u8>>) -> Self {
+        self.insert(path, data);
+        self
+    }
+
+    /// Replaces the file if there already is one
+    pub fn insert(&self, path: impl AsRef<Path>, data: impl Into<Vec<u8>>) {
+        self.files
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(entry_name(path.as_ref()), data.into());
+    }
+
+    pub fn remove(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.files
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&entry_name(path.as_ref()))
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl AssetSource for MemorySource {
+    async fn read(&self, path: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let files = self.files.read().unwrap_or_else(|e| e.into_inner());
+        Ok(files.get(path).cloned())
+    }
+
+    fn describe(&self) -> String {
+        "memory".to_owned()
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl AssetSource for Archive {
+    async fn read(&self, path: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        Archive::read(self, path)
+    }
+
+    fn describe(&self) -> String {
+        format!("archive ({} files)", self.entries().count())
+    }
+}
+
+/// Files served over HTTP below `base`. Web only, reqwest needs a Tokio runtime natively.
+#[cfg(target_arch = "wasm32")]
+pub struct HttpSource {
+    base: url::Url,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl HttpSource {
+    /// `base` is a folder, a missing trailing '/' is added
+    pub fn new(mut base: url::Url) -> Self {
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        Self { base }
+    }
+
+    /// The `assets/` folder next to the page
+    pub fn from_page() -> anyhow::Result<Self> {
+        let origin = web_sys::window()
+            .and_then(|window| window.location().origin().ok())
+            .ok_or_else(|| anyhow::anyhow!("No page location"))?;
+        Ok(Self::new(url::Url::parse(&format!("{origin}/assets/"))?))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[async_trait(?Send)]
+impl AssetSource for HttpSource {
+    async fn read(&self, path: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let response = reqwest::get(self.base.join(path)?).await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(response.error_for_status()?.bytes().await?.to_vec()))
+    }
+
+    fn describe(&self) -> String {
+        self.base.to_string()
+    }
+}
@@ -0,0 +1,280 @@
+// STL meshes from CAD tools, binary or ASCII. Every triangle gets its own three vertices
+// with the facet normal, so edges stay hard; ASCII files with several `solid`s give one mesh
+// per solid.
+use std::path::Path;
+
+use cgmath::{InnerSpace as _, Vector3};
+
+use crate::{
+    asset_manager::{
+        import::{ImportError, ImportedMaterial, ImportedMesh, ImportedModel},
+        io::Io,
+    },
+    model::{Bounds, ModelVertex},
+};
+
+pub struct StlLoader;
+
+impl StlLoader {
+    pub async fn load_model(path: &Path) -> anyhow::Result<ImportedModel> {
+        let data = Io::load_binary(path.to_string_lossy().as_ref())
+            .await
+            .map_err(|e| ImportError::new(path, None, format!("{e:#}")))?;
+        let file_name = path
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().to_string());
+
+        let solids = if is_binary(&data) {
+            vec![(file_name.clone(), parse_binary(&data))]
+        } else {
+            let text = String::from_utf8_lossy(&data);
+            parse_ascii(&text).map_err(|(line, message)| ImportError::new(path, line, message))?
+        };
+        if solids.iter().all(|(_, facets)| facets.is_empty()) {
+            return Err(ImportError::new(path, None, "no facets").into());
+        }
+
+        let meshes = solids
+            .into_iter()
+            .filter(|(_, facets)| !facets.is_empty())
+            .map(|(name, facets)| {
+                let vertices: Vec<ModelVertex> = facets
+                    .iter()
+                    .flat_map(|facet| {
+                        let normal = facet_normal(facet);
+                        facet.vertices.map(|position| ModelVertex {
+                            position,
+                            tex_coords: [0.0; 2],
+                            normal,
+                            color: ModelVertex::NO_COLOR,
+                        })
+                    })
+                    .collect();
+                ImportedMesh {
+                    name: if name.is_empty() {
+                        file_name.clone()
+                    } else {
+                        name
+                    },
+                    bounds: Bounds::from_vertices(&vertices),
+                    indices: (0..vertices.len() as u32).collect(),
+                    vertices,
+                    material: 0,
+                    skin: None,
+                    morph_targets: Vec::new(),
+                }
+            })
+            .collect();
+
+        Ok(ImportedModel {
+            name: file_name,
+            meshes,
+            materials: vec![ImportedMaterial {
+                name: "default".to_owned(),
+                ..Default::default()
+            }],
+            textures: Vec::new(),
+            dependencies: Vec::new(),
+        })
+    }
+}
+
+/// Line and message
+type ParseError = (Option<u32>, String);
+
+struct Facet {
+    normal: [f32; 3],
+    vertices: [[f32; 3]; 3],
+}
+
+/// The file's normal unless it is missing (many exporters write zeros), otherwise the
+/// counter-clockwise winding decides
+fn facet_normal(facet: &Facet) -> [f32; 3] {
+    let normal = Vector3::from(facet.normal);
+    if normal.magnitude2() > 0.0 && normal.magnitude2().is_finite() {
+        return normal.normalize().into();
+    }
+    let [a, b, c] = facet.vertices.map(Vector3::from);
+    let normal = (b - a).cross(c - a);
+    if normal.magnitude2() > 0.0 {
+        normal.normalize().into()
+    } else {
+        // Degenerate, any unit vector beats NaNs in the shader
+        [0.0, 1.0, 0.0]
+    }
+}
+
+const BINARY_HEADER: usize = 84;
+const BINARY_FACET: usize = 50;
+
+/// Binary files may start with "solid" too, the size tells them apart
+fn is_binary(data: &[u8]) -> bool {
+    let declared = data
+        .get(80..BINARY_HEADER)
+        .map(|count| u32::from_le_bytes(count.try_into().expect("4 bytes")) as usize);
+    match declared {
+        Some(count) if data.len() == BINARY_HEADER + count * BINARY_FACET => true,
+        _ => !data.trim_ascii_start().starts_with(b"solid"),
+    }
+}
+
+/// A truncated file keeps the facets it has
+fn parse_binary(data: &[u8]) -> Vec<Facet> {
+    let floats = |bytes: &[u8]| -> [f32; 3] {
+        let float = |i: usize| bytes[i * 4..i * 4 + 4].try_into().expect("4 bytes");
+        [0, 1, 2].map(|i| f32::from_le_bytes(float(i)))
+    };
+    let facets: Vec<Facet> = data
+        .get(BINARY_HEADER..)
+        .unwrap_or_default()
+        .chunks_exact(BINARY_FACET)
+        .map(|facet| Facet {
+            normal: floats(&facet[0..12]),
+            vertices: [
+                floats(&facet[12..24]),
+                floats(&facet[24..36]),
+                floats(&facet[36..48]),
+            ],
+        })
+        .collect();
+    if data.len() != BINARY_HEADER + facets.len() * BINARY_FACET {
+        tracing::warn!(
+            "Binary STL size does not match its facet count, read {} facets",
+            facets.len()
+        );
+    }
+    facets
+}
+
+/// Named solids with their facets. Polygons with more than three vertices are triangulated
+/// as fans.
+fn parse_ascii(text: &str) -> Result<Vec<(String, Vec<Facet>)>, ParseError> {
+    let mut solids = Vec::new();
+    let mut normal = [0.0; 3];
+    let mut polygon: Vec<[f32; 3]> = Vec::new();
+    for (line, number) in text.lines().zip(1..) {
+        let error = |message: &str| (Some(number), format!("{message}: {:?}", line.trim()));
+        let floats = |words: &[&str]| -> Result<[f32; 3], ParseError> {
+            match words {
+                [x, y, z] => {
+                    let parse =
+                        |word: &str| word.parse::<f32>().map_err(|_| error("invalid number"));
+                    Ok([parse(x)?, parse(y)?, parse(z)?])
+                }
+                _ => Err(error("expected three numbers")),
+            }
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] | ["outer", "loop"] | ["endloop"] | ["endsolid", ..] => {}
+            ["solid", ..] => {
+                let name = line.trim_start()["solid".len()..].trim();
+                solids.push((name.to_owned(), Vec::new()));
+            }
+            ["facet", "normal", rest @ ..] => {
+                normal = floats(rest)?;
+                polygon.clear();
+            }
+            ["vertex", rest @ ..] => polygon.push(floats(rest)?),
+            ["endfacet"] => {
+                let (_, facets) = solids
+                    .last_mut()
+                    .ok_or_else(|| error("facet outside a solid"))?;
+                if polygon.len() < 3 {
+                    return Err(error("facet with fewer than three vertices"));
+                }
+                for i in 1..polygon.len() - 1 {
+                    facets.push(Facet {
+                        normal,
+                        vertices: [polygon[0], polygon[i], polygon[i + 1]],
+                    });
+                }
+                polygon.clear();
+            }
+            _ => return Err(error("unexpected line")),
+        }
+    }
+    Ok(solids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary(facets: &[[[f32; 3]; 4]]) -> Vec<u8> {
+        // Binary headers may start with "solid" too
+        let mut data = b"solid exported as binary".to_vec();
+        data.resize(80, 0);
+        data.extend((facets.len() as u32).to_le_bytes());
+        for facet in facets {
+            data.extend(facet.iter().flatten().flat_map(|v| v.to_le_bytes()));
+            data.extend([0, 0]);
+        }
+        data
+    }
+
+    const TRIANGLE: [[f32; 3]; 4] = [[0.0; 3], [0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+    #[test]
+    fn binary_files_are_told_apart_by_size() {
+        let data = binary(&[TRIANGLE, TRIANGLE]);
+        assert!(is_binary(&data));
+        let facets = parse_binary(&data);
+        assert_eq!(facets.len(), 2);
+        assert_eq!(facets[1].vertices[2], [0.0, 1.0, 0.0]);
+
+        assert!(!is_binary(b"solid cube\nendsolid cube\n"));
+    }
+
+    #[test]
+    fn truncated_binary_keeps_whole_facets() {
+        let mut data = binary(&[TRIANGLE, TRIANGLE]);
+        data.truncate(data.len() - 10);
+        // The size no longer matches, but the header does not start with "solid"
+        data[..5].copy_from_slice(b"model");
+        assert!(is_binary(&data));
+        assert_eq!(parse_binary(&data).len(), 1);
+        assert!(parse_binary(&data[..40]).is_empty());
+    }
+
+    #[test]
+    fn ascii_solids_and_polygons() {
+        let solids = parse_ascii(
+            "solid first\n facet normal 0 0 1\n  outer loop\n   vertex 0 0 0\n   vertex 1 0 0\n\
+             \x20  vertex 1 1 0\n   vertex 0 1 0\n  endloop\n endfacet\nendsolid first\n\
+             solid\n facet normal 0 0 0\n  outer loop\n   vertex 0 0 0\n   vertex 1 0 0\n\
+             \x20  vertex 0 1 0\n  endloop\n endfacet\nendsolid\n",
+        )
+        .unwrap();
+        assert_eq!(solids.len(), 2);
+        let (name, quad) = &solids[0];
+        assert_eq!(name, "first");
+        assert_eq!(quad.len(), 2);
+        assert_eq!(
+            quad[1].vertices,
+            [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]]
+        );
+        assert_eq!(solids[1].0, "");
+    }
+
+    #[test]
+    fn ascii_errors_point_at_the_line() {
+        let error = |text: &str| parse_ascii(text).err().unwrap();
+        let (line, message) = error("solid a\nfacet normal 0 0 1\nvertex 0 0\n");
+        assert_eq!(line, Some(3));
+        assert!(message.starts_with("expected three numbers"), "{message}");
+        assert_eq!(error("facet normal 0 0 1\nendfacet\n").0, Some(2));
+        assert_eq!(error("solid a\nvertex 0 0 x\n").0, Some(2));
+        assert_eq!(error("solid a\nbogus\n").0, Some(2));
+    }
+
+    #[test]
+    fn missing_normals_come_from_the_winding() {
+        let facet = |normal: [f32; 3], vertices| facet_normal(&Facet { normal, vertices });
+        let triangle = [[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        assert_eq!(facet([0.0, 0.0, 2.0], triangle), [0.0, 0.0, 1.0]);
+        assert_eq!(facet([0.0; 3], triangle), [0.0, 0.0, 1.0]);
+        assert_eq!(facet([f32::NAN; 3], triangle), [0.0, 0.0, 1.0]);
+        assert_eq!(facet([0.0; 3], [[0.0; 3]; 3]), [0.0, 1.0, 0.0]);
+    }
+}
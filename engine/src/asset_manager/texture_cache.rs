@@ -0,0 +1,178 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use image::DynamicImage;
+
+use crate::{
+    asset_manager::{
+        handle::{Assets, Handle},
+        import::{ImportedTexture, TextureData},
+    },
+    gpu::context::GpuContext,
+    texture::Texture,
+};
+
+/// The same file is uploaded once per color space (and float precision for HDR images)
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureKey {
+    pub path: PathBuf,
+    pub srgb: bool,
+    pub float_format: wgpu::TextureFormat,
+}
+
+/// Totals for the debug UI
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TextureMemory {
+    pub textures: usize,
+    /// Textures that came from a file and can be shared
+    pub cached: usize,
+    pub bytes: u64,
+}
+
+/// Owns every texture of the `AssetManager`. Textures read from a file are shared by path,
+/// so materials and models that reference the same image share one GPU texture. Models hold a reference per material slot, a texture is freed when the last
+/// one is released.
+#[derive(Default)]
+pub struct TextureCache {
+    textures: Assets<Texture>,
+    paths: HashMap<TextureKey, Handle<Texture>>,
+    refs: HashMap<Handle<Texture>, u32>,
+}
+
+impl TextureCache {
+    /// Reuses the texture uploaded from the same file, uploads it otherwise.
+    /// Settings of the first upload (sampler) win. Compressed formats the device can't
+    /// sample are decoded to RGBA8 first.
+    pub fn get_or_upload(
+        &mut self,
+        gpu_context: &GpuContext,
+        imported: &ImportedTexture,
+        data: &TextureData,
+    ) -> anyhow::Result<Handle<Texture>> {
+        let key = imported.source.as_ref().map(|path| TextureKey {
+            path: path.clone(),
+            srgb: imported.srgb,
+            float_format: imported.float_format,
+        });
+        if let Some(key) = &key
+            && let Some(&handle) = self.paths.get(key)
+            && self.textures.contains(handle)
+        {
+            return Ok(handle);
+        }
+
+        let texture = match data {
+            TextureData::Image(image) if is_float(image) => Texture::from_float_image(
+                &gpu_context.device,
+                &gpu_context.queue,
+                image,
+                Some(&imported.label),
+                imported.float_format,
+                &imported.sampler,
+            )?,
+            TextureData::Image(image) => {
+                let format = if imported.srgb {
+                    wgpu::TextureFormat::Rgba8UnormSrgb
+                } else {
+                    wgpu::TextureFormat::Rgba8Unorm
+                };
+                Texture::from_image_with(
+                    &gpu_context.device,
+                    &gpu_context.queue,
+                    image,
+                    Some(&imported.label),
+                    format,
+                    &imported.sampler,
+                )?
+            }
+            TextureData::Compressed(chain) => {
+                let mut chain = chain.clone().with_color_space(imported.srgb);
+                if !chain.is_supported(gpu_context.device.features()) {
+                    tracing::info!(
+                        "{}: {:?} is not supported by the device, decoding on the CPU",
+                        imported.label,
+                        chain.format
+                    );
+                    chain = chain.decompress()?;
+                }
+                Texture::from_mip_chain(
+                    &gpu_context.device,
+                    &gpu_context.queue,
+                    &chain,
+                    Some(&imported.label),
+                    &imported.sampler,
+                )
+            }
+        };
+        let handle = self.textures.insert(texture);
+        if let Some(key) = key {
+            self.paths.insert(key, handle);
+        }
+        Ok(handle)
+    }
+
+    /// Uncached texture, owned by whoever inserted it until a model references it.
+    pub fn insert(&mut self, texture: Texture) -> Handle<Texture> {
+        self.textures.insert(texture)
+    }
+
+    pub fn get(&self, handle: Handle<Texture>) -> Option<&Arc<Texture>> {
+        self.textures.get(handle)
+    }
+
+    pub fn acquire(&mut self, handle: Handle<Texture>) {
+        *self.refs.entry(handle).or_default() += 1;
+    }
+
+    /// Drops one reference, frees the texture with the last one. Textures that were never
+    /// acquired are left alone.
+    pub fn release(&mut self, handle: Handle<Texture>) {
+        let Some(count) = self.refs.get_mut(&handle) else {
+            return;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return;
+        }
+        self.refs.remove(&handle);
+        self.paths.retain(|_, cached| *cached != handle);
+        self.textures.remove(handle);
+    }
+
+    /// The next upload of `path` creates a new texture, for files that changed on disk.
+    /// Users of the current texture keep it until they release it.
+    pub fn forget_path(&mut self, path: &Path) {
+        self.paths.retain(|key, _| key.path != path);
+    }
+
+    pub fn ref_count(&self, handle: Handle<Texture>) -> u32 {
+        self.refs.get(&handle).copied().unwrap_or(0)
+    }
+
+    pub fn memory(&self) -> TextureMemory {
+        TextureMemory {
+            textures: self.textures.len(),
+            cached: self.paths.len(),
+            bytes: self
+                .textures
+                .iter()
+                .map(|(_, texture)| texture.memory_size())
+                .sum(),
+        }
+    }
+
+    pub fn textures(&self) -> &Assets<Texture> {
+        &self.textures
+    }
+}
+
+/// Radiance HDR and OpenEXR decode to 32 bit floats, everything else is uploaded as 8 bit
+fn is_float(image: &DynamicImage) -> bool {
+    matches!(
+        image,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    )
+}
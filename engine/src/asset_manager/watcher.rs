@@ -0,0 +1,59 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+// Polls modification times of watched files. Cheap enough for the handful of files
+// an editor session touches and needs no platform specific notification backend.
+pub struct FileWatcher {
+    files: HashMap<PathBuf, Option<SystemTime>>,
+    interval: Duration,
+    last_poll: Instant,
+}
+
+impl FileWatcher {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            files: HashMap::new(),
+            interval,
+            last_poll: Instant::now(),
+        }
+    }
+
+    pub fn watch(&mut self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        let modified = Self::modified(&path);
+        self.files.insert(path, modified);
+    }
+
+    pub fn unwatch(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    /// Files whose modification time changed since the previous poll.
+    /// Returns nothing until `interval` has passed since the last check.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < self.interval {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        self.files
+            .iter_mut()
+            .filter_map(|(path, last)| {
+                let modified = Self::modified(path);
+                // A file that is being rewritten may briefly disappear, wait until it is back
+                if modified.is_none() || modified == *last {
+                    return None;
+                }
+                *last = modified;
+                Some(path.clone())
+            })
+            .collect()
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+}
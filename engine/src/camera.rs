@@ -41,6 +41,10 @@ pub struct CameraUniform {
     // We can't use cgmath with bytemuck directly, so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
     view_proj: [[f32; 4]; 4],
+    // Used by fullscreen passes (e.g. the editor grid) to go from clip space back to world space
+    inv_view_proj: [[f32; 4]; 4],
+    // w is unused, kept for 16 byte alignment
+    view_position: [f32; 4],
 }
 
 impl CameraUniform {
@@ -48,11 +52,20 @@ impl CameraUniform {
         use cgmath::SquareMatrix;
         Self {
             view_proj: cgmath::Matrix4::identity().into(),
+            inv_view_proj: cgmath::Matrix4::identity().into(),
+            view_position: [0.0; 4],
         }
     }
 
     pub fn set_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = camera.build_view_projection_matrix().into();
+        use cgmath::SquareMatrix;
+        let view_proj = camera.build_view_projection_matrix();
+        self.view_proj = view_proj.into();
+        self.inv_view_proj = view_proj
+            .invert()
+            .unwrap_or(cgmath::Matrix4::identity())
+            .into();
+        self.view_position = camera.eye.to_homogeneous().into();
     }
 }
 
@@ -1,26 +1,102 @@
-use std::sync::Arc;
-
 use bevy_ecs::{component::Component, resource::Resource};
+use cgmath::{InnerSpace as _, Matrix3, Matrix4, SquareMatrix as _, Vector3};
 
-use crate::{instance::InstanceRaw, model::Model};
+use crate::{
+    animation::{JointMatrix, skeleton::Skeleton},
+    asset_manager::handle::Handle,
+    instance::InstanceRaw,
+    model::{Material, Model},
+};
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy, Debug)]
 pub struct Transform {
     pub position: cgmath::Vector3<f32>,
     pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            rotation: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
 }
+
 impl Transform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    /// Splits an affine matrix back into translation, rotation and scale. Shear is lost.
+    pub fn from_matrix(m: Matrix4<f32>) -> Self {
+        let mut scale = Vector3::new(
+            m.x.truncate().magnitude(),
+            m.y.truncate().magnitude(),
+            m.z.truncate().magnitude(),
+        );
+        // A mirrored basis keeps the rotation proper by flipping one axis
+        if m.determinant() < 0.0 {
+            scale.x = -scale.x;
+        }
+        let axis = |column: cgmath::Vector4<f32>, s: f32| {
+            if s == 0.0 {
+                Vector3::new(0.0, 0.0, 0.0)
+            } else {
+                column.truncate() / s
+            }
+        };
+        let rotation =
+            Matrix3::from_cols(axis(m.x, scale.x), axis(m.y, scale.y), axis(m.z, scale.z));
+
+        Self {
+            position: m.w.truncate(),
+            rotation: cgmath::Quaternion::from(rotation).normalize(),
+            scale,
+        }
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
         InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation))
-            .into(),
+            model: self.matrix().into(),
+            joint_offset: InstanceRaw::NOT_SKINNED,
+            morph_offset: InstanceRaw::NOT_MORPHED,
         }
     }
 }
 
 #[derive(Component)]
-pub struct MeshHandle(pub Arc<Model>);
+pub struct MeshHandle(pub Handle<Model>);
+
+/// Draws every mesh of the entity's [`MeshHandle`] with this material instead of the ones the
+/// model was imported with, e.g. one loaded with
+/// [`AssetManager::load_material`](crate::asset_manager::AssetManager::load_material)
+#[derive(Component)]
+pub struct MaterialOverride(pub Handle<Material>);
+
+/// Deforms the entity's [`MeshHandle`] with the joints of `skeleton`
+#[derive(Component)]
+pub struct SkinnedMesh {
+    pub skeleton: Handle<Skeleton>,
+}
+
+/// Skinning matrices of the current frame, written by [`crate::animation::animate`]
+#[derive(Component, Default)]
+pub struct JointMatrices(pub Vec<JointMatrix>);
+
+/// Blend shape weights of the entity's [`MeshHandle`], one per morph target.
+/// Set them directly or let an [`AnimationPlayer`](crate::animation::player::AnimationPlayer)
+/// drive them.
+#[derive(Component, Clone, Debug, Default)]
+pub struct MorphWeights(pub Vec<f32>);
+
+/// Node of the source file the entity was spawned from, animation channels target nodes
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceNode(pub usize);
 
 #[derive(Component)]
 pub struct Name(pub String);
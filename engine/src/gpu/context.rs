@@ -34,7 +34,9 @@ impl GpuContext {
 
         let device_desc = wgpu::DeviceDescriptor {
             label: None,
-            required_features: wgpu::Features::POLYGON_MODE_LINE,
+            // The pipeline cache is only available on some backends (Vulkan), use it when we can
+            required_features: wgpu::Features::POLYGON_MODE_LINE
+                | (adapter.features() & wgpu::Features::PIPELINE_CACHE),
             // WebGL doesn't support all of wgpu's features, so if
             // we're building for the web we'll have to disable some.
             required_limits: if cfg!(target_arch = "wasm32") {
@@ -1,3 +1,5 @@
 pub mod context;
 pub mod pipeline;
+pub mod pipeline_cache;
 pub mod resource;
+pub mod shader;
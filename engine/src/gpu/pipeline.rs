@@ -8,10 +8,15 @@ pub struct PipelineBuilder<'a> {
     bind_group_layouts: Vec<Option<&'a wgpu::BindGroupLayout>>,
     target_format: wgpu::TextureFormat,
     depth_format: Option<wgpu::TextureFormat>,
+    depth_write: bool,
+    depth_compare: wgpu::CompareFunction,
+    blend: Option<wgpu::BlendState>,
     polygon_mode: wgpu::PolygonMode,
     cull_mode: Option<wgpu::Face>,
     topology: wgpu::PrimitiveTopology,
     topology_strip_index_format: Option<wgpu::IndexFormat>,
+    sample_count: u32,
+    cache: Option<&'a wgpu::PipelineCache>,
 }
 
 impl<'a> PipelineBuilder<'a> {
@@ -26,10 +31,15 @@ impl<'a> PipelineBuilder<'a> {
             bind_group_layouts: Vec::new(),
             target_format: format,
             depth_format: None,
+            depth_write: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            blend: Some(wgpu::BlendState::REPLACE),
             polygon_mode: wgpu::PolygonMode::Fill,
             cull_mode: Some(wgpu::Face::Back),
             topology: wgpu::PrimitiveTopology::TriangleList,
             topology_strip_index_format: None,
+            sample_count: 1,
+            cache: None,
         }
     }
 
@@ -57,6 +67,23 @@ impl<'a> PipelineBuilder<'a> {
         self.depth_format = Some(format);
         self
     }
+
+    /// Только вместе с with_depth. Прозрачным проходам (сетка) запись глубины обычно не нужна
+    pub fn with_depth_write(mut self, enabled: bool) -> Self {
+        self.depth_write = enabled;
+        self
+    }
+
+    pub fn with_depth_compare(mut self, compare: wgpu::CompareFunction) -> Self {
+        self.depth_compare = compare;
+        self
+    }
+
+    /// None отключает смешивание, по умолчанию REPLACE
+    pub fn with_blend(mut self, blend: Option<wgpu::BlendState>) -> Self {
+        self.blend = blend;
+        self
+    }
     /// Позволяет изменить названия входных точек шейдера
     pub fn with_entry_points(mut self, vertex: &str, fragment: &str) -> Self {
         self.vertex_entry = vertex.into();
@@ -78,7 +105,7 @@ impl<'a> PipelineBuilder<'a> {
     }
 
     /// In ANY strip format use with_topology_strip_index_format
-    /// ```
+    /// ```text
     /// PointList,
     /// LineList,
     /// LineStrip, <- use with_topology_strip_index_format()
@@ -96,6 +123,18 @@ impl<'a> PipelineBuilder<'a> {
         self
     }
 
+    /// Для MSAA, должно совпадать с sample_count целевой текстуры
+    pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    /// Драйверный кэш пайплайнов (только где бэкенд поддерживает PIPELINE_CACHE)
+    pub fn with_cache(mut self, cache: Option<&'a wgpu::PipelineCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
     pub fn build(self) -> wgpu::RenderPipeline {
         let shader = self.shader.expect("Shader module is required for pipeline");
 
@@ -122,7 +161,7 @@ impl<'a> PipelineBuilder<'a> {
                     entry_point: Some(&self.fragment_entry),
                     targets: &[Some(wgpu::ColorTargetState {
                         format: self.target_format,
-                        blend: Some(wgpu::BlendState::REPLACE),
+                        blend: self.blend,
                         write_mask: wgpu::ColorWrites::ALL,
                     })],
                     compilation_options: Default::default(),
@@ -137,14 +176,17 @@ impl<'a> PipelineBuilder<'a> {
                 },
                 depth_stencil: self.depth_format.map(|format| wgpu::DepthStencilState {
                     format,
-                    depth_write_enabled: Some(true),
-                    depth_compare: Some(wgpu::CompareFunction::Less),
+                    depth_write_enabled: Some(self.depth_write),
+                    depth_compare: Some(self.depth_compare),
                     stencil: wgpu::StencilState::default(),
                     bias: wgpu::DepthBiasState::default(),
                 }),
-                multisample: wgpu::MultisampleState::default(),
+                multisample: wgpu::MultisampleState {
+                    count: self.sample_count,
+                    ..Default::default()
+                },
                 multiview_mask: None,
-                cache: None,
+                cache: self.cache,
             })
     }
 }
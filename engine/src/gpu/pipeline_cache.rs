@@ -0,0 +1,324 @@
+use std::collections::{HashMap, HashSet};
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+
+use crate::{
+    gpu::{
+        context::GpuContext,
+        pipeline::PipelineBuilder,
+        shader::{ShaderKey, ShaderLibrary, catch_validation},
+    },
+    instance::InstanceRaw,
+    model::{ModelVertex, SkinVertex, Vertex},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    #[default]
+    Opaque,
+    Alpha,
+    Premultiplied,
+    Additive,
+}
+
+impl BlendMode {
+    fn state(self) -> Option<wgpu::BlendState> {
+        match self {
+            BlendMode::Opaque => Some(wgpu::BlendState::REPLACE),
+            BlendMode::Alpha => Some(wgpu::BlendState::ALPHA_BLENDING),
+            BlendMode::Premultiplied => Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            BlendMode::Additive => Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent::OVER,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DepthState {
+    pub format: wgpu::TextureFormat,
+    pub write: bool,
+    pub compare: wgpu::CompareFunction,
+}
+
+/// Vertex buffers a pipeline consumes. `wgpu::VertexBufferLayout` isn't hashable, so pipelines
+/// name one of the layouts the engine knows about instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VertexLayout {
+    /// Nothing bound, vertices are generated in the shader (fullscreen passes)
+    None,
+    /// `ModelVertex` in slot 0, `InstanceRaw` in slot 1
+    ModelInstanced,
+    /// `ModelInstanced` plus `SkinVertex` in slot 2
+    ModelInstancedSkinned,
+}
+
+impl VertexLayout {
+    fn buffers(self) -> Vec<wgpu::VertexBufferLayout<'static>> {
+        match self {
+            VertexLayout::None => Vec::new(),
+            VertexLayout::ModelInstanced => vec![ModelVertex::desc(), InstanceRaw::desc()],
+            VertexLayout::ModelInstancedSkinned => {
+                vec![ModelVertex::desc(), InstanceRaw::desc(), SkinVertex::desc()]
+            }
+        }
+    }
+}
+
+/// Bind group layouts a pipeline is built against, registered once with
+/// [`PipelineCache::register_bindings`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Bindings {
+    /// group 0 material, group 1 camera
+    Scene,
+    /// `Scene` plus the joint matrix and morph weight storage buffers at group 2
+    SceneSkinned,
+    /// `SceneSkinned` plus the morph target deltas of the mesh at group 3
+    SceneMorph,
+    /// group 0 camera
+    Camera,
+}
+
+/// Everything that makes two render pipelines different
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub shader: ShaderKey,
+    pub bindings: Bindings,
+    pub vertex_layout: VertexLayout,
+    pub blend: BlendMode,
+    pub cull_mode: Option<wgpu::Face>,
+    pub polygon_mode: wgpu::PolygonMode,
+    pub depth: Option<DepthState>,
+    pub sample_count: u32,
+    pub format: wgpu::TextureFormat,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct DiskCache {
+    cache: wgpu::PipelineCache,
+    path: PathBuf,
+}
+
+/// Builds render pipeline variants the first time they are asked for and hands out the same
+/// pipeline afterwards. Where the backend supports it, the driver side cache is saved to
+/// the cache folder on drop so the next run skips shader compilation.
+pub struct PipelineCache {
+    pipelines: HashMap<PipelineKey, wgpu::RenderPipeline>,
+    // Keys that built neither with their shader nor with its fallback, retried when the
+    // shader is reloaded
+    failed: HashSet<PipelineKey>,
+    bindings: HashMap<Bindings, Vec<wgpu::BindGroupLayout>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    disk: Option<DiskCache>,
+}
+
+impl PipelineCache {
+    pub fn new(gpu_context: &GpuContext) -> Self {
+        Self {
+            pipelines: HashMap::new(),
+            failed: HashSet::new(),
+            bindings: HashMap::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            disk: Self::open_disk_cache(gpu_context),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn open_disk_cache(gpu_context: &GpuContext) -> Option<DiskCache> {
+        let device = &gpu_context.device;
+        if !device.features().contains(wgpu::Features::PIPELINE_CACHE) {
+            return None;
+        }
+        let key = wgpu::util::pipeline_cache_key(&gpu_context.adapter.get_info())?;
+        let path = crate::asset_manager::io::get_cache_folder().ok()?.join(key);
+        let data = std::fs::read(&path).ok();
+
+        // SAFETY: the file is only ever written by `save` from a cache created for the same
+        // adapter (the file name comes from pipeline_cache_key), and `fallback` makes the
+        // driver start empty if it still rejects the data
+        let cache = unsafe {
+            device.create_pipeline_cache(&wgpu::PipelineCacheDescriptor {
+                label: Some("Pipeline Cache"),
+                data: data.as_deref(),
+                fallback: true,
+            })
+        };
+        tracing::info!(
+            "Pipeline cache {} ({})",
+            path.display(),
+            if data.is_some() { "loaded" } else { "new" }
+        );
+        Some(DiskCache { cache, path })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn driver_cache(&self) -> Option<&wgpu::PipelineCache> {
+        self.disk.as_ref().map(|d| &d.cache)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn driver_cache(&self) -> Option<&wgpu::PipelineCache> {
+        None
+    }
+
+    pub fn register_bindings(&mut self, bindings: Bindings, layouts: Vec<wgpu::BindGroupLayout>) {
+        self.bindings.insert(bindings, layouts);
+    }
+
+    /// `None` when neither the shader nor its fallback builds a valid pipeline for `key`,
+    /// the error is on the shader (see [`ShaderLibrary::errors`]) and the caller skips the
+    /// draw.
+    pub fn get(
+        &mut self,
+        device: &wgpu::Device,
+        shaders: &mut ShaderLibrary,
+        key: &PipelineKey,
+    ) -> Option<&wgpu::RenderPipeline> {
+        if !self.pipelines.contains_key(key) && !self.failed.contains(key) {
+            // A shader from a material file may not match the bindings or vertex layout
+            let built = self.try_build(device, shaders, key).or_else(|e| {
+                tracing::error!("Pipeline for {} failed to build: {e}", key.shader);
+                shaders.set_error(&key.shader, Some(e.clone()));
+                let fallback = PipelineKey {
+                    shader: ShaderKey::fallback(),
+                    ..key.clone()
+                };
+                if fallback == *key {
+                    return Err(e);
+                }
+                self.try_build(device, shaders, &fallback).inspect_err(|e| {
+                    tracing::error!("Fallback pipeline for {} failed too: {e}", key.shader)
+                })
+            });
+            match built {
+                Ok(pipeline) => {
+                    self.pipelines.insert(key.clone(), pipeline);
+                }
+                Err(_) => {
+                    self.failed.insert(key.clone());
+                }
+            }
+        }
+        self.pipelines.get(key)
+    }
+
+    /// Rebuilds every cached variant of a shader that was just reloaded, failed ones
+    /// included. Variants that fail keep their previous pipeline, their errors are returned
+    /// and the others are still rebuilt.
+    pub fn rebuild_shader(
+        &mut self,
+        device: &wgpu::Device,
+        shaders: &mut ShaderLibrary,
+        shader: &ShaderKey,
+    ) -> Vec<(PipelineKey, String)> {
+        let keys: Vec<PipelineKey> = self
+            .pipelines
+            .keys()
+            .chain(&self.failed)
+            .filter(|k| k.shader == *shader)
+            .cloned()
+            .collect();
+        let mut errors = Vec::new();
+        for key in keys {
+            match self.try_build(device, shaders, &key) {
+                Ok(pipeline) => {
+                    self.failed.remove(&key);
+                    self.pipelines.insert(key, pipeline);
+                }
+                Err(e) => errors.push((key, e)),
+            }
+        }
+        errors
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+
+    /// [`Self::build`], with wgpu validation errors caught
+    fn try_build(
+        &self,
+        device: &wgpu::Device,
+        shaders: &mut ShaderLibrary,
+        key: &PipelineKey,
+    ) -> Result<wgpu::RenderPipeline, String> {
+        catch_validation(device, || self.build(device, shaders, key))?
+    }
+
+    fn build(
+        &self,
+        device: &wgpu::Device,
+        shaders: &mut ShaderLibrary,
+        key: &PipelineKey,
+    ) -> Result<wgpu::RenderPipeline, String> {
+        let shader = shaders.get(device, &key.shader)?;
+        let label = format!("{} Pipeline", key.shader);
+        tracing::debug!("Building pipeline {key:?}");
+
+        let mut builder = PipelineBuilder::new(device, key.format)
+            .with_label(&label)
+            .with_shader(shader)
+            .with_blend(key.blend.state())
+            .with_culling(key.cull_mode)
+            .with_polygon_mode(key.polygon_mode)
+            .with_sample_count(key.sample_count)
+            .with_cache(self.driver_cache());
+
+        let layouts = self
+            .bindings
+            .get(&key.bindings)
+            .unwrap_or_else(|| panic!("Bindings {:?} were never registered", key.bindings));
+        for layout in layouts {
+            builder = builder.add_layout(layout);
+        }
+        for buffer in key.vertex_layout.buffers() {
+            builder = builder.add_vertex_layout(Some(buffer));
+        }
+        if let Some(depth) = key.depth {
+            builder = builder
+                .with_depth(depth.format)
+                .with_depth_write(depth.write)
+                .with_depth_compare(depth.compare);
+        }
+        Ok(builder.build())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self) {
+        let Some(disk) = &self.disk else {
+            return;
+        };
+        let Some(data) = disk.cache.get_data() else {
+            return;
+        };
+        let result = (|| -> std::io::Result<()> {
+            if let Some(parent) = disk.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            // write next to the target and rename, a crash mid-write must not leave a torn cache
+            let tmp = disk.path.with_extension("tmp");
+            std::fs::write(&tmp, &data)?;
+            std::fs::rename(&tmp, &disk.path)
+        })();
+        match result {
+            Ok(()) => tracing::info!("Saved pipeline cache ({} bytes)", data.len()),
+            Err(e) => tracing::warn!("Can't save pipeline cache {}: {e}", disk.path.display()),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for PipelineCache {
+    fn drop(&mut self) {
+        self.save();
+    }
+}
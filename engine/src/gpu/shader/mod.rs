@@ -0,0 +1,381 @@
+pub mod preprocess;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use cfg_if::cfg_if;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::asset_manager::{io::get_assets_folder, watcher::FileWatcher};
+use crate::gpu::shader::preprocess::{Preprocessed, preprocess};
+
+/// Shaders shipped with the engine, relative to the assets folder
+pub const SCENE_SHADER: &str = "shaders/shader.wgsl";
+pub const GRID_SHADER: &str = "shaders/grid.wgsl";
+
+// Used in release builds, on the web, and whenever the file on disk can't be read.
+// Anything reachable through #include has to be listed here too.
+const EMBEDDED: &[(&str, &str)] = &[
+    (
+        SCENE_SHADER,
+        include_str!("../../../../assets/shaders/shader.wgsl"),
+    ),
+    (
+        GRID_SHADER,
+        include_str!("../../../../assets/shaders/grid.wgsl"),
+    ),
+    (
+        "shaders/camera.wgsl",
+        include_str!("../../../../assets/shaders/camera.wgsl"),
+    ),
+];
+
+/// One permutation of a shader: the entry file plus the defines it is compiled with
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ShaderKey {
+    pub path: String,
+    pub defines: BTreeMap<String, String>,
+}
+
+impl ShaderKey {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            defines: BTreeMap::new(),
+        }
+    }
+
+    pub fn define(self, name: impl Into<String>) -> Self {
+        self.define_value(name, "")
+    }
+
+    pub fn define_value(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.defines.insert(name.into(), value.into());
+        self
+    }
+
+    /// Stands in when a permutation does not build: the scene shader without defines. A
+    /// define from a material file may be what broke the build, so none are carried over.
+    pub fn fallback() -> ShaderKey {
+        ShaderKey::new(SCENE_SHADER)
+    }
+
+    fn is_embedded(&self) -> bool {
+        EMBEDDED.iter().any(|(path, _)| *path == self.path)
+    }
+}
+
+impl fmt::Display for ShaderKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path)?;
+        if !self.defines.is_empty() {
+            let defines: Vec<String> = self
+                .defines
+                .iter()
+                .map(|(name, value)| match value.is_empty() {
+                    true => name.clone(),
+                    false => format!("{name}={value}"),
+                })
+                .collect();
+            write!(f, " [{}]", defines.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+struct ShaderEntry {
+    // `None` when neither the permutation nor the fallback compiled
+    module: Option<wgpu::ShaderModule>,
+    // Every file the permutation was built from, a change in any of them triggers a rebuild
+    files: Vec<String>,
+    // Last compile or pipeline error, cleared by the next successful reload
+    error: Option<String>,
+}
+
+/// Compiles shader permutations on first use and caches them by [`ShaderKey`].
+/// With hot reloading on, sources come from the assets folder and are recompiled
+/// when any file they include changes; a broken edit keeps the previous module
+/// and records the naga diagnostic instead.
+pub struct ShaderLibrary {
+    modules: HashMap<ShaderKey, ShaderEntry>,
+    hot_reload: bool,
+    #[cfg(not(target_arch = "wasm32"))]
+    watcher: FileWatcher,
+}
+
+impl ShaderLibrary {
+    pub fn new(hot_reload: bool) -> Self {
+        Self {
+            modules: HashMap::new(),
+            hot_reload: hot_reload && !cfg!(target_arch = "wasm32"),
+            #[cfg(not(target_arch = "wasm32"))]
+            watcher: FileWatcher::new(std::time::Duration::from_millis(250)),
+        }
+    }
+
+    /// A permutation that does not compile gets the module of its fallback, the error is
+    /// kept for [`Self::errors`]. Fails only when the fallback does not compile either.
+    pub fn get(
+        &mut self,
+        device: &wgpu::Device,
+        key: &ShaderKey,
+    ) -> Result<&wgpu::ShaderModule, String> {
+        if !self.modules.contains_key(key) {
+            let entry = match compile(device, key, self.hot_reload) {
+                Ok((module, files)) => ShaderEntry {
+                    module: Some(module),
+                    files,
+                    error: None,
+                },
+                Err(e) => {
+                    tracing::error!("{e}");
+                    // A broken edit of an engine shader on disk keeps the permutation
+                    // through the embedded copy
+                    let embedded = (self.hot_reload && key.is_embedded()).then(|| key.clone());
+                    let (module, mut files) = embedded
+                        .into_iter()
+                        .chain([ShaderKey::fallback()])
+                        .find_map(|fallback| compile(device, &fallback, false).ok())
+                        .map_or((None, Vec::new()), |(module, files)| (Some(module), files));
+                    // Fixing the file recompiles it
+                    files.push(key.path.clone());
+                    ShaderEntry {
+                        module,
+                        files,
+                        error: Some(e),
+                    }
+                }
+            };
+            self.watch(&entry.files);
+            self.modules.insert(key.clone(), entry);
+        }
+        let entry = &self.modules[key];
+        entry.module.as_ref().ok_or_else(|| {
+            let error = entry.error.as_deref().unwrap_or_default();
+            format!(
+                "{error}
+The fallback {} does not compile either",
+                ShaderKey::fallback()
+            )
+        })
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = (&ShaderKey, &str)> {
+        self.modules
+            .iter()
+            .filter_map(|(key, entry)| Some((key, entry.error.as_deref()?)))
+    }
+
+    /// Pipelines can still fail after the module compiled (e.g. a changed entry point signature)
+    pub fn set_error(&mut self, key: &ShaderKey, error: Option<String>) {
+        if let Some(entry) = self.modules.get_mut(key) {
+            entry.error = error;
+        }
+    }
+
+    /// Recompiles permutations whose files changed on disk.
+    /// Returns the ones that compiled, their pipelines have to be rebuilt.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Vec<ShaderKey> {
+        cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                let _ = device;
+                Vec::new()
+            } else {
+                if !self.hot_reload {
+                    return Vec::new();
+                }
+                let Ok(folder) = get_assets_folder() else {
+                    return Vec::new();
+                };
+                let changed: Vec<String> = self
+                    .watcher
+                    .poll()
+                    .iter()
+                    .filter_map(|p| p.strip_prefix(&folder).ok())
+                    .map(|p| p.to_string_lossy().replace('\\', "/"))
+                    .collect();
+                if changed.is_empty() {
+                    return Vec::new();
+                }
+
+                let mut reloaded = Vec::new();
+                let mut new_files = Vec::new();
+                for (key, entry) in self.modules.iter_mut() {
+                    if !entry.files.iter().any(|f| changed.contains(f)) {
+                        continue;
+                    }
+                    match compile(device, key, true) {
+                        Ok((module, files)) => {
+                            tracing::info!("Reloaded shader {key}");
+                            entry.module = Some(module);
+                            entry.error = None;
+                            new_files.extend(files.iter().cloned());
+                            entry.files = files;
+                            reloaded.push(key.clone());
+                        }
+                        Err(e) => {
+                            tracing::error!("Shader {key} failed to reload, keeping the last working version:\n{e}");
+                            entry.error = Some(e);
+                        }
+                    }
+                }
+                // an edit may have pulled in a new #include
+                self.watch(&new_files);
+                reloaded
+            }
+        }
+    }
+
+    fn watch(&mut self, files: &[String]) {
+        cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                let _ = files;
+            } else {
+                if !self.hot_reload {
+                    return;
+                }
+                if let Ok(folder) = get_assets_folder() {
+                    files.iter().for_each(|f| self.watcher.watch(folder.join(f)));
+                }
+            }
+        }
+    }
+}
+
+/// Runs `f` inside a validation error scope, so a broken pipeline or module comes back
+/// as an error instead of reaching the uncaptured error handler, which panics.
+pub fn catch_validation<T>(device: &wgpu::Device, f: impl FnOnce() -> T) -> Result<T, String> {
+    let scope = device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = f();
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            // On the web the scope resolves asynchronously, errors still show up in the console
+            drop(scope.pop());
+            Ok(value)
+        } else {
+            match pollster::block_on(scope.pop()) {
+                Some(e) => Err(e.to_string()),
+                None => Ok(value),
+            }
+        }
+    }
+}
+
+fn compile(
+    device: &wgpu::Device,
+    key: &ShaderKey,
+    from_disk: bool,
+) -> Result<(wgpu::ShaderModule, Vec<String>), String> {
+    let preprocessed = preprocess(&key.path, &key.defines, &mut |path| {
+        load_source(path, from_disk)
+    })
+    .map_err(|e| e.to_string())?;
+    let source = &preprocessed.source;
+    let label = key.to_string();
+
+    // naga gives readable diagnostics, wgpu would just panic
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| {
+        describe(
+            &preprocessed,
+            e.location(source),
+            e.emit_to_string_with_path(source, &label),
+        )
+    })?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::default(),
+    )
+    .validate(&module)
+    .map_err(|e| {
+        describe(
+            &preprocessed,
+            e.location(source),
+            e.emit_to_string_with_path(source, &label),
+        )
+    })?;
+
+    let module = catch_validation(device, || {
+        device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(&label),
+            source: wgpu::ShaderSource::Wgsl(source.as_str().into()),
+        })
+    })?;
+    Ok((module, preprocessed.files))
+}
+
+// naga reports lines of the flattened source, point at the file the line came from
+fn describe(
+    preprocessed: &Preprocessed,
+    location: Option<naga::SourceLocation>,
+    diagnostic: String,
+) -> String {
+    match location.and_then(|l| preprocessed.origin(l.line_number)) {
+        Some((file, line)) => format!("{file}:{line}\n{diagnostic}"),
+        None => diagnostic,
+    }
+}
+
+fn load_source(path: &str, from_disk: bool) -> anyhow::Result<String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if from_disk {
+        return Ok(std::fs::read_to_string(get_assets_folder()?.join(path))?);
+    }
+    #[cfg(target_arch = "wasm32")]
+    let _ = from_disk;
+
+    if let Some((_, source)) = EMBEDDED.iter().find(|(p, _)| *p == path) {
+        return Ok(source.to_string());
+    }
+    // Shaders the engine does not ship are always read from the assets folder
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            anyhow::bail!("{path} is not an embedded shader")
+        } else {
+            Ok(std::fs::read_to_string(get_assets_folder()?.join(path))?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::asset_manager::{io::Io, material_file::MaterialLoader, source::MemorySource};
+
+    /// `None` where there is no adapter at all, not even a software one
+    fn device() -> Option<wgpu::Device> {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&Default::default())).ok()?;
+        let (device, _queue) =
+            pollster::block_on(adapter.request_device(&Default::default())).ok()?;
+        Some(device)
+    }
+
+    #[test]
+    fn material_defines_that_break_the_shader_fall_back_to_the_bare_scene_shader() {
+        let Some(device) = device() else {
+            eprintln!("No wgpu adapter, skipped");
+            return;
+        };
+        // Substituted for the struct's name, the scene shader no longer parses
+        let mount = Io::mount(
+            "shader-tests",
+            0,
+            MemorySource::new().with_file("broken.ron", r#"(defines: ["Material=1"])"#),
+        );
+        let file = pollster::block_on(MaterialLoader::load(Path::new("shader-tests/broken.ron")));
+        Io::unmount(mount);
+        let key = file.unwrap().material.render_state.shader;
+        assert_eq!(ShaderKey::fallback(), ShaderKey::new(SCENE_SHADER));
+        assert_ne!(key, ShaderKey::fallback());
+
+        let mut shaders = ShaderLibrary::new(false);
+        assert!(shaders.get(&device, &key).is_ok());
+        let errors: Vec<_> = shaders.errors().collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(*errors[0].0, key);
+    }
+}
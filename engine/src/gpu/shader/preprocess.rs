@@ -0,0 +1,428 @@
+// Minimal WGSL preprocessor.
+//
+//   #include "camera.wgsl"      relative to the including file, every file is pasted at most once
+//   #define NAME [value]        a value is substituted for NAME as a whole identifier, not in
+//                               comments or inside numbers
+//   #undef NAME
+//   #ifdef NAME / #ifndef NAME / #else / #endif
+//
+// Every output line remembers where it came from, so errors reported against the
+// flattened source can be pointed back at the original file and line.
+use std::{collections::BTreeMap, fmt};
+
+#[derive(Debug, Clone)]
+pub struct PreprocessError {
+    pub file: String,
+    pub line: u32,
+    pub message: String,
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for PreprocessError {}
+
+pub struct Preprocessed {
+    pub source: String,
+    /// Every file that ended up in the output, the entry file first
+    pub files: Vec<String>,
+    // (index into files, 1-based line) for each output line
+    lines: Vec<(usize, u32)>,
+}
+
+impl Preprocessed {
+    /// Original file and line for a 1-based line of the flattened source
+    pub fn origin(&self, line: u32) -> Option<(&str, u32)> {
+        let (file, line) = *self.lines.get(line.checked_sub(1)? as usize)?;
+        Some((&self.files[file], line))
+    }
+}
+
+struct Condition {
+    active: bool,
+    // whether the enclosing block is active, #else can only turn a branch on if it is
+    parent_active: bool,
+    seen_else: bool,
+    line: u32,
+}
+
+struct Preprocessor<'a> {
+    defines: BTreeMap<String, String>,
+    load: &'a mut dyn FnMut(&str) -> anyhow::Result<String>,
+    output: String,
+    files: Vec<String>,
+    lines: Vec<(usize, u32)>,
+    include_stack: Vec<String>,
+}
+
+pub fn preprocess(
+    path: &str,
+    defines: &BTreeMap<String, String>,
+    load: &mut dyn FnMut(&str) -> anyhow::Result<String>,
+) -> Result<Preprocessed, PreprocessError> {
+    let mut preprocessor = Preprocessor {
+        defines: defines.clone(),
+        load,
+        output: String::new(),
+        files: Vec::new(),
+        lines: Vec::new(),
+        include_stack: Vec::new(),
+    };
+    preprocessor.process_file(path, None)?;
+
+    Ok(Preprocessed {
+        source: preprocessor.output,
+        files: preprocessor.files,
+        lines: preprocessor.lines,
+    })
+}
+
+impl Preprocessor<'_> {
+    fn process_file(
+        &mut self,
+        path: &str,
+        included_from: Option<(&str, u32)>,
+    ) -> Result<(), PreprocessError> {
+        let error_at_include = |message: String| {
+            let (file, line) = included_from.unwrap_or((path, 0));
+            PreprocessError {
+                file: file.to_owned(),
+                line,
+                message,
+            }
+        };
+
+        if self.include_stack.iter().any(|p| p == path) {
+            return Err(error_at_include(format!(
+                "circular include of {path} ({})",
+                self.include_stack.join(" -> ")
+            )));
+        }
+        if self.files.iter().any(|p| p == path) {
+            return Ok(());
+        }
+
+        let source = (self.load)(path).map_err(|e| error_at_include(format!("{path}: {e}")))?;
+        let file_index = self.files.len();
+        self.files.push(path.to_owned());
+        self.include_stack.push(path.to_owned());
+
+        let error = |line: u32, message: String| PreprocessError {
+            file: path.to_owned(),
+            line,
+            message,
+        };
+
+        let mut conditions: Vec<Condition> = Vec::new();
+        // Nesting depth of /* */ comments at the start of the current line
+        let mut comment_depth = 0;
+        for (index, raw) in source.lines().enumerate() {
+            let line = index as u32 + 1;
+            let active = conditions.last().is_none_or(|c| c.active);
+
+            let Some(directive) = raw.trim_start().strip_prefix('#') else {
+                if active {
+                    let substituted = self.substitute(raw, &mut comment_depth);
+                    self.output.push_str(&substituted);
+                    self.output.push('\n');
+                    self.lines.push((file_index, line));
+                }
+                continue;
+            };
+
+            let (name, argument) = directive
+                .split_once(char::is_whitespace)
+                .map(|(name, argument)| (name, argument.trim()))
+                .unwrap_or((directive.trim(), ""));
+
+            match name {
+                "ifdef" | "ifndef" => {
+                    if argument.is_empty() {
+                        return Err(error(line, format!("#{name} needs a name")));
+                    }
+                    let defined = self.defines.contains_key(argument);
+                    conditions.push(Condition {
+                        active: active && (defined == (name == "ifdef")),
+                        parent_active: active,
+                        seen_else: false,
+                        line,
+                    });
+                }
+                "else" => {
+                    let Some(condition) = conditions.last_mut() else {
+                        return Err(error(line, "#else without #ifdef".into()));
+                    };
+                    if condition.seen_else {
+                        return Err(error(line, "second #else in the same block".into()));
+                    }
+                    condition.seen_else = true;
+                    condition.active = condition.parent_active && !condition.active;
+                }
+                "endif" => {
+                    if conditions.pop().is_none() {
+                        return Err(error(line, "#endif without #ifdef".into()));
+                    }
+                }
+                _ if !active => {}
+                "define" => {
+                    let (define, value) = argument
+                        .split_once(char::is_whitespace)
+                        .map(|(n, v)| (n, v.trim()))
+                        .unwrap_or((argument, ""));
+                    if define.is_empty() {
+                        return Err(error(line, "#define needs a name".into()));
+                    }
+                    self.defines.insert(define.to_owned(), value.to_owned());
+                }
+                "undef" => {
+                    self.defines.remove(argument);
+                }
+                "include" => {
+                    let Some(include) =
+                        argument.strip_prefix('"').and_then(|a| a.strip_suffix('"'))
+                    else {
+                        return Err(error(line, "expected #include \"file.wgsl\"".into()));
+                    };
+                    let resolved = resolve(path, include);
+                    self.process_file(&resolved, Some((path, line)))?;
+                }
+                other => return Err(error(line, format!("unknown directive #{other}"))),
+            }
+        }
+
+        if let Some(condition) = conditions.last() {
+            return Err(error(
+                condition.line,
+                "#ifdef is never closed with #endif".into(),
+            ));
+        }
+        self.include_stack.pop();
+        Ok(())
+    }
+
+    // Replaces identifiers that have a #define value. Numbers are skipped as a whole so
+    // `1e5` keeps its exponent, comments (WGSL block comments nest) are copied as they are.
+    fn substitute(&self, line: &str, comment_depth: &mut u32) -> String {
+        let mut out = String::with_capacity(line.len());
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            let token_len = if *comment_depth > 0 {
+                if rest.starts_with("*/") {
+                    *comment_depth -= 1;
+                    2
+                } else if rest.starts_with("/*") {
+                    *comment_depth += 1;
+                    2
+                } else {
+                    c.len_utf8()
+                }
+            } else if rest.starts_with("//") {
+                rest.len()
+            } else if rest.starts_with("/*") {
+                *comment_depth += 1;
+                2
+            } else if c.is_ascii_alphanumeric() || c == '_' {
+                let number = c.is_ascii_digit();
+                let end = rest
+                    .find(|c: char| {
+                        !(c.is_ascii_alphanumeric() || c == '_' || (number && c == '.'))
+                    })
+                    .unwrap_or(rest.len());
+                let token = &rest[..end];
+                match self.defines.get(token) {
+                    Some(value) if !number && !value.is_empty() => out.push_str(value),
+                    _ => out.push_str(token),
+                }
+                rest = &rest[end..];
+                continue;
+            } else {
+                c.len_utf8()
+            };
+            out.push_str(&rest[..token_len]);
+            rest = &rest[token_len..];
+        }
+        out
+    }
+}
+
+// `include` relative to the directory of `from`, with `.` and `..` folded away
+fn resolve(from: &str, include: &str) -> String {
+    let mut parts: Vec<&str> = from.split('/').collect();
+    parts.pop();
+    for part in include.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    // The first file is the entry
+    fn run(
+        files: &[(&str, &str)],
+        defines: &[(&str, &str)],
+    ) -> Result<Preprocessed, PreprocessError> {
+        let sources: HashMap<&str, &str> = files.iter().copied().collect();
+        let defines = defines
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        preprocess(files[0].0, &defines, &mut |path| {
+            sources
+                .get(path)
+                .map(|source| source.to_string())
+                .ok_or_else(|| anyhow::anyhow!("not found"))
+        })
+    }
+
+    fn lines(source: &str) -> Vec<&str> {
+        source.lines().map(str::trim).collect()
+    }
+
+    #[test]
+    fn circular_includes_are_errors() {
+        let error = run(
+            &[
+                ("a/main.wgsl", "#include \"b.wgsl\""),
+                ("a/b.wgsl", "// b\n#include \"main.wgsl\""),
+            ],
+            &[],
+        )
+        .err()
+        .expect("cycle");
+        assert_eq!(error.file, "a/b.wgsl");
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("circular include"), "{error}");
+    }
+
+    #[test]
+    fn files_are_included_once() {
+        let out = run(
+            &[
+                ("a/main.wgsl", "#include \"b.wgsl\"\n#include \"c.wgsl\""),
+                ("a/b.wgsl", "#include \"c.wgsl\"\nb"),
+                ("a/c.wgsl", "c"),
+            ],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(lines(&out.source), ["c", "b"]);
+        assert_eq!(out.files, ["a/main.wgsl", "a/b.wgsl", "a/c.wgsl"]);
+    }
+
+    #[test]
+    fn includes_resolve_relative_paths() {
+        let out = run(
+            &[
+                ("a/shaders/main.wgsl", "#include \"../common/./x.wgsl\""),
+                ("a/common/x.wgsl", "x"),
+            ],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(lines(&out.source), ["x"]);
+    }
+
+    #[test]
+    fn nested_conditions() {
+        let source = "\
+#ifdef A
+  #ifdef B
+    ab
+  #else
+    a_not_b
+  #endif
+#else
+  #ifndef B
+    not_a_not_b
+  #else
+    not_a_b
+  #endif
+#endif";
+        let cases = [
+            (&[("A", ""), ("B", "")][..], "ab"),
+            (&[("A", "")][..], "a_not_b"),
+            (&[("B", "")][..], "not_a_b"),
+            (&[][..], "not_a_not_b"),
+        ];
+        for (defines, expected) in cases {
+            let out = run(&[("main.wgsl", source)], defines).unwrap();
+            assert_eq!(lines(&out.source), [expected], "{defines:?}");
+        }
+    }
+
+    #[test]
+    fn unbalanced_conditions_are_errors() {
+        for (source, line) in [("#ifdef A\nx", 1), ("x\n#endif", 2), ("#else", 1)] {
+            let error = run(&[("main.wgsl", source)], &[]).err().expect(source);
+            assert_eq!(error.line, line, "{source}");
+        }
+        let error = run(&[("main.wgsl", "#ifdef A\n#else\n#else\n#endif")], &[])
+            .err()
+            .expect("second #else");
+        assert_eq!(error.line, 3);
+    }
+
+    #[test]
+    fn origin_maps_back_to_the_source_line() {
+        let out = run(
+            &[
+                (
+                    "main.wgsl",
+                    "first\n#include \"inc.wgsl\"\n#ifdef X\nskipped\n#endif\nlast",
+                ),
+                ("inc.wgsl", "// comment\n\ninc"),
+            ],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(out.origin(1), Some(("main.wgsl", 1)));
+        assert_eq!(out.origin(2), Some(("inc.wgsl", 1)));
+        assert_eq!(out.origin(4), Some(("inc.wgsl", 3)));
+        assert_eq!(out.origin(5), Some(("main.wgsl", 6)));
+        assert_eq!(out.origin(6), None);
+        assert_eq!(out.origin(0), None);
+    }
+
+    #[test]
+    fn defines_replace_whole_identifiers_only() {
+        let out = run(
+            &[(
+                "main.wgsl",
+                "#define e5 2.0\n#define N 4\nlet x = 1e5 + e5 + N + N_2 + 0x1e5 + 1.5e5;",
+            )],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            lines(&out.source),
+            ["let x = 1e5 + 2.0 + 4 + N_2 + 0x1e5 + 1.5e5;"]
+        );
+    }
+
+    #[test]
+    fn defines_leave_comments_alone() {
+        let source = "\
+N // N
+/* N /* nested N */ N
+N */ N";
+        let out = run(&[("main.wgsl", source)], &[("N", "4")]).unwrap();
+        assert_eq!(
+            lines(&out.source),
+            ["4 // N", "/* N /* nested N */ N", "N */ 4"]
+        );
+    }
+}
@@ -7,6 +7,8 @@ use egui_winit::winit::event::WindowEvent;
 use egui_winit::winit::window::Window;
 use wgpu::{CommandEncoder, Device, Queue, TextureFormat, TextureView};
 
+use crate::renderer::graph::{PassBuilder, PassContext, RenderNode, SURFACE};
+
 pub struct EguiRenderer {
     pub context: Context,
     state: State,
@@ -115,3 +117,38 @@ impl EguiRenderer {
         }
     }
 }
+
+/// Debug UI as a render graph pass, drawn on top of whatever is on the surface
+pub struct EguiNode<'a, F: FnMut(&Context)> {
+    pub egui: &'a mut EguiRenderer,
+    pub window: &'a Window,
+    pub size_in_pixels: [u32; 2],
+    pub pixels_per_point: f32,
+    pub run_ui: F,
+}
+
+impl<F: FnMut(&Context)> RenderNode for EguiNode<'_, F> {
+    fn name(&self) -> &str {
+        "egui"
+    }
+
+    fn setup(&self, builder: &mut PassBuilder) {
+        builder.write_texture(SURFACE);
+    }
+
+    fn execute(&mut self, ctx: &mut PassContext) {
+        let screen_descriptor = ScreenDescriptor {
+            size_in_pixels: self.size_in_pixels,
+            pixels_per_point: self.pixels_per_point,
+        };
+        self.egui.draw(
+            ctx.device,
+            ctx.queue,
+            &mut *ctx.encoder,
+            self.window,
+            ctx.texture(SURFACE),
+            screen_descriptor,
+            &mut self.run_ui,
+        );
+    }
+}
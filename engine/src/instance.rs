@@ -1,10 +1,25 @@
+use cgmath::{InnerSpace, Matrix4, Point3, Transform};
+
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
     pub model: [[f32; 4]; 4],
+    /// First matrix of this instance in the joint storage buffer
+    pub joint_offset: u32,
+    /// First weight of this instance in the morph weight storage buffer
+    pub morph_offset: u32,
 }
 
 impl InstanceRaw {
+    pub const NOT_SKINNED: u32 = u32::MAX;
+    pub const NOT_MORPHED: u32 = u32::MAX;
+
+    /// Squared distance from `eye` to `point`, given in the instance's model space
+    pub fn distance2(&self, eye: Point3<f32>, point: [f32; 3]) -> f32 {
+        let world = Matrix4::from(self.model).transform_point(Point3::from(point));
+        (world - eye).magnitude2()
+    }
+
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
         wgpu::VertexBufferLayout {
@@ -38,6 +53,17 @@ impl InstanceRaw {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                wgpu::VertexAttribute {
+                    offset: (mem::size_of::<[f32; 16]>() + mem::size_of::<u32>())
+                        as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Uint32,
+                },
             ],
         }
     }
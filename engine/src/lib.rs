@@ -1,3 +1,4 @@
+pub mod animation;
 pub mod asset_manager;
 pub mod camera;
 pub mod camera_controller;
@@ -189,6 +190,17 @@ impl ApplicationHandler<UserEvent> for App {
             } => {
                 state.draw_lines = !state.draw_lines;
             }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(KeyCode::F2),
+                        ..
+                    },
+                ..
+            } => {
+                state.draw_grid = !state.draw_grid;
+            }
             WindowEvent::Resized(physical_size) => {
                 state.resize(physical_size);
                 // tracing::info!("physical_size: {physical_size:?}");
@@ -1,6 +1,13 @@
 use std::ops::Range;
 
-use crate::texture::Texture;
+use crate::{
+    asset_manager::handle::Handle,
+    gpu::{
+        pipeline_cache::BlendMode,
+        shader::{SCENE_SHADER, ShaderKey},
+    },
+    texture::Texture,
+};
 pub trait DrawModel<'a> {
     fn draw_mesh(
         &mut self,
@@ -45,6 +52,12 @@ where
         camera_bind_group: &'b wgpu::BindGroup,
     ) {
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        if let Some(skin_buffer) = &mesh.skin_buffer {
+            self.set_vertex_buffer(2, skin_buffer.slice(..));
+        }
+        if let Some(morph) = &mesh.morph_targets {
+            self.set_bind_group(3, &morph.bind_group, &[]);
+        }
         self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
         self.set_bind_group(0, &material.bind_group, &[]);
         self.set_bind_group(1, camera_bind_group, &[]);
@@ -73,10 +86,284 @@ pub struct Model {
     pub materials: Vec<Material>,
 }
 
+impl Model {
+    /// Skinned models are drawn with the `SKINNED` shader permutation
+    pub fn is_skinned(&self) -> bool {
+        self.meshes.iter().any(|mesh| mesh.skin_buffer.is_some())
+    }
+
+    /// Morphed models are drawn with the `MORPH` shader permutation
+    pub fn has_morph_targets(&self) -> bool {
+        self.meshes.iter().any(|mesh| mesh.morph_targets.is_some())
+    }
+
+    pub fn morph_target_count(&self) -> usize {
+        self.meshes
+            .iter()
+            .filter_map(|mesh| mesh.morph_targets.as_ref())
+            .map(|morph| morph.count as usize)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Clone)]
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: Texture,
+    /// Base color, bound at group 0 together with its sampler
+    pub diffuse_texture: Handle<Texture>,
+    pub normal_texture: Option<Handle<Texture>>,
+    pub metallic_roughness_texture: Option<Handle<Texture>>,
+    pub occlusion_texture: Option<Handle<Texture>>,
+    pub emissive_texture: Option<Handle<Texture>>,
+    pub ambient_texture: Option<Handle<Texture>>,
+    pub specular_texture: Option<Handle<Texture>>,
+    pub shininess_texture: Option<Handle<Texture>>,
+    pub dissolve_texture: Option<Handle<Texture>>,
+    pub displacement_texture: Option<Handle<Texture>>,
+    pub reflection_texture: Option<Handle<Texture>>,
+    pub params: MaterialParams,
+    /// [`MaterialUniform`] at group 0 binding 2
+    pub params_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
+    pub render_state: RenderState,
+}
+
+/// How meshes with a material are drawn. Importers derive it from the material's alpha mode
+/// and sidedness with [`RenderState::from_params`], material files may pick other shaders and
+/// modes.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RenderState {
+    /// Has to follow the scene shader's interface (vertex inputs, bind groups and the
+    /// `SKINNED`/`MORPH`/`WIREFRAME` defines). The renderer adds its defines to the ones here.
+    pub shader: ShaderKey,
+    /// Blended meshes are drawn after the opaque ones, without depth writes
+    pub blend: BlendMode,
+    pub cull_mode: Option<wgpu::Face>,
+}
+
+impl RenderState {
+    /// The scene shader, alpha blended for [`AlphaMode::Blend`] and without culling for
+    /// double-sided materials
+    pub fn from_params(params: &MaterialParams) -> Self {
+        Self {
+            blend: match params.alpha_mode {
+                AlphaMode::Blend => BlendMode::Alpha,
+                AlphaMode::Opaque | AlphaMode::Mask(_) => BlendMode::Opaque,
+            },
+            cull_mode: (!params.double_sided).then_some(wgpu::Face::Back),
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for RenderState {
+    fn default() -> Self {
+        Self {
+            shader: ShaderKey::new(SCENE_SHADER),
+            blend: BlendMode::Opaque,
+            cull_mode: Some(wgpu::Face::Back),
+        }
+    }
+}
+
+impl Material {
+    /// Binding of the normal map, the first of the optional maps
+    pub const FIRST_MAP_BINDING: u32 = 3;
+
+    pub fn textures(&self) -> impl Iterator<Item = Handle<Texture>> + '_ {
+        std::iter::once(self.diffuse_texture).chain(self.optional_textures().into_iter().flatten())
+    }
+
+    /// In the order of the `MaterialUniform::MAP_*` bits
+    fn optional_textures(&self) -> [Option<Handle<Texture>>; OPTIONAL_MAPS] {
+        [
+            self.normal_texture,
+            self.metallic_roughness_texture,
+            self.occlusion_texture,
+            self.emissive_texture,
+            self.ambient_texture,
+            self.specular_texture,
+            self.shininess_texture,
+            self.dissolve_texture,
+            self.displacement_texture,
+            self.reflection_texture,
+        ]
+    }
+
+    /// Uploads [`Self::params`] after they were changed
+    pub fn write_params(&self, queue: &wgpu::Queue) {
+        let uniform = MaterialUniform::new(&self.params, map_flags(&self.optional_textures()));
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// Texture and sampler of the base color at bindings 0 and 1, [`MaterialUniform`] at 2,
+    /// then the optional maps from [`Self::FIRST_MAP_BINDING`] in the order of the `MAP_*`
+    /// bits. The maps share the base color's sampler.
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let entries = [
+            texture(0),
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(
+                        std::mem::size_of::<MaterialUniform>() as u64
+                    ),
+                },
+                count: None,
+            },
+        ]
+        .into_iter()
+        .chain((0..OPTIONAL_MAPS as u32).map(|i| texture(Self::FIRST_MAP_BINDING + i)))
+        .collect::<Vec<_>>();
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("material_bind_group_layout"),
+        })
+    }
+}
+
+/// Texture maps a material may have besides the base color, one `MAP_*` bit each
+pub const OPTIONAL_MAPS: usize = 10;
+
+/// [`MaterialUniform::maps`] of the optional textures, in the order of the `MAP_*` bits
+pub(crate) fn map_flags(textures: &[Option<Handle<Texture>>; OPTIONAL_MAPS]) -> u32 {
+    textures
+        .iter()
+        .enumerate()
+        .filter(|(_, texture)| texture.is_some())
+        .fold(0, |flags, (bit, _)| flags | 1 << bit)
+}
+
+/// `Material` struct of the scene shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    /// Ka, w is unused
+    pub ambient: [f32; 4],
+    /// Base color (Kd) with the dissolve (d) as alpha
+    pub diffuse: [f32; 4],
+    /// Ks, w is the shininess (Ns)
+    pub specular: [f32; 4],
+    /// Ke, w is the index of refraction (Ni)
+    pub emissive: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    /// Fragments with less alpha are discarded, 0 unless the alpha mode is `Mask`
+    pub alpha_cutoff: f32,
+    pub illum: u32,
+    /// Which optional texture maps the material has, `MAP_*` bits
+    pub maps: u32,
+    pub _padding: u32,
+}
+
+impl MaterialUniform {
+    pub const MAP_NORMAL: u32 = 1 << 0;
+    pub const MAP_METALLIC_ROUGHNESS: u32 = 1 << 1;
+    pub const MAP_OCCLUSION: u32 = 1 << 2;
+    pub const MAP_EMISSIVE: u32 = 1 << 3;
+    pub const MAP_AMBIENT: u32 = 1 << 4;
+    pub const MAP_SPECULAR: u32 = 1 << 5;
+    pub const MAP_SHININESS: u32 = 1 << 6;
+    pub const MAP_DISSOLVE: u32 = 1 << 7;
+    pub const MAP_DISPLACEMENT: u32 = 1 << 8;
+    pub const MAP_REFLECTION: u32 = 1 << 9;
+
+    pub fn new(params: &MaterialParams, maps: u32) -> Self {
+        let [r, g, b] = params.ambient;
+        let [sr, sg, sb] = params.specular;
+        let [er, eg, eb] = params.emissive;
+        Self {
+            ambient: [r, g, b, 0.0],
+            diffuse: params.base_color,
+            specular: [sr, sg, sb, params.shininess],
+            emissive: [er, eg, eb, params.ior],
+            metallic: params.metallic,
+            roughness: params.roughness,
+            normal_scale: params.normal_scale,
+            occlusion_strength: params.occlusion_strength,
+            alpha_cutoff: match params.alpha_mode {
+                AlphaMode::Mask(cutoff) => cutoff,
+                AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+            },
+            illum: params.illum,
+            maps,
+            _padding: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    /// Alpha test against the cutoff
+    Mask(f32),
+    Blend,
+}
+
+/// Metallic-roughness PBR factors, multiplied with the matching textures, and the
+/// Phong-style parameters of MTL files. Shaders read them as [`MaterialUniform`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MaterialParams {
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool,
+    /// Ka
+    pub ambient: [f32; 3],
+    /// Ks
+    pub specular: [f32; 3],
+    /// Ns, the specular exponent
+    pub shininess: f32,
+    /// Ni
+    pub ior: f32,
+    /// MTL illumination model, 2 (diffuse and specular) unless the file says otherwise
+    pub illum: u32,
+}
+
+impl Default for MaterialParams {
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 4],
+            metallic: 0.0,
+            roughness: 1.0,
+            emissive: [0.0; 3],
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            double_sided: false,
+            ambient: [0.0; 3],
+            specular: [0.0; 3],
+            shininess: 0.0,
+            ior: 1.5,
+            illum: 2,
+        }
+    }
 }
 
 pub struct Mesh {
@@ -85,6 +372,95 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    pub bounds: Bounds,
+    /// [`SkinVertex`] per vertex, bound to slot 2
+    pub skin_buffer: Option<wgpu::Buffer>,
+    pub morph_targets: Option<MorphTargets>,
+}
+
+/// Axis-aligned box around the rest pose of a mesh, in model space
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Bounds {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Bounds {
+    /// Empty meshes get a zero sized box at the origin
+    pub fn from_vertices(vertices: &[ModelVertex]) -> Self {
+        let Some(first) = vertices.first() else {
+            return Self::default();
+        };
+        vertices.iter().fold(
+            Self {
+                min: first.position,
+                max: first.position,
+            },
+            |bounds, vertex| Self {
+                min: [0, 1, 2].map(|i| bounds.min[i].min(vertex.position[i])),
+                max: [0, 1, 2].map(|i| bounds.max[i].max(vertex.position[i])),
+            },
+        )
+    }
+
+    pub fn center(&self) -> [f32; 3] {
+        [0, 1, 2].map(|i| (self.min[i] + self.max[i]) * 0.5)
+    }
+}
+
+/// Blend shape deltas of a mesh, bound at group 3
+pub struct MorphTargets {
+    pub count: u32,
+    /// [`MorphDelta`]s, target after target, one per vertex
+    pub deltas: wgpu::Buffer,
+    pub info: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl MorphTargets {
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("morph_bind_group_layout"),
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MorphDelta {
+    // xyz, w is padding
+    pub position: [f32; 4],
+    pub normal: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MorphInfo {
+    pub vertex_count: u32,
+    pub target_count: u32,
+    pub _padding: [u32; 2],
 }
 
 pub trait Vertex {
@@ -97,10 +473,19 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    /// Linear RGBA, multiplied with the material's base color
+    pub color: [f32; 4],
 }
 
-const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
-    wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2, 2 => Float32x3];
+impl ModelVertex {
+    /// Color of vertices the file gives none, leaves the material color as it is
+    pub const NO_COLOR: [f32; 4] = [1.0; 4];
+}
+
+// The color comes after the instance attributes (5..=10) to keep the older locations
+const ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+    0 => Float32x3, 1 => Float32x2, 2 => Float32x3, 11 => Float32x4
+];
 
 impl Vertex for ModelVertex {
     fn desc() -> wgpu::VertexBufferLayout<'static> {
@@ -112,14 +497,36 @@ impl Vertex for ModelVertex {
         }
     }
 }
+/// Joint influences of a vertex, kept out of [`ModelVertex`] so rigid meshes don't pay for them
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinVertex {
+    pub joints: [u16; 4],
+    pub weights: [f32; 4],
+}
+
+const SKIN_ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+    wgpu::vertex_attr_array![3 => Uint16x4, 4 => Float32x4];
+
+impl Vertex for SkinVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<SkinVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &SKIN_ATTRIBUTES,
+        }
+    }
+}
+
 #[rustfmt::skip]
 pub const VERTICES: &[ModelVertex] = &[
     // Changed
-   ModelVertex { position: [-0.0868241,   0.49240386, 0.0], normal: [0.0,    0.0,0.0],   tex_coords: [0.4131759,    0.00759614] },    // A
-   ModelVertex { position: [-0.49513406,  0.06958647, 0.0], normal: [0.0,    0.0,0.0],   tex_coords: [0.0048659444, 0.43041354] },    // B
-   ModelVertex { position: [-0.21918549, -0.44939706, 0.0], normal: [0.0,    0.0,0.0],   tex_coords: [0.28081453,   0.949397]   },    // C
-   ModelVertex { position: [ 0.35966998, -0.3473291,  0.0], normal: [0.0,    0.0,0.0],   tex_coords: [0.85967,      0.84732914] },    // D
-   ModelVertex { position: [ 0.44147372,  0.2347359,  0.0], normal: [0.0,    0.0,0.0],   tex_coords: [0.9414737,    0.2652641]   },   // E
+   ModelVertex { position: [-0.0868241,   0.49240386, 0.0], normal: [0.0,    0.0,0.0],   tex_coords: [0.4131759,    0.00759614], color: ModelVertex::NO_COLOR },    // A
+   ModelVertex { position: [-0.49513406,  0.06958647, 0.0], normal: [0.0,    0.0,0.0],   tex_coords: [0.0048659444, 0.43041354], color: ModelVertex::NO_COLOR },    // B
+   ModelVertex { position: [-0.21918549, -0.44939706, 0.0], normal: [0.0,    0.0,0.0],   tex_coords: [0.28081453,   0.949397],   color: ModelVertex::NO_COLOR },    // C
+   ModelVertex { position: [ 0.35966998, -0.3473291,  0.0], normal: [0.0,    0.0,0.0],   tex_coords: [0.85967,      0.84732914], color: ModelVertex::NO_COLOR },    // D
+   ModelVertex { position: [ 0.44147372,  0.2347359,  0.0], normal: [0.0,    0.0,0.0],   tex_coords: [0.9414737,    0.2652641],  color: ModelVertex::NO_COLOR },    // E
 ];
 
 pub const INDICES: &[u16] = &[0, 1, 4, 1, 2, 4, 2, 3, 4];
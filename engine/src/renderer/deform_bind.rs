@@ -0,0 +1,138 @@
+use cgmath::{Matrix4, SquareMatrix as _};
+use wgpu::util::DeviceExt as _;
+
+use crate::animation::JointMatrix;
+
+// Storage buffers with the joint matrices (binding 0) and morph target weights (binding 1)
+// of every deformed instance drawn this frame. Both share group 2.
+pub struct DeformBinding {
+    joints: wgpu::Buffer,
+    morph_weights: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl DeformBinding {
+    pub fn new(device: &wgpu::Device) -> Self {
+        // A storage binding can't be empty, start with a single identity matrix and weight
+        let identity: JointMatrix = Matrix4::<f32>::identity().into();
+        let joints =
+            Self::storage_buffer(device, "joints_buffer", bytemuck::cast_slice(&[identity]));
+        let morph_weights = Self::storage_buffer(
+            device,
+            "morph_weights_buffer",
+            bytemuck::cast_slice(&[0.0f32]),
+        );
+
+        let entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[entry(0), entry(1)],
+            label: Some("deform_layout"),
+        });
+        let bind_group = Self::create_bind_group(device, &layout, &joints, &morph_weights);
+
+        Self {
+            joints,
+            morph_weights,
+            layout,
+            bind_group,
+        }
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    // called once per frame with the palette and weights collected by the scene
+    pub fn sync(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        joints: &[JointMatrix],
+        morph_weights: &[f32],
+    ) {
+        let grown = Self::write(
+            device,
+            queue,
+            &mut self.joints,
+            "joints_buffer",
+            bytemuck::cast_slice(joints),
+        ) | Self::write(
+            device,
+            queue,
+            &mut self.morph_weights,
+            "morph_weights_buffer",
+            bytemuck::cast_slice(morph_weights),
+        );
+        if grown {
+            self.bind_group =
+                Self::create_bind_group(device, &self.layout, &self.joints, &self.morph_weights);
+        }
+    }
+
+    // true when the buffer had to be recreated
+    fn write(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        buffer: &mut wgpu::Buffer,
+        label: &str,
+        contents: &[u8],
+    ) -> bool {
+        if contents.is_empty() {
+            return false;
+        }
+        let grown = contents.len() as u64 > buffer.size();
+        if grown {
+            *buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: (contents.len() as u64).next_power_of_two(),
+                usage: buffer.usage(),
+                mapped_at_creation: false,
+            });
+        }
+        queue.write_buffer(buffer, 0, contents);
+        grown
+    }
+
+    fn storage_buffer(device: &wgpu::Device, label: &str, contents: &[u8]) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        joints: &wgpu::Buffer,
+        morph_weights: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: joints.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: morph_weights.as_entire_binding(),
+                },
+            ],
+            label: Some("deform_bind_group"),
+        })
+    }
+}
@@ -0,0 +1,517 @@
+// Render graph: passes declare the resources they read and write, the graph
+// figures out execution order, culls passes nobody needs, owns transient targets
+// and rebuilds them on resize.
+pub mod pass;
+mod resources;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use anyhow::bail;
+
+pub use pass::{PassBuilder, PassContext, RenderNode};
+
+use crate::renderer::{
+    frame::Frame,
+    graph::{pass::ResourceKind, resources::TransientPool},
+};
+
+/// The swapchain image of the current frame, imported by the graph every frame
+pub const SURFACE: &str = "surface";
+/// Scene depth buffer, created by the scene pass
+pub const DEPTH: &str = "depth";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextureSize {
+    /// Follows the surface, recreated on resize
+    Surface,
+    Fixed {
+        width: u32,
+        height: u32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureDesc {
+    pub size: TextureSize,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+    pub sample_count: u32,
+}
+
+impl TextureDesc {
+    pub fn surface_sized(format: wgpu::TextureFormat, usage: wgpu::TextureUsages) -> Self {
+        Self {
+            size: TextureSize::Surface,
+            format,
+            usage,
+            sample_count: 1,
+        }
+    }
+
+    fn create(
+        &self,
+        device: &wgpu::Device,
+        label: &str,
+        surface_size: (u32, u32),
+    ) -> wgpu::Texture {
+        let (width, height) = match self.size {
+            TextureSize::Surface => surface_size,
+            TextureSize::Fixed { width, height } => (width, height),
+        };
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: self.sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.format,
+            usage: self.usage,
+            view_formats: &[],
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BufferDesc {
+    pub size: u64,
+    pub usage: wgpu::BufferUsages,
+}
+
+#[derive(Debug)]
+enum Declared {
+    Imported,
+    Texture(TextureDesc),
+    Buffer(BufferDesc),
+}
+
+pub struct RenderGraph {
+    surface_size: (u32, u32),
+    pool: TransientPool,
+}
+
+impl RenderGraph {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            surface_size: (width, height),
+            pool: TransientPool::default(),
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.surface_size = (width, height);
+        self.pool.evict_surface_sized();
+    }
+
+    /// Number of physical textures currently held by the graph, useful to see aliasing at work
+    pub fn transient_texture_count(&self) -> usize {
+        self.pool.texture_count()
+    }
+
+    /// Records every pass that is not culled. Declarations that don't add up (unknown or
+    /// duplicate names, a buffer used as a texture, a dependency cycle) fail before anything
+    /// is recorded.
+    #[profiling::function]
+    pub fn execute(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frame: &mut Frame,
+        nodes: &mut [&mut dyn RenderNode],
+    ) -> anyhow::Result<()> {
+        let declarations: Vec<PassBuilder> = nodes
+            .iter()
+            .map(|node| {
+                let mut builder = PassBuilder::default();
+                node.setup(&mut builder);
+                builder
+            })
+            .collect();
+
+        let names: Vec<&str> = nodes.iter().map(|node| node.name()).collect();
+        let CompiledGraph {
+            declared,
+            order,
+            lifetimes,
+            first_writer,
+        } = Self::compile(&names, &declarations)?;
+
+        // Allocate in execution order so freed allocations can be aliased by later resources
+        self.pool.begin_frame();
+        let mut texture_slots = HashMap::new();
+        let mut buffer_slots = HashMap::new();
+        for (name, (first, last)) in lifetimes {
+            match declared[name] {
+                Declared::Imported => {}
+                Declared::Texture(desc) => {
+                    let slot = self.pool.acquire_texture(
+                        device,
+                        name,
+                        desc,
+                        self.surface_size,
+                        first,
+                        last,
+                    );
+                    texture_slots.insert(name, slot);
+                }
+                Declared::Buffer(desc) => {
+                    let slot = self.pool.acquire_buffer(device, name, desc, first, last);
+                    buffer_slots.insert(name, slot);
+                }
+            }
+        }
+
+        let Frame { view, encoder, .. } = frame;
+        let mut all_textures: HashMap<&'static str, &wgpu::TextureView> = texture_slots
+            .iter()
+            .map(|(name, slot)| (*name, &self.pool.texture(*slot).view))
+            .collect();
+        all_textures.insert(SURFACE, view);
+        let all_buffers: HashMap<&'static str, &wgpu::Buffer> = buffer_slots
+            .iter()
+            .map(|(name, slot)| (*name, self.pool.buffer(*slot)))
+            .collect();
+
+        for &pass in &order {
+            // `compile` checked every declared name, a pass only sees what it declared
+            let declaration = &declarations[pass];
+            let textures = declaration
+                .resources()
+                .filter(|(_, kind)| *kind == ResourceKind::Texture)
+                .map(|(name, _)| (name, all_textures[name]))
+                .collect();
+            let buffers = declaration
+                .resources()
+                .filter(|(_, kind)| *kind == ResourceKind::Buffer)
+                .map(|(name, _)| (name, all_buffers[name]))
+                .collect();
+            let first_writes = first_writer
+                .iter()
+                .filter(|(_, writer)| **writer == pass)
+                .map(|(name, _)| *name)
+                .collect();
+            let mut ctx = PassContext {
+                device,
+                queue,
+                encoder: &mut *encoder,
+                textures,
+                buffers,
+                first_writes,
+                surface_size: self.surface_size,
+            };
+            nodes[pass].execute(&mut ctx);
+        }
+
+        self.pool.end_frame();
+        Ok(())
+    }
+
+    /// Everything about a frame that does not need the GPU: validates the declarations,
+    /// orders and culls the passes and computes resource lifetimes. `names` are the pass
+    /// names for error messages.
+    fn compile(names: &[&str], declarations: &[PassBuilder]) -> anyhow::Result<CompiledGraph> {
+        let declared = Self::collect_resources(names, declarations)?;
+        let order = Self::schedule(names, declarations)?;
+        let order = Self::cull(&declared, declarations, order);
+
+        // first and last position in `order` where each resource is touched
+        let mut spans: HashMap<&'static str, (usize, usize)> = HashMap::new();
+        let mut first_writer: HashMap<&'static str, usize> = HashMap::new();
+        for (position, &pass) in order.iter().enumerate() {
+            for (name, _) in declarations[pass].resources() {
+                spans
+                    .entry(name)
+                    .and_modify(|(_, last)| *last = position)
+                    .or_insert((position, position));
+            }
+            for &(name, _) in &declarations[pass].writes {
+                first_writer.entry(name).or_insert(pass);
+            }
+        }
+        // Allocation goes in execution order so freed allocations can be aliased by later
+        // resources
+        let mut lifetimes: Vec<(&'static str, (usize, usize))> = spans.into_iter().collect();
+        lifetimes.sort_by_key(|(name, (first, _))| (*first, *name));
+
+        Ok(CompiledGraph {
+            declared,
+            order,
+            lifetimes,
+            first_writer,
+        })
+    }
+
+    fn collect_resources(
+        names: &[&str],
+        declarations: &[PassBuilder],
+    ) -> anyhow::Result<HashMap<&'static str, Declared>> {
+        let mut declared = HashMap::from([(SURFACE, Declared::Imported)]);
+        for (pass, decl) in names.iter().zip(declarations) {
+            let created = decl
+                .textures
+                .iter()
+                .map(|(name, desc)| (*name, Declared::Texture(*desc)))
+                .chain(
+                    decl.buffers
+                        .iter()
+                        .map(|(name, desc)| (*name, Declared::Buffer(*desc))),
+                );
+            for (name, resource) in created {
+                if declared.insert(name, resource).is_some() {
+                    bail!("pass {pass} creates {name}, which already exists");
+                }
+            }
+        }
+
+        for (pass, decl) in names.iter().zip(declarations) {
+            for (name, kind) in decl.resources() {
+                let matches = match declared.get(name) {
+                    None => bail!("pass {pass} uses undeclared resource {name}"),
+                    Some(Declared::Imported) | Some(Declared::Texture(_)) => {
+                        kind == ResourceKind::Texture
+                    }
+                    Some(Declared::Buffer(_)) => kind == ResourceKind::Buffer,
+                };
+                if !matches {
+                    bail!("pass {pass} uses {name} as a {kind:?}, but it is not one");
+                }
+            }
+        }
+        Ok(declared)
+    }
+
+    /// Orders passes so that every reader runs after the producer of what it reads.
+    /// Passes that touch the same resource otherwise keep the order they were added in.
+    fn schedule(names: &[&str], declarations: &[PassBuilder]) -> anyhow::Result<Vec<usize>> {
+        let count = declarations.len();
+        let mut dependencies: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); count];
+
+        for later in 0..count {
+            for earlier in 0..later {
+                let (a, b) = (&declarations[earlier], &declarations[later]);
+                for (name, _) in a.resources().chain(b.resources()) {
+                    let read_after_write = a.writes(name) && b.reads(name);
+                    let write_after_write = a.writes(name) && b.writes(name);
+                    let write_after_read = a.reads(name) && b.writes(name);
+                    if read_after_write || write_after_write {
+                        dependencies[later].insert(earlier);
+                    } else if write_after_read {
+                        // `earlier` reads something only produced later: the producer goes first,
+                        // unless somebody before `earlier` already wrote it
+                        let produced_before =
+                            declarations[..earlier].iter().any(|d| d.writes(name));
+                        if produced_before {
+                            dependencies[later].insert(earlier);
+                        } else {
+                            dependencies[earlier].insert(later);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Kahn's algorithm, always picking the lowest index to keep insertion order stable
+        let mut order = Vec::with_capacity(count);
+        let mut done = vec![false; count];
+        while order.len() < count {
+            let Some(next) =
+                (0..count).find(|&i| !done[i] && dependencies[i].iter().all(|d| done[*d]))
+            else {
+                let stuck: Vec<&str> = (0..count).filter(|i| !done[*i]).map(|i| names[i]).collect();
+                bail!("render graph has a dependency cycle between {stuck:?}");
+            };
+            done[next] = true;
+            order.push(next);
+        }
+        Ok(order)
+    }
+
+    /// Drops passes whose results nobody uses. A pass runs when it writes an imported
+    /// resource (the surface), writes something a later pass that runs touches, or declares
+    /// no writes at all, since the graph cannot see what such a pass does.
+    fn cull(
+        declared: &HashMap<&'static str, Declared>,
+        declarations: &[PassBuilder],
+        order: Vec<usize>,
+    ) -> Vec<usize> {
+        let mut needed: HashSet<&str> = HashSet::new();
+        let mut kept: Vec<usize> = order
+            .into_iter()
+            .rev()
+            .filter(|&pass| {
+                let decl = &declarations[pass];
+                let keep = decl.writes.is_empty()
+                    || decl.writes.iter().any(|(name, _)| {
+                        matches!(declared[name], Declared::Imported) || needed.contains(name)
+                    });
+                if keep {
+                    needed.extend(decl.resources().map(|(name, _)| name));
+                }
+                keep
+            })
+            .collect();
+        kept.reverse();
+        kept
+    }
+}
+
+struct CompiledGraph {
+    declared: HashMap<&'static str, Declared>,
+    /// Passes that run, in execution order
+    order: Vec<usize>,
+    /// First and last position in `order` where each resource is used, by first use
+    lifetimes: Vec<(&'static str, (usize, usize))>,
+    /// Resource -> pass that writes it first, and clears it
+    first_writer: HashMap<&'static str, usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texture() -> TextureDesc {
+        TextureDesc::surface_sized(
+            wgpu::TextureFormat::Rgba8Unorm,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        )
+    }
+
+    fn pass(setup: impl FnOnce(&mut PassBuilder)) -> PassBuilder {
+        let mut builder = PassBuilder::default();
+        setup(&mut builder);
+        builder
+    }
+
+    fn compile(passes: &[PassBuilder]) -> anyhow::Result<CompiledGraph> {
+        let names: Vec<String> = (0..passes.len()).map(|i| format!("p{i}")).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        RenderGraph::compile(&names, passes)
+    }
+
+    #[test]
+    fn readers_run_after_the_producer() {
+        let passes = [
+            pass(|b| {
+                b.read_texture("gbuffer").write_texture(SURFACE);
+            }),
+            pass(|b| {
+                b.create_texture("gbuffer", texture());
+            }),
+        ];
+        assert_eq!(compile(&passes).unwrap().order, [1, 0]);
+    }
+
+    #[test]
+    fn independent_passes_keep_their_order() {
+        let passes = [
+            pass(|b| {
+                b.write_texture(SURFACE);
+            }),
+            pass(|b| {
+                b.write_texture(SURFACE);
+            }),
+            pass(|b| {
+                b.write_texture(SURFACE);
+            }),
+        ];
+        assert_eq!(compile(&passes).unwrap().order, [0, 1, 2]);
+    }
+
+    #[test]
+    fn cycles_are_errors() {
+        let passes = [
+            pass(|b| {
+                b.create_texture("a", texture()).read_texture("b");
+            }),
+            pass(|b| {
+                b.create_texture("b", texture())
+                    .read_texture("a")
+                    .write_texture(SURFACE);
+            }),
+        ];
+        let error = compile(&passes).err().expect("cycle").to_string();
+        assert!(error.contains("cycle"), "{error}");
+    }
+
+    #[test]
+    fn passes_nobody_reads_are_culled() {
+        let passes = [
+            pass(|b| {
+                b.create_texture("unused", texture());
+            }),
+            pass(|b| {
+                b.create_texture("shadow", texture());
+            }),
+            pass(|b| {
+                b.read_texture("shadow").write_texture(SURFACE);
+            }),
+            // Side effects the graph can't see
+            pass(|_| {}),
+        ];
+        let compiled = compile(&passes).unwrap();
+        assert_eq!(compiled.order, [1, 2, 3]);
+        assert!(compiled.lifetimes.iter().all(|(name, _)| *name != "unused"));
+    }
+
+    #[test]
+    fn lifetimes_are_positions_in_the_culled_order() {
+        let passes = [
+            pass(|b| {
+                b.create_texture("dead", texture());
+            }),
+            pass(|b| {
+                b.create_texture("a", texture());
+            }),
+            pass(|b| {
+                b.read_texture("a").create_texture("b", texture());
+            }),
+            pass(|b| {
+                b.read_texture("b").write_texture(SURFACE);
+            }),
+        ];
+        let compiled = compile(&passes).unwrap();
+        let lifetimes: HashMap<_, _> = compiled.lifetimes.iter().copied().collect();
+        assert_eq!(lifetimes["a"], (0, 1));
+        assert_eq!(lifetimes["b"], (1, 2));
+        assert_eq!(lifetimes[SURFACE], (2, 2));
+        assert_eq!(compiled.first_writer["b"], 2);
+    }
+
+    #[test]
+    fn unknown_names_fail_at_compile_time() {
+        let passes = [pass(|b| {
+            b.read_texture("dpeth").write_texture(SURFACE);
+        })];
+        let error = compile(&passes).err().expect("unknown name").to_string();
+        assert!(error.contains("undeclared resource dpeth"), "{error}");
+    }
+
+    #[test]
+    fn kinds_have_to_match() {
+        let passes = [
+            pass(|b| {
+                b.create_texture("t", texture());
+            }),
+            pass(|b| {
+                b.read_buffer("t").write_texture(SURFACE);
+            }),
+        ];
+        assert!(compile(&passes).is_err());
+    }
+
+    #[test]
+    fn resources_are_created_once() {
+        let passes = [
+            pass(|b| {
+                b.create_texture("t", texture()).write_texture(SURFACE);
+            }),
+            pass(|b| {
+                b.create_texture("t", texture()).write_texture(SURFACE);
+            }),
+        ];
+        assert!(compile(&passes).is_err());
+    }
+}
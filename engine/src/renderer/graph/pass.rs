@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use crate::renderer::graph::{BufferDesc, TextureDesc};
+
+/// A unit of GPU work in the [`RenderGraph`](super::RenderGraph).
+///
+/// `setup` is called every frame before anything is recorded and only declares
+/// which resources the pass touches. The graph uses these declarations to order
+/// passes, allocate transient targets and decide which pass clears what.
+pub trait RenderNode {
+    fn name(&self) -> &str;
+    fn setup(&self, builder: &mut PassBuilder);
+    fn execute(&mut self, ctx: &mut PassContext);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ResourceKind {
+    Texture,
+    Buffer,
+}
+
+#[derive(Default)]
+pub struct PassBuilder {
+    pub(crate) textures: Vec<(&'static str, TextureDesc)>,
+    pub(crate) buffers: Vec<(&'static str, BufferDesc)>,
+    pub(crate) reads: Vec<(&'static str, ResourceKind)>,
+    pub(crate) writes: Vec<(&'static str, ResourceKind)>,
+}
+
+impl PassBuilder {
+    /// Declares a transient texture owned by the graph. The creating pass is its first writer.
+    pub fn create_texture(&mut self, name: &'static str, desc: TextureDesc) -> &mut Self {
+        self.textures.push((name, desc));
+        self.write_texture(name)
+    }
+
+    /// Declares a transient buffer owned by the graph. The creating pass is its first writer.
+    pub fn create_buffer(&mut self, name: &'static str, desc: BufferDesc) -> &mut Self {
+        self.buffers.push((name, desc));
+        self.write_buffer(name)
+    }
+
+    pub fn read_texture(&mut self, name: &'static str) -> &mut Self {
+        self.reads.push((name, ResourceKind::Texture));
+        self
+    }
+
+    pub fn write_texture(&mut self, name: &'static str) -> &mut Self {
+        self.writes.push((name, ResourceKind::Texture));
+        self
+    }
+
+    pub fn read_buffer(&mut self, name: &'static str) -> &mut Self {
+        self.reads.push((name, ResourceKind::Buffer));
+        self
+    }
+
+    pub fn write_buffer(&mut self, name: &'static str) -> &mut Self {
+        self.writes.push((name, ResourceKind::Buffer));
+        self
+    }
+
+    pub(crate) fn reads(&self, name: &str) -> bool {
+        self.reads.iter().any(|(n, _)| *n == name)
+    }
+
+    pub(crate) fn writes(&self, name: &str) -> bool {
+        self.writes.iter().any(|(n, _)| *n == name)
+    }
+
+    pub(crate) fn resources(&self) -> impl Iterator<Item = (&'static str, ResourceKind)> + '_ {
+        self.reads.iter().chain(self.writes.iter()).copied()
+    }
+}
+
+/// Everything a pass needs while recording. Resources are looked up by the
+/// names the pass declared in [`RenderNode::setup`].
+pub struct PassContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub encoder: &'a mut wgpu::CommandEncoder,
+    // Only the resources this pass declared
+    pub(crate) textures: HashMap<&'static str, &'a wgpu::TextureView>,
+    pub(crate) buffers: HashMap<&'static str, &'a wgpu::Buffer>,
+    pub(crate) first_writes: Vec<&'static str>,
+    pub(crate) surface_size: (u32, u32),
+}
+
+impl<'a> PassContext<'a> {
+    /// Declared names that don't exist or have the wrong kind fail the whole graph before
+    /// any pass runs, see [`RenderGraph::execute`](super::RenderGraph::execute).
+    ///
+    /// # Panics
+    ///
+    /// If this pass did not read or write `name` in [`RenderNode::setup`].
+    pub fn texture(&self, name: &str) -> &'a wgpu::TextureView {
+        // Views are returned with the graph lifetime, not tied to `&self`,
+        // so they can be used while `encoder` is borrowed mutably
+        self.textures
+            .get(name)
+            .unwrap_or_else(|| panic!("texture {name} was not declared in setup"))
+    }
+
+    /// # Panics
+    ///
+    /// If this pass did not read or write `name` in [`RenderNode::setup`].
+    pub fn buffer(&self, name: &str) -> &'a wgpu::Buffer {
+        self.buffers
+            .get(name)
+            .unwrap_or_else(|| panic!("buffer {name} was not declared in setup"))
+    }
+
+    /// Clear when this pass is the first one to write the resource this frame, load otherwise.
+    /// Transient targets may alias memory of another resource, so the first writer must clear.
+    pub fn load_op<V>(&self, name: &str, clear: V) -> wgpu::LoadOp<V> {
+        if self.first_writes.contains(&name) {
+            wgpu::LoadOp::Clear(clear)
+        } else {
+            wgpu::LoadOp::Load
+        }
+    }
+
+    pub fn surface_size(&self) -> (u32, u32) {
+        self.surface_size
+    }
+}
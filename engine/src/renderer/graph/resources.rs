@@ -0,0 +1,173 @@
+// Physical backing for transient graph resources.
+// Textures and buffers are kept between frames and handed out again when a
+// resource with the same description asks for one; inside a frame two resources
+// share one allocation when their lifetimes do not overlap.
+use crate::renderer::graph::{BufferDesc, TextureDesc, TextureSize};
+
+struct Pooled<D, R> {
+    desc: D,
+    resource: R,
+    // last pass (position in execution order) that still needs this allocation
+    busy_until: Option<usize>,
+    used_this_frame: bool,
+}
+
+pub(crate) struct PooledTexture {
+    #[allow(unused)]
+    pub(crate) texture: wgpu::Texture,
+    pub(crate) view: wgpu::TextureView,
+}
+
+#[derive(Default)]
+pub(crate) struct TransientPool {
+    textures: Vec<Pooled<TextureDesc, PooledTexture>>,
+    buffers: Vec<Pooled<BufferDesc, wgpu::Buffer>>,
+}
+
+impl TransientPool {
+    pub(crate) fn begin_frame(&mut self) {
+        for t in &mut self.textures {
+            t.busy_until = None;
+            t.used_this_frame = false;
+        }
+        for b in &mut self.buffers {
+            b.busy_until = None;
+            b.used_this_frame = false;
+        }
+    }
+
+    /// Drops allocations nobody asked for this frame, so a pass that was removed
+    /// does not keep its targets alive forever.
+    pub(crate) fn end_frame(&mut self) {
+        self.textures.retain(|t| t.used_this_frame);
+        self.buffers.retain(|b| b.used_this_frame);
+    }
+
+    pub(crate) fn evict_surface_sized(&mut self) {
+        self.textures
+            .retain(|t| t.desc.size != TextureSize::Surface);
+    }
+
+    pub(crate) fn acquire_texture(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        desc: TextureDesc,
+        surface_size: (u32, u32),
+        first_use: usize,
+        last_use: usize,
+    ) -> usize {
+        reserve(&mut self.textures, desc, first_use, last_use, || {
+            let texture = desc.create(device, label, surface_size);
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            PooledTexture { texture, view }
+        })
+    }
+
+    pub(crate) fn acquire_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        desc: BufferDesc,
+        first_use: usize,
+        last_use: usize,
+    ) -> usize {
+        reserve(&mut self.buffers, desc, first_use, last_use, || {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: desc.size,
+                usage: desc.usage,
+                mapped_at_creation: false,
+            })
+        })
+    }
+
+    pub(crate) fn texture(&self, index: usize) -> &PooledTexture {
+        &self.textures[index].resource
+    }
+
+    pub(crate) fn buffer(&self, index: usize) -> &wgpu::Buffer {
+        &self.buffers[index].resource
+    }
+
+    pub(crate) fn texture_count(&self) -> usize {
+        self.textures.len()
+    }
+}
+
+/// Index of an allocation matching `desc` that is free from `first_use` on, created when
+/// there is none. It stays busy until after `last_use`.
+fn reserve<D: PartialEq, R>(
+    pool: &mut Vec<Pooled<D, R>>,
+    desc: D,
+    first_use: usize,
+    last_use: usize,
+    create: impl FnOnce() -> R,
+) -> usize {
+    let free = pool.iter().position(|p| {
+        p.desc == desc && p.busy_until.is_none_or(|busy_until| busy_until < first_use)
+    });
+    let index = free.unwrap_or_else(|| {
+        pool.push(Pooled {
+            desc,
+            resource: create(),
+            busy_until: None,
+            used_this_frame: false,
+        });
+        pool.len() - 1
+    });
+    let slot = &mut pool[index];
+    slot.busy_until = Some(last_use);
+    slot.used_this_frame = true;
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desc() -> BufferDesc {
+        BufferDesc {
+            size: 256,
+            usage: wgpu::BufferUsages::STORAGE,
+        }
+    }
+
+    #[test]
+    fn aliases_only_after_the_last_use() {
+        let mut pool: Vec<Pooled<BufferDesc, ()>> = Vec::new();
+        let a = reserve(&mut pool, desc(), 0, 1, || ());
+        // Overlaps `a` at pass 1
+        let b = reserve(&mut pool, desc(), 1, 2, || ());
+        // `a` is done after pass 1
+        let c = reserve(&mut pool, desc(), 2, 3, || ());
+        assert_ne!(a, b);
+        assert_eq!(c, a);
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn different_descriptions_never_alias() {
+        let mut pool: Vec<Pooled<BufferDesc, ()>> = Vec::new();
+        let a = reserve(&mut pool, desc(), 0, 0, || ());
+        let bigger = BufferDesc {
+            size: 512,
+            ..desc()
+        };
+        let b = reserve(&mut pool, bigger, 1, 1, || ());
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn allocations_are_reused_across_frames() {
+        let mut pool: Vec<Pooled<BufferDesc, ()>> = Vec::new();
+        reserve(&mut pool, desc(), 0, 5, || ());
+        // next frame
+        for slot in &mut pool {
+            slot.busy_until = None;
+        }
+        let mut created = false;
+        reserve(&mut pool, desc(), 0, 5, || created = true);
+        assert!(!created);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::{
+    gpu::{
+        pipeline_cache::{Bindings, BlendMode, DepthState, PipelineKey, VertexLayout},
+        shader::{GRID_SHADER, ShaderKey},
+    },
+    renderer::graph::{DEPTH, PassBuilder, PassContext, RenderNode, SURFACE},
+    texture::Texture,
+};
+
+// Editor ground grid. Runs after the scene pass and depth tests against
+// the geometry that was just rendered without writing depth itself.
+pub(crate) struct GridNode<'a> {
+    pub(crate) pipeline: &'a wgpu::RenderPipeline,
+    pub(crate) camera_bind_group: &'a wgpu::BindGroup,
+}
+
+impl GridNode<'_> {
+    pub(crate) fn pipeline_key(format: wgpu::TextureFormat) -> PipelineKey {
+        PipelineKey {
+            shader: ShaderKey::new(GRID_SHADER),
+            bindings: Bindings::Camera,
+            vertex_layout: VertexLayout::None,
+            blend: BlendMode::Alpha,
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            depth: Some(DepthState {
+                format: Texture::DEPTH_FORMAT,
+                write: false,
+                compare: wgpu::CompareFunction::Less,
+            }),
+            sample_count: 1,
+            format,
+        }
+    }
+}
+
+impl RenderNode for GridNode<'_> {
+    fn name(&self) -> &str {
+        "Grid Pass"
+    }
+
+    fn setup(&self, builder: &mut PassBuilder) {
+        builder.read_texture(DEPTH).write_texture(SURFACE);
+    }
+
+    fn execute(&mut self, ctx: &mut PassContext) {
+        let mut pass = ctx.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Grid Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: ctx.texture(SURFACE),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: ctx.texture(DEPTH),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            multiview_mask: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(self.pipeline);
+        pass.set_bind_group(0, self.camera_bind_group, &[]);
+        // fullscreen triangle, vertices are generated in the shader
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -1,11 +1,27 @@
-// Renderer owns a small growable buffer pool, keyed by mesh identity
+// Renderer owns a small growable buffer pool, keyed by model handle and material override
 use std::collections::HashMap;
 
-use crate::{instance::InstanceRaw, model::Model};
+use crate::{
+    asset_manager::handle::Handle,
+    instance::InstanceRaw,
+    model::{Material, Model},
+};
+
+pub(crate) type BatchKey = (Handle<Model>, Option<Handle<Material>>);
+
+// Buffers of models that stopped being drawn are released after this many frames
+const EVICT_AFTER_FRAMES: u64 = 300;
+
+struct PooledBuffer {
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    last_used: u64,
+}
 
 #[derive(Default)]
 pub(crate) struct InstanceBufferPool {
-    buffers: HashMap<*const Model, (wgpu::Buffer, usize)>, // buffer + capacity
+    buffers: HashMap<BatchKey, PooledBuffer>,
+    frame: u64,
 }
 
 impl InstanceBufferPool {
@@ -13,21 +29,30 @@ impl InstanceBufferPool {
         &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        model: &Model,
+        batch: BatchKey,
         data: &[InstanceRaw],
     ) -> &wgpu::Buffer {
-        let key = model as *const Model;
         let needed = data.len().max(1);
-        let (buf, cap) = self
-            .buffers
-            .entry(key)
-            .or_insert_with(|| (Self::create(device, needed), needed));
-        if *cap < needed {
-            *buf = Self::create(device, needed);
-            *cap = needed;
+        let entry = self.buffers.entry(batch).or_insert_with(|| PooledBuffer {
+            buffer: Self::create(device, needed),
+            capacity: needed,
+            last_used: self.frame,
+        });
+        if entry.capacity < needed {
+            entry.buffer = Self::create(device, needed);
+            entry.capacity = needed;
         }
-        queue.write_buffer(buf, 0, bytemuck::cast_slice(data));
-        buf
+        entry.last_used = self.frame;
+        queue.write_buffer(&entry.buffer, 0, bytemuck::cast_slice(data));
+        &entry.buffer
+    }
+
+    /// Called once per frame, drops buffers that have not been used for a while.
+    pub(crate) fn end_frame(&mut self) {
+        let frame = self.frame;
+        self.buffers
+            .retain(|_, pooled| frame - pooled.last_used < EVICT_AFTER_FRAMES);
+        self.frame += 1;
     }
 
     fn create(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
@@ -1,20 +1,38 @@
 pub mod camera_bind;
+pub mod deform_bind;
 pub mod frame;
+pub mod graph;
+pub mod grid;
 pub mod instance_buffers_pool;
+mod scene_pass;
 
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use egui::{Color32, Rgba};
-use wgpu::{Buffer, Color, RenderPipeline, util::DeviceExt};
+use wgpu::Color;
 use winit::dpi::PhysicalSize;
 
 use crate::{
+    animation::JointMatrix,
+    asset_manager::handle::Handle,
     camera::Camera,
-    gpu::{context::GpuContext, pipeline::PipelineBuilder},
+    gpu::{
+        context::GpuContext,
+        pipeline_cache::{
+            Bindings, BlendMode, DepthState, PipelineCache, PipelineKey, VertexLayout,
+        },
+        shader::{ShaderKey, ShaderLibrary},
+    },
     instance::InstanceRaw,
-    model::{DrawModel, INDICES, Model, ModelVertex, Vertex},
+    model::{Material, Mesh, Model, MorphTargets, RenderState},
     renderer::{
-        camera_bind::CameraBinding, frame::Frame, instance_buffers_pool::InstanceBufferPool,
+        camera_bind::CameraBinding,
+        deform_bind::DeformBinding,
+        frame::Frame,
+        graph::{RenderGraph, RenderNode},
+        grid::GridNode,
+        instance_buffers_pool::{BatchKey, InstanceBufferPool},
+        scene_pass::{SceneDraw, ScenePass},
     },
     texture::Texture,
 };
@@ -30,95 +48,181 @@ pub enum RenderError {
 }
 pub struct Renderer {
     gpu_context: Arc<GpuContext>,
-    depth_texture: Texture,
+    graph: RenderGraph,
+    // Passes added by the application, run after the built-in ones every frame
+    custom_nodes: Vec<Box<dyn RenderNode>>,
 
-    render_pipeline: RenderPipeline,
-    line_pipeline: RenderPipeline,
-    index_buffer: Buffer,
-    _texture_layout: wgpu::BindGroupLayout,
+    shaders: ShaderLibrary,
+    pipelines: PipelineCache,
     camera_binding: CameraBinding,
+    deform_binding: DeformBinding,
 
     pub clear_color: Color32,
     instance_pool: InstanceBufferPool,
 }
 pub struct DrawBatch {
+    pub handle: Handle<Model>,
     pub model: Arc<Model>,
+    /// From the entities' `MaterialOverride`, replaces every material of `model`
+    pub material: Option<(Handle<Material>, Arc<Material>)>,
     pub instances: Vec<InstanceRaw>,
 }
 
+impl DrawBatch {
+    pub fn material(&self, mesh: &Mesh) -> &Material {
+        match &self.material {
+            Some((_, material)) => material,
+            None => &self.model.materials[mesh.material],
+        }
+    }
+
+    pub(crate) fn key(&self) -> BatchKey {
+        (
+            self.handle,
+            self.material.as_ref().map(|(handle, _)| *handle),
+        )
+    }
+}
+
+/// Everything the scene pass draws in a frame
+#[derive(Default)]
+pub struct DrawList {
+    pub batches: Vec<DrawBatch>,
+    /// Joint matrices of all skinned instances, `InstanceRaw::joint_offset` indexes into it
+    pub joint_matrices: Vec<JointMatrix>,
+    /// Morph target weights of all morphed instances, indexed by `InstanceRaw::morph_offset`
+    pub morph_weights: Vec<f32>,
+}
+
 pub struct DrawParams<'a> {
     pub camera: &'a Camera,
-    pub batches: &'a [DrawBatch],
+    pub draw_list: &'a DrawList,
     pub clear_color: wgpu::Color,
     pub draw_lines: bool,
+    pub draw_grid: bool,
 }
 
 impl Renderer {
     pub fn new(gpu_context: Arc<GpuContext>) -> Self {
-        let shader =
-            gpu_context
-                .as_ref()
-                .device
-                .create_shader_module(wgpu::ShaderModuleDescriptor {
-                    label: Some("Shader"),
-                    source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/shader.wgsl").into()),
-                });
+        // Debug builds read shaders from the assets folder and pick up edits at runtime
+        let hot_reload = cfg!(debug_assertions) && !cfg!(target_arch = "wasm32");
+        let shaders = ShaderLibrary::new(hot_reload);
 
         let camera_binding = CameraBinding::new(&gpu_context.device);
+        let deform_binding = DeformBinding::new(&gpu_context.device);
 
-        let depth_texture = Texture::create_depth_texture(&gpu_context, "depth_texture");
-
-        let _texture_layout: wgpu::BindGroupLayout =
-            Texture::create_bind_group_layout(&gpu_context.device);
-
-        let render_pipeline =
-            PipelineBuilder::new(&gpu_context.device, gpu_context.config().format)
-                .with_label("Render Pipeline")
-                .with_shader(&shader)
-                .with_entry_points("vs_main", "fs_main")
-                .add_layout(&_texture_layout)
-                .add_layout(camera_binding.layout()) // <- same call as before, just via the binding
-                .add_vertex_layout(Some(ModelVertex::desc()))
-                .add_vertex_layout(Some(InstanceRaw::desc()))
-                .with_depth(Texture::DEPTH_FORMAT)
-                .build();
-
-        let line_pipeline =
-            PipelineBuilder::new(&gpu_context.as_ref().device, gpu_context.config().format)
-                .with_label("Wireframe Render Pipeline")
-                .with_shader(&shader)
-                .with_entry_points("vs_main", "fs_main")
-                .add_layout(&_texture_layout)
-                .add_layout(camera_binding.layout())
-                .add_vertex_layout(Some(ModelVertex::desc()))
-                .add_vertex_layout(Some(InstanceRaw::desc()))
-                .with_polygon_mode(wgpu::PolygonMode::Line)
-                .with_depth(Texture::DEPTH_FORMAT)
-                .build();
-
-        let index_buffer =
-            gpu_context
-                .as_ref()
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Index Buffer"),
-                    contents: bytemuck::cast_slice(INDICES),
-                    usage: wgpu::BufferUsages::INDEX,
-                });
+        let graph = RenderGraph::new(gpu_context.config().width, gpu_context.config().height);
+
+        let material_layout = Material::create_bind_group_layout(&gpu_context.device);
+        let morph_layout = MorphTargets::create_bind_group_layout(&gpu_context.device);
+
+        // Pipelines are built on first use, only the bind group layouts are known up front
+        let mut pipelines = PipelineCache::new(&gpu_context);
+        pipelines.register_bindings(
+            Bindings::Scene,
+            vec![material_layout.clone(), camera_binding.layout().clone()],
+        );
+        pipelines.register_bindings(
+            Bindings::SceneSkinned,
+            vec![
+                material_layout.clone(),
+                camera_binding.layout().clone(),
+                deform_binding.layout().clone(),
+            ],
+        );
+        pipelines.register_bindings(
+            Bindings::SceneMorph,
+            vec![
+                material_layout,
+                camera_binding.layout().clone(),
+                deform_binding.layout().clone(),
+                morph_layout,
+            ],
+        );
+        pipelines.register_bindings(Bindings::Camera, vec![camera_binding.layout().clone()]);
 
         Self {
             gpu_context,
-            depth_texture,
-            render_pipeline,
-            line_pipeline,
-            index_buffer,
+            graph,
+            custom_nodes: Vec::new(),
+            shaders,
+            pipelines,
             camera_binding,
-            _texture_layout,
+            deform_binding,
             clear_color: Color32::from_rgb(0, 50, 20),
             instance_pool: InstanceBufferPool::default(),
         }
     }
 
+    fn scene_pipeline_key(
+        &self,
+        draw_lines: bool,
+        skinned: bool,
+        morph: bool,
+        state: &RenderState,
+    ) -> PipelineKey {
+        let mut shader = state.shader.clone();
+        if draw_lines {
+            shader = shader.define("WIREFRAME");
+        }
+        if skinned {
+            shader = shader.define("SKINNED");
+        }
+        if morph {
+            shader = shader.define("MORPH");
+        }
+        PipelineKey {
+            shader,
+            bindings: match (skinned, morph) {
+                (_, true) => Bindings::SceneMorph,
+                (true, false) => Bindings::SceneSkinned,
+                (false, false) => Bindings::Scene,
+            },
+            vertex_layout: if skinned {
+                VertexLayout::ModelInstancedSkinned
+            } else {
+                VertexLayout::ModelInstanced
+            },
+            blend: state.blend,
+            cull_mode: state.cull_mode,
+            polygon_mode: if draw_lines {
+                wgpu::PolygonMode::Line
+            } else {
+                wgpu::PolygonMode::Fill
+            },
+            depth: Some(DepthState {
+                format: Texture::DEPTH_FORMAT,
+                write: state.blend == BlendMode::Opaque,
+                compare: wgpu::CompareFunction::Less,
+            }),
+            sample_count: 1,
+            format: self.gpu_context.config().format,
+        }
+    }
+
+    /// Picks up shader edits from disk and rebuilds the pipelines that use them.
+    /// A pipeline that fails to build keeps the previous one, the error goes to [`Self::shader_errors`].
+    pub fn reload_shaders(&mut self) {
+        let device = &self.gpu_context.device;
+        for key in self.shaders.poll(device) {
+            let errors = self
+                .pipelines
+                .rebuild_shader(device, &mut self.shaders, &key);
+            if errors.is_empty() {
+                continue;
+            }
+            for (pipeline, e) in &errors {
+                tracing::error!("Pipeline {pipeline:?} failed to rebuild: {e}");
+            }
+            let messages: Vec<String> = errors.into_iter().map(|(_, e)| e).collect();
+            self.shaders.set_error(&key, Some(messages.join("\n")));
+        }
+    }
+
+    pub fn shader_errors(&self) -> impl Iterator<Item = (&ShaderKey, &str)> {
+        self.shaders.errors()
+    }
+
     // fn begin_frame(&mut self) -> Result<Frame, RenderError> { todo!() }
     // fn draw(&mut self, frame: &mut Frame, scene: &Scene, camera_bind_group: &wgpu::BindGroup) { todo!() }
     // fn end_frame(&mut self, frame: Frame) { todo!() }
@@ -150,63 +254,136 @@ impl Renderer {
         })
     }
 
-    pub fn draw(&mut self, frame: &mut Frame, params: DrawParams) {
+    /// Records the frame through the render graph.
+    /// `extra_nodes` are per-frame passes (e.g. the debug UI) scheduled after everything else.
+    pub fn draw(
+        &mut self,
+        frame: &mut Frame,
+        params: DrawParams,
+        extra_nodes: &mut [&mut dyn RenderNode],
+    ) {
         self.camera_binding
             .sync(&self.gpu_context.queue, params.camera);
+        self.deform_binding.sync(
+            &self.gpu_context.device,
+            &self.gpu_context.queue,
+            &params.draw_list.joint_matrices,
+            &params.draw_list.morph_weights,
+        );
         let clear_color = Rgba::from(self.clear_color).to_rgba_unmultiplied();
-        let render_pass_desc = wgpu::RenderPassDescriptor {
-            label: Some("Scene Pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &frame.view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(Color {
-                        r: clear_color[0] as f64,
-                        g: clear_color[1] as f64,
-                        b: clear_color[2] as f64,
-                        a: clear_color[3] as f64,
-                    }),
-                    store: wgpu::StoreOp::Store,
-                },
-                depth_slice: None,
-            })],
-            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.depth_texture.view,
-                depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
-                    store: wgpu::StoreOp::Store,
-                }),
-                stencil_ops: None,
-            }),
-            multiview_mask: None,
-            occlusion_query_set: None,
-            timestamp_writes: None,
-        };
 
-        let mut pass = frame.encoder.begin_render_pass(&render_pass_desc);
-        pass.set_pipeline(if params.draw_lines {
-            &self.line_pipeline
-        } else {
-            &self.render_pipeline
+        // One draw per mesh, its material picks the pipeline. Blended ones go last, the
+        // farthest first, ordered by their farthest instance.
+        let device = &self.gpu_context.device;
+        let eye = params.camera.eye;
+        let mut pipelines = Vec::new();
+        let mut pipeline_indices: HashMap<PipelineKey, usize> = HashMap::new();
+        let mut draws = Vec::new();
+        for (batch_index, batch) in params.draw_list.batches.iter().enumerate() {
+            let (skinned, morph) = (batch.model.is_skinned(), batch.model.has_morph_targets());
+            for (mesh_index, mesh) in batch.model.meshes.iter().enumerate() {
+                let state = &batch.material(mesh).render_state;
+                let key = self.scene_pipeline_key(params.draw_lines, skinned, morph, state);
+                let pipeline = match pipeline_indices.get(&key) {
+                    Some(&index) => index,
+                    None => {
+                        // Broken shader, the error is shown in the UI
+                        let Some(built) = self.pipelines.get(device, &mut self.shaders, &key)
+                        else {
+                            continue;
+                        };
+                        pipelines.push(built.clone());
+                        pipeline_indices.insert(key, pipelines.len() - 1);
+                        pipelines.len() - 1
+                    }
+                };
+                let blended = state.blend != BlendMode::Opaque;
+                let distance2 = if blended {
+                    batch
+                        .instances
+                        .iter()
+                        .map(|instance| instance.distance2(eye, mesh.bounds.center()))
+                        .fold(0.0, f32::max)
+                } else {
+                    0.0
+                };
+                draws.push((
+                    distance2,
+                    SceneDraw {
+                        batch: batch_index,
+                        mesh: mesh_index,
+                        pipeline,
+                        blended,
+                    },
+                ));
+            }
+        }
+        draws.sort_by(|(a_distance2, a), (b_distance2, b)| {
+            a.blended
+                .cmp(&b.blended)
+                .then(b_distance2.total_cmp(a_distance2))
         });
-        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-        pass.set_bind_group(1, self.camera_binding.bind_group(), &[]);
-
-        params.batches.iter().for_each(|batch| {
-            let buf = self.instance_pool.upload(
-                &self.gpu_context.device,
-                &self.gpu_context.queue,
-                &batch.model,
-                &batch.instances,
-            );
-            pass.set_vertex_buffer(1, buf.slice(..));
-            pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            pass.draw_model_instanced(
-                &batch.model,
-                0..batch.instances.len() as u32,
-                self.camera_binding.bind_group(),
-            );
+        let draws: Vec<SceneDraw> = draws.into_iter().map(|(_, draw)| draw).collect();
+        let grid_key = GridNode::pipeline_key(self.gpu_context.config().format);
+        let grid_pipeline = self
+            .pipelines
+            .get(device, &mut self.shaders, &grid_key)
+            .cloned();
+
+        let mut scene_pass = ScenePass {
+            pipelines: &pipelines,
+            draws: &draws,
+            camera_bind_group: self.camera_binding.bind_group(),
+            deform_bind_group: self.deform_binding.bind_group(),
+            instance_pool: &mut self.instance_pool,
+            batches: &params.draw_list.batches,
+            clear_color: Color {
+                r: clear_color[0] as f64,
+                g: clear_color[1] as f64,
+                b: clear_color[2] as f64,
+                a: clear_color[3] as f64,
+            },
+        };
+        let mut grid_node = grid_pipeline.as_ref().map(|pipeline| GridNode {
+            pipeline,
+            camera_bind_group: self.camera_binding.bind_group(),
         });
+
+        let mut nodes: Vec<&mut dyn RenderNode> = vec![&mut scene_pass];
+        if params.draw_grid
+            && let Some(grid_node) = &mut grid_node
+        {
+            nodes.push(grid_node);
+        }
+        nodes.extend(
+            self.custom_nodes
+                .iter_mut()
+                .map(|n| n.as_mut() as &mut dyn RenderNode),
+        );
+        nodes.extend(
+            extra_nodes
+                .iter_mut()
+                .map(|n| &mut **n as &mut dyn RenderNode),
+        );
+
+        if let Err(e) = self.graph.execute(
+            &self.gpu_context.device,
+            &self.gpu_context.queue,
+            frame,
+            &mut nodes,
+        ) {
+            tracing::error!("Render graph: {e:#}");
+        }
+    }
+
+    /// Inserts an application pass into every frame without touching the renderer.
+    /// It can read and write graph resources like [`graph::SURFACE`] and [`graph::DEPTH`].
+    pub fn add_node(&mut self, node: Box<dyn RenderNode>) {
+        self.custom_nodes.push(node);
+    }
+
+    pub fn pipeline_count(&self) -> usize {
+        self.pipelines.len()
     }
 
     pub fn end_frame(&mut self, frame: Frame) {
@@ -218,7 +395,7 @@ impl Renderer {
 
     pub fn resize(&mut self, new_size: &PhysicalSize<u32>) {
         self.gpu_context.resize(new_size);
-        self.depth_texture = Texture::create_depth_texture(&self.gpu_context, "depth_texture");
+        self.graph.resize(new_size.width, new_size.height);
     }
 
     pub fn is_zero_sized(&self) -> bool {
@@ -0,0 +1,105 @@
+use wgpu::{Color, RenderPipeline};
+
+use crate::{
+    model::DrawModel,
+    renderer::{
+        DrawBatch,
+        graph::{DEPTH, PassBuilder, PassContext, RenderNode, SURFACE, TextureDesc},
+        instance_buffers_pool::InstanceBufferPool,
+    },
+    texture::Texture,
+};
+
+// One mesh of a batch with the pipeline its material needs
+pub(crate) struct SceneDraw {
+    pub(crate) batch: usize,
+    pub(crate) mesh: usize,
+    pub(crate) pipeline: usize,
+    pub(crate) blended: bool,
+}
+
+// Main geometry pass. Borrows everything from the Renderer for a single frame.
+pub(crate) struct ScenePass<'a> {
+    pub(crate) pipelines: &'a [RenderPipeline],
+    // Opaque draws first, then blended ones
+    pub(crate) draws: &'a [SceneDraw],
+    pub(crate) camera_bind_group: &'a wgpu::BindGroup,
+    pub(crate) deform_bind_group: &'a wgpu::BindGroup,
+    pub(crate) instance_pool: &'a mut InstanceBufferPool,
+    pub(crate) batches: &'a [DrawBatch],
+    pub(crate) clear_color: Color,
+}
+
+impl RenderNode for ScenePass<'_> {
+    fn name(&self) -> &str {
+        "Scene Pass"
+    }
+
+    fn setup(&self, builder: &mut PassBuilder) {
+        builder
+            .create_texture(
+                DEPTH,
+                TextureDesc::surface_sized(
+                    Texture::DEPTH_FORMAT,
+                    wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                ),
+            )
+            .write_texture(SURFACE);
+    }
+
+    fn execute(&mut self, ctx: &mut PassContext) {
+        let render_pass_desc = wgpu::RenderPassDescriptor {
+            label: Some("Scene Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: ctx.texture(SURFACE),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: ctx.load_op(SURFACE, self.clear_color),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: ctx.texture(DEPTH),
+                depth_ops: Some(wgpu::Operations {
+                    load: ctx.load_op(DEPTH, 1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            multiview_mask: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        };
+
+        // Instance data has to be uploaded before the pass borrows the buffers
+        let buffers: Vec<wgpu::Buffer> = self
+            .batches
+            .iter()
+            .map(|batch| {
+                self.instance_pool
+                    .upload(ctx.device, ctx.queue, batch.key(), &batch.instances)
+                    .clone()
+            })
+            .collect();
+
+        let mut pass = ctx.encoder.begin_render_pass(&render_pass_desc);
+        pass.set_bind_group(1, self.camera_bind_group, &[]);
+        pass.set_bind_group(2, self.deform_bind_group, &[]);
+
+        for draw in self.draws {
+            let batch = &self.batches[draw.batch];
+            let mesh = &batch.model.meshes[draw.mesh];
+            // Skinned and morphed models read their joint matrices and weights from group 2
+            pass.set_pipeline(&self.pipelines[draw.pipeline]);
+            pass.set_vertex_buffer(1, buffers[draw.batch].slice(..));
+            pass.draw_mesh_instanced(
+                mesh,
+                batch.material(mesh),
+                0..batch.instances.len() as u32,
+                self.camera_bind_group,
+            );
+        }
+        self.instance_pool.end_frame();
+    }
+}
@@ -1,27 +1,37 @@
-use std::{collections::HashMap, path::Path, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
 
-use bevy_ecs::{schedule::Schedule, world::World};
+use bevy_ecs::{entity::Entity, schedule::Schedule, world::World};
 
 use cgmath::Rotation3;
 
 use crate::{
-    asset_manager::AssetManager,
+    animation,
+    asset_manager::{AssetManager, gltf_import::GltfScene, handle::Handle},
     camera::Camera,
     camera_controller::CameraController,
-    components::{self, MeshHandle, Spin, Transform},
+    components::{
+        self, JointMatrices, MaterialOverride, MeshHandle, MorphWeights, Spin, Transform,
+    },
+    gpu::pipeline_cache::BlendMode,
     instance::InstanceRaw,
-    model::Model,
-    renderer::{DrawBatch, Renderer},
+    model::{Material, Model},
+    renderer::{DrawBatch, DrawList, Renderer, instance_buffers_pool::BatchKey},
 };
 pub struct Scene {
     pub camera: Camera,
     pub camera_controller: CameraController,
     world: World,
+    // Stale handles already reported, so the warning is not repeated every frame
+    stale_handles: HashSet<Handle<Model>>,
+    stale_materials: HashSet<Handle<Material>>,
     _update_schedule: Schedule,
 }
 
 impl Scene {
-    pub async fn new(renderer: &Renderer, asset_manager: &mut AssetManager) -> Self {
+    pub fn new(renderer: &Renderer, asset_manager: &mut AssetManager) -> Self {
         let mut world = World::new();
         let camera = Camera {
             eye: (0.0, 1.0, 2.0).into(),
@@ -57,10 +67,8 @@ impl Scene {
 
         // let cubes = InstanceSet::new(renderer.device(), instances);
 
-        let obj_model = asset_manager
-            .load_obj(Path::new("models/cube/cube.obj"))
-            .await
-            .unwrap();
+        // Drawn as a placeholder until the background load finishes
+        let obj_model = asset_manager.load_model_async(Path::new("models/cube/cube.obj"));
 
         for i in 0..10 {
             world.spawn((
@@ -74,8 +82,9 @@ impl Scene {
                         cgmath::Vector3::unit_z(),
                         cgmath::Deg(0.0),
                     ),
+                    ..Default::default()
                 },
-                MeshHandle(obj_model.clone()),
+                MeshHandle(obj_model),
                 components::Name("CUBE".into()),
                 components::Spin {
                     axis: cgmath::Vector3::unit_z(),
@@ -89,34 +98,126 @@ impl Scene {
             camera_controller,
 
             world,
+            stale_handles: HashSet::new(),
+            stale_materials: HashSet::new(),
             _update_schedule: Schedule::default(),
         }
     }
 
-    pub fn draw_batches(&mut self) -> Vec<DrawBatch> {
-        let mut grouped: HashMap<*const Model, (Arc<Model>, Vec<InstanceRaw>)> = HashMap::new();
+    pub fn draw_list(&mut self, assets: &AssetManager) -> DrawList {
+        // Entities overriding the material are batched apart from the rest of the model's
+        let mut grouped: HashMap<BatchKey, Vec<InstanceRaw>> = HashMap::new();
+        let mut joint_matrices = Vec::new();
+        let mut morph_weights = Vec::new();
 
-        let mut query = self.world.query::<(&Transform, &MeshHandle)>();
-        for (transform, mesh) in query.iter(&self.world) {
-            let key = Arc::as_ptr(&mesh.0);
+        let mut query = self.world.query::<(
+            &Transform,
+            &MeshHandle,
+            Option<&JointMatrices>,
+            Option<&MorphWeights>,
+            Option<&MaterialOverride>,
+        )>();
+        for (transform, mesh, joints, weights, material) in query.iter(&self.world) {
+            let mut instance = transform.to_raw();
+            if let Some(joints) = joints.filter(|joints| !joints.0.is_empty()) {
+                instance.joint_offset = joint_matrices.len() as u32;
+                joint_matrices.extend_from_slice(&joints.0);
+            }
+            if let Some(weights) = weights
+                && let Some(model) = assets.model(mesh.0)
+                && model.has_morph_targets()
+            {
+                // The shader reads one weight per target, missing ones count as zero
+                instance.morph_offset = morph_weights.len() as u32;
+                let targets = model.morph_target_count();
+                morph_weights.extend(
+                    weights
+                        .0
+                        .iter()
+                        .copied()
+                        .chain(std::iter::repeat(0.0))
+                        .take(targets),
+                );
+            }
             grouped
-                .entry(key)
-                .or_insert_with(|| (mesh.0.clone(), Vec::new()))
-                .1
-                .push(transform.to_raw());
+                .entry((mesh.0, material.map(|material| material.0)))
+                .or_default()
+                .push(instance);
         }
 
-        grouped
-            .into_values()
-            .map(|(model, instances)| DrawBatch {
-                model: model.clone(),
-                instances,
+        let batches = grouped
+            .into_iter()
+            .filter_map(
+                |((handle, material), instances)| match assets.model(handle) {
+                    Some(model) => Some(DrawBatch {
+                        handle,
+                        model: model.clone(),
+                        material: material.and_then(|material| match assets.material(material) {
+                            Some(loaded) => Some((material, loaded.clone())),
+                            None => {
+                                if self.stale_materials.insert(material) {
+                                    tracing::warn!(
+                                        "{} entities override with removed material \
+                                         {material:?}, drawing their model's materials",
+                                        instances.len()
+                                    );
+                                }
+                                None
+                            }
+                        }),
+                        instances,
+                    }),
+                    None => {
+                        if self.stale_handles.insert(handle) {
+                            tracing::warn!(
+                                "{} entities reference removed model {handle:?}, skipping",
+                                instances.len()
+                            );
+                        }
+                        None
+                    }
+                },
+            )
+            .map(|mut batch| {
+                // Instances of a batch share one draw call, blended ones blend back to front
+                let eye = self.camera.eye;
+                let blended = batch
+                    .model
+                    .meshes
+                    .iter()
+                    .any(|mesh| batch.material(mesh).render_state.blend != BlendMode::Opaque);
+                if blended {
+                    batch.instances.sort_by(|a, b| {
+                        let origin = [0.0; 3];
+                        b.distance2(eye, origin)
+                            .total_cmp(&a.distance2(eye, origin))
+                    });
+                }
+                batch
             })
-            .collect()
+            .collect();
+
+        DrawList {
+            batches,
+            joint_matrices,
+            morph_weights,
+        }
+    }
+
+    /// Instantiates a loaded glTF file, returns the root entity. `None` if the handle is stale.
+    pub fn spawn_gltf(
+        &mut self,
+        assets: &AssetManager,
+        scene: Handle<GltfScene>,
+        transform: Transform,
+    ) -> Option<Entity> {
+        let scene = assets.gltf_scene(scene)?;
+        Some(scene.spawn(&mut self.world, transform))
     }
 
-    pub fn update(&mut self, dt: f32) {
+    pub fn update(&mut self, dt: f32, assets: &AssetManager) {
         self.camera_controller.update_camera(&mut self.camera, dt);
+        animation::animate(&mut self.world, assets, dt);
 
         let mut query = self.world.query::<(&mut Transform, &Spin)>();
         for (mut transform, spin) in query.iter_mut(&mut self.world) {
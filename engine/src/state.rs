@@ -1,5 +1,4 @@
 use anyhow::Result;
-use egui_wgpu::ScreenDescriptor;
 use std::sync::Arc;
 use wgpu::Color;
 
@@ -20,9 +19,9 @@ use winit::{
 };
 
 use crate::{
-    asset_manager::AssetManager,
+    asset_manager::{AssetManager, loader::LoadStatus},
     gpu::context::GpuContext,
-    gui::EguiRenderer,
+    gui::{EguiNode, EguiRenderer},
     renderer::{DrawParams, Renderer},
     scene::Scene,
 };
@@ -37,9 +36,11 @@ pub struct State {
     pub free_mouse: bool,
 
     renderer: Renderer,
+    assets: AssetManager,
     scene: Scene,
 
     pub draw_lines: bool,
+    pub draw_grid: bool,
 }
 
 impl State {
@@ -52,7 +53,7 @@ impl State {
 
         let renderer: Renderer = Renderer::new(gpu_context.clone());
 
-        let scene = Scene::new(&renderer, &mut asset_manager).await;
+        let scene = Scene::new(&renderer, &mut asset_manager);
 
         let egui: EguiRenderer = EguiRenderer::new(
             &gpu_context.as_ref().device,
@@ -68,7 +69,9 @@ impl State {
             delay: 0.0,
             free_mouse: true,
             renderer,
+            assets: asset_manager,
             draw_lines: false,
+            draw_grid: true,
             scene,
         }
     }
@@ -102,7 +105,9 @@ impl State {
 
     #[profiling::function]
     pub fn update(&mut self, delta_time: f32) {
-        self.scene.update(delta_time);
+        self.renderer.reload_shaders();
+        self.assets.poll_loads();
+        self.scene.update(delta_time, &self.assets);
     }
 
     #[profiling::function]
@@ -113,12 +118,94 @@ impl State {
 
         let mut frame = self.renderer.begin_frame().unwrap();
 
-        let batches = self.scene.draw_batches();
+        let draw_list = self.scene.draw_list(&self.assets);
+
+        // The UI is recorded inside the render graph while the renderer and scene are borrowed,
+        // so it edits copies that are written back once the frame is recorded
+        let mut delay = self.delay;
+        let mut draw_grid = self.draw_grid;
+        let mut fovy = self.scene.camera.fovy;
+        let mut color = self.renderer.clear_color;
+        let camera_state = self.scene.camera_controller.get_camera_state(); // owned value, borrow ends here
+        let pipeline_count = self.renderer.pipeline_count();
+        let texture_memory = self.assets.texture_memory();
+        let shader_errors: Vec<(String, String)> = self
+            .renderer
+            .shader_errors()
+            .map(|(key, error)| (key.to_string(), error.to_owned()))
+            .collect();
+        let mut loads: Vec<(String, LoadStatus)> = self
+            .assets
+            .load_statuses()
+            .map(|(path, status)| (path.display().to_string(), status.clone()))
+            .collect();
+        loads.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut egui_node = EguiNode {
+            egui: &mut self.egui,
+            window: &self.window,
+            size_in_pixels: [self.renderer.width(), self.renderer.height()],
+            pixels_per_point: self.window.scale_factor() as f32,
+            run_ui: |ctx: &egui::Context| {
+                egui::Window::new("Debug")
+                    .collapsible(true)
+                    .default_open(false)
+                    .movable(true)
+                    .show(ctx, |ui| {
+                        ui.label(format!("FPS: {:.1}", 1.0 / delta_time));
+                        ui.label(format!("Frame Time: {:.2}ms", delta_time * 1000.0));
+                        ui.label(format!("Pipelines: {pipeline_count}"));
+                        ui.label(format!(
+                            "Textures: {} ({} from files), {:.1} MiB",
+                            texture_memory.textures,
+                            texture_memory.cached,
+                            texture_memory.bytes as f64 / (1024.0 * 1024.0)
+                        ));
+                        ui.add(egui::Slider::new(&mut delay, 0.0..=240.0).text("Max fps"));
+                        ui.add(egui::Slider::new(&mut fovy, 5.0..=100.0).text("Camera FOV"));
+                        ui.color_edit_button_srgba(&mut color);
+                        ui.checkbox(&mut draw_grid, "Grid (F2)");
+                        ui.code(egui::RichText::new(format!("{:#?}", camera_state)).code());
+
+                        if !loads.is_empty() {
+                            ui.separator();
+                            ui.label(egui::RichText::new("Assets").strong());
+                            for (path, status) in &loads {
+                                match status {
+                                    LoadStatus::Loading => ui.label(format!("{path}: loading")),
+                                    LoadStatus::Loaded => ui.label(format!("{path}: loaded")),
+                                    LoadStatus::Failed(error) => ui
+                                        .colored_label(
+                                            egui::Color32::RED,
+                                            format!("{path}: failed"),
+                                        )
+                                        .on_hover_text(error),
+                                };
+                            }
+                        }
+                    });
+
+                if !shader_errors.is_empty() {
+                    egui::Window::new("Shader errors")
+                        .collapsible(true)
+                        .movable(true)
+                        .show(ctx, |ui| {
+                            egui::ScrollArea::vertical().show(ui, |ui| {
+                                for (key, error) in &shader_errors {
+                                    ui.label(egui::RichText::new(key).strong());
+                                    ui.code(error);
+                                }
+                            });
+                        });
+                }
+            },
+        };
 
         self.renderer.draw(
             &mut frame,
             DrawParams {
                 draw_lines: self.draw_lines,
+                draw_grid: self.draw_grid,
                 clear_color: Color {
                     r: 1.0,
                     g: 1.0,
@@ -126,47 +213,15 @@ impl State {
                     a: 1.0,
                 },
                 camera: &self.scene.camera,
-                batches: &batches,
+                draw_list: &draw_list,
             },
+            &mut [&mut egui_node],
         );
 
-        let screen_descriptor = ScreenDescriptor {
-            size_in_pixels: [self.renderer.width(), self.renderer.height()],
-            pixels_per_point: self.window.scale_factor() as f32,
-        };
-        let gpu_context = self.renderer.gpu_context();
-        let device = &gpu_context.device;
-        let queue = &gpu_context.queue;
-
-        let (encoder, view) = frame.encoder_and_view();
-
-        let delay = &mut self.delay;
-        let fovy = &mut self.scene.camera.fovy;
-        let color = &mut self.renderer.clear_color;
-        let camera_state = self.scene.camera_controller.get_camera_state(); // owned value, borrow ends here
-
-        self.egui.draw(
-            device,
-            queue,
-            encoder,
-            &self.window,
-            view,
-            screen_descriptor,
-            |ctx| {
-                egui::Window::new("Debug")
-                    .collapsible(true)
-                    .default_open(false)
-                    .movable(true)
-                    .show(ctx, |ui| {
-                        ui.label(format!("FPS: {:.1}", 1.0 / delta_time));
-                        ui.label(format!("Frame Time: {:.2}ms", delta_time * 1000.0));
-                        ui.add(egui::Slider::new(delay, 0.0..=240.0).text("Max fps"));
-                        ui.add(egui::Slider::new(fovy, 5.0..=100.0).text("Camera FOV"));
-                        ui.color_edit_button_srgba(color);
-                        ui.code(egui::RichText::new(format!("{:#?}", camera_state)).code());
-                    });
-            },
-        );
+        self.delay = delay;
+        self.draw_grid = draw_grid;
+        self.scene.camera.fovy = fovy;
+        self.renderer.clear_color = color;
 
         if self.delay > 0.0 {
             // make frame cap from target fps
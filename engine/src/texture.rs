@@ -1,7 +1,8 @@
 use anyhow::*;
 use image::GenericImageView;
+use wgpu::util::DeviceExt as _;
 
-use crate::gpu::context::GpuContext;
+use crate::{asset_manager::compressed::MipChain, gpu::context::GpuContext};
 
 pub struct Texture {
     #[allow(unused)]
@@ -69,6 +70,26 @@ impl Texture {
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+    ) -> Result<Self> {
+        Self::from_image_with(
+            device,
+            queue,
+            img,
+            label,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            &Self::default_sampler(),
+        )
+    }
+
+    /// `format` has to be an 8 bit RGBA format, e.g. `Rgba8Unorm` for normal maps and other
+    /// non-color data.
+    pub fn from_image_with(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
+        sampler: &wgpu::SamplerDescriptor,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -84,7 +105,7 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
@@ -106,7 +127,124 @@ impl Texture {
         );
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        let sampler = device.create_sampler(sampler);
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    /// Keeps the range of HDR/EXR images. `format` is `Rgba16Float` or `Rgba32Float`, the
+    /// latter is only filterable with `Features::FLOAT32_FILTERABLE`.
+    pub fn from_float_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
+        sampler: &wgpu::SamplerDescriptor,
+    ) -> Result<Self> {
+        let rgba = img.to_rgba32f();
+        let texels: Vec<u8> = match format {
+            wgpu::TextureFormat::Rgba32Float => bytemuck::cast_slice(rgba.as_raw()).to_vec(),
+            wgpu::TextureFormat::Rgba16Float => rgba
+                .as_raw()
+                .iter()
+                .flat_map(|&c| half::f16::from_f32(c).to_le_bytes())
+                .collect(),
+            other => bail!("{other:?} is not a floating-point RGBA format"),
+        };
+        let (width, height) = img.dimensions();
+
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label,
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            &texels,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(sampler);
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    /// Uploads every level of `chain` as stored. The device has to support its format,
+    /// see [`MipChain::is_supported`].
+    pub fn from_mip_chain(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        chain: &MipChain,
+        label: Option<&str>,
+        sampler: &wgpu::SamplerDescriptor,
+    ) -> Self {
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label,
+                size: wgpu::Extent3d {
+                    width: chain.width,
+                    height: chain.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: chain.levels.len() as u32,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: chain.format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            &chain.levels.concat(),
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(sampler);
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// GPU memory of all mip levels and layers
+    pub fn memory_size(&self) -> u64 {
+        let format = self.texture.format();
+        let (block_width, block_height) = format.block_dimensions();
+        // Depth/stencil formats have no copy size without an aspect, they are 4 bytes here
+        let block_size = format.block_copy_size(None).unwrap_or(4) as u64;
+        let size = self.texture.size();
+        (0..self.texture.mip_level_count())
+            .map(|mip| {
+                let width = (size.width >> mip).max(1).div_ceil(block_width) as u64;
+                let height = (size.height >> mip).max(1).div_ceil(block_height) as u64;
+                width * height * block_size * size.depth_or_array_layers as u64
+            })
+            .sum()
+    }
+
+    pub fn default_sampler() -> wgpu::SamplerDescriptor<'static> {
+        wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
@@ -114,13 +252,7 @@ impl Texture {
             min_filter: wgpu::FilterMode::Nearest,
             mipmap_filter: wgpu::MipmapFilterMode::Nearest,
             ..Default::default()
-        });
-
-        Ok(Self {
-            texture,
-            view,
-            sampler,
-        })
+        }
     }
 
     pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
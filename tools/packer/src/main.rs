@@ -0,0 +1,56 @@
+// Packs an assets folder into the single-file archive shipping builds read assets from.
+//
+//   cargo run -p packer -- [--no-compress] [assets folder] [output]
+//
+// Defaults to ./assets and ./assets.pak. Put the archive next to the executable or point
+// `ASSETS_ARCHIVE` at it.
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result, bail};
+use chu_engine::asset_manager::archive::{Archive, ArchiveWriter, Compression};
+
+fn main() -> Result<()> {
+    let mut compress = true;
+    let mut paths = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--no-compress" => compress = false,
+            flag if flag.starts_with('-') => bail!("Unknown option {flag}"),
+            path => paths.push(PathBuf::from(path)),
+        }
+    }
+    let mut paths = paths.into_iter();
+    let input = paths.next().unwrap_or_else(|| "assets".into());
+    let output = paths.next().unwrap_or_else(|| "assets.pak".into());
+
+    let mut writer = ArchiveWriter::new().compress(compress);
+    let count = writer.add_dir(&input)?;
+    let file = File::create(&output).with_context(|| format!("Creating {output:?}"))?;
+    let mut out = BufWriter::new(file);
+    let size = writer.write(&mut out)?;
+    out.flush()?;
+
+    // Read it back, a broken archive should fail here rather than in a shipped build
+    let archive = Archive::open(&output)?;
+    let original: u64 = archive.entries().map(|(_, entry)| entry.size).sum();
+    let compressed = archive
+        .entries()
+        .filter(|(_, entry)| entry.compression == Compression::Lz4)
+        .count();
+    for (path, _) in archive.entries() {
+        archive.read(path)?;
+    }
+
+    println!(
+        "Packed {count} files from {} into {} ({compressed} compressed): {:.1} MiB -> {:.1} MiB",
+        input.display(),
+        output.display(),
+        original as f64 / (1024.0 * 1024.0),
+        size as f64 / (1024.0 * 1024.0),
+    );
+    Ok(())
+}